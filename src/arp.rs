@@ -0,0 +1,163 @@
+use anyhow::Result;
+use pnet_datalink::Channel;
+use pnet_datalink::Config;
+use pnet_datalink::MacAddr;
+use pnet_datalink::NetworkInterface;
+use pnet_packet::arp::ArpHardwareTypes;
+use pnet_packet::arp::ArpOperations;
+use pnet_packet::arp::ArpPacket;
+use pnet_packet::arp::MutableArpPacket;
+use pnet_packet::ethernet::EtherTypes;
+use pnet_packet::ethernet::EthernetPacket;
+use pnet_packet::ethernet::MutableEthernetPacket;
+use pnet_packet::Packet;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use std::time::Instant;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ARP_PACKET_LEN: usize = 28;
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// The interface `dst_ipv4` is directly attached to and the source address on it,
+/// only `interface` is looked at when it is set.
+pub fn local_interface(
+    dst_ipv4: Ipv4Addr,
+    interface: Option<&str>,
+) -> Option<(NetworkInterface, Ipv4Addr)> {
+    for i in pnet_datalink::interfaces() {
+        if interface.is_some_and(|name| name != i.name)
+            || !i.is_up()
+            || i.is_loopback()
+            || i.mac.is_none()
+        {
+            continue;
+        }
+        for network in &i.ips {
+            if let IpAddr::V4(src_ipv4) = network.ip() {
+                if network.contains(dst_ipv4.into()) {
+                    return Some((i.clone(), src_ipv4));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// The broadcast ARP request for `dst_ipv4`, in an Ethernet frame.
+fn request(
+    src_mac: MacAddr,
+    src_ipv4: Ipv4Addr,
+    dst_ipv4: Ipv4Addr,
+) -> [u8; ETHERNET_HEADER_LEN + ARP_PACKET_LEN] {
+    let mut ethernet_buff = [0u8; ETHERNET_HEADER_LEN + ARP_PACKET_LEN];
+    let mut arp_buff = [0u8; ARP_PACKET_LEN];
+    // both buffers have the exact packet size, so the packets can always be built
+    let mut arp_packet = MutableArpPacket::new(&mut arp_buff).unwrap();
+    arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp_packet.set_protocol_type(EtherTypes::Ipv4);
+    arp_packet.set_hw_addr_len(6);
+    arp_packet.set_proto_addr_len(4);
+    arp_packet.set_operation(ArpOperations::Request);
+    arp_packet.set_sender_hw_addr(src_mac);
+    arp_packet.set_sender_proto_addr(src_ipv4);
+    arp_packet.set_target_hw_addr(MacAddr::zero());
+    arp_packet.set_target_proto_addr(dst_ipv4);
+    let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buff).unwrap();
+    ethernet_packet.set_destination(MacAddr::broadcast());
+    ethernet_packet.set_source(src_mac);
+    ethernet_packet.set_ethertype(EtherTypes::Arp);
+    ethernet_packet.set_payload(arp_packet.packet());
+    ethernet_buff
+}
+
+/// The MAC address of `dst_ipv4` when the frame is its ARP reply.
+fn reply_mac(frame: &[u8], dst_ipv4: Ipv4Addr) -> Option<MacAddr> {
+    let ethernet_packet = EthernetPacket::new(frame)?;
+    if ethernet_packet.get_ethertype() != EtherTypes::Arp {
+        return None;
+    }
+    let arp_packet = ArpPacket::new(ethernet_packet.payload())?;
+    if arp_packet.get_operation() == ArpOperations::Reply
+        && arp_packet.get_sender_proto_addr() == dst_ipv4
+    {
+        return Some(arp_packet.get_sender_hw_addr());
+    }
+    None
+}
+
+/// Send one ARP request for `dst_ipv4` and wait for the reply,
+/// returns `None` when nothing answered before `timeout`.
+pub fn arp_ping(
+    dst_ipv4: Ipv4Addr,
+    src_ipv4: Ipv4Addr,
+    interface: &NetworkInterface,
+    timeout: Duration,
+) -> Result<Option<MacAddr>> {
+    let src_mac = match interface.mac {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+    // our own address never answers ARP, but it is up
+    if dst_ipv4 == src_ipv4 {
+        return Ok(Some(src_mac));
+    }
+    let config = Config {
+        read_timeout: Some(READ_TIMEOUT),
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = match pnet_datalink::channel(interface, config)? {
+        Channel::Ethernet(tx, rx) => (tx, rx),
+        _ => return Ok(None),
+    };
+
+    let ethernet_buff = request(src_mac, src_ipv4, dst_ipv4);
+    if let Some(Err(e)) = tx.send_to(&ethernet_buff, None) {
+        return Err(e.into());
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        // a read timeout comes back as an error, just try again
+        let frame = match rx.next() {
+            Ok(f) => f,
+            Err(_) => continue,
+        };
+        if let Some(mac) = reply_mac(frame, dst_ipv4) {
+            return Ok(Some(mac));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet_packet::MutablePacket;
+
+    #[test]
+    fn test_request_reply() {
+        let src_mac = MacAddr::new(0x02, 0, 0, 0, 0, 1);
+        let dst_mac = MacAddr::new(0x02, 0, 0, 0, 0, 2);
+        let src_ipv4 = Ipv4Addr::new(192, 168, 1, 1);
+        let dst_ipv4 = Ipv4Addr::new(192, 168, 1, 2);
+        let frame = request(src_mac, src_ipv4, dst_ipv4);
+        let ethernet_packet = EthernetPacket::new(&frame).unwrap();
+        assert_eq!(ethernet_packet.get_destination(), MacAddr::broadcast());
+        let arp_packet = ArpPacket::new(ethernet_packet.payload()).unwrap();
+        assert_eq!(arp_packet.get_operation(), ArpOperations::Request);
+        assert_eq!(arp_packet.get_target_proto_addr(), dst_ipv4);
+        // our own request is not a reply
+        assert_eq!(reply_mac(&frame, dst_ipv4), None);
+
+        // the reply of the target, answering with its own addresses
+        let mut frame = request(dst_mac, dst_ipv4, src_ipv4);
+        let mut ethernet_packet = MutableEthernetPacket::new(&mut frame).unwrap();
+        let mut arp_packet = MutableArpPacket::new(ethernet_packet.payload_mut()).unwrap();
+        arp_packet.set_operation(ArpOperations::Reply);
+        assert_eq!(reply_mac(&frame, dst_ipv4), Some(dst_mac));
+        assert_eq!(reply_mac(&frame, Ipv4Addr::new(192, 168, 1, 3)), None);
+        assert_eq!(reply_mac(&frame[..ETHERNET_HEADER_LEN], dst_ipv4), None);
+    }
+}
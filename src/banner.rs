@@ -0,0 +1,80 @@
+use crate::output::Protocol;
+use crate::output::Record;
+use crate::service;
+use crate::timing::Timing;
+use crate::utils;
+use crate::BannerArgs;
+use anyhow::Result;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Printable ASCII as it is, everything else escaped like `\r\n` or `\x00`.
+pub fn escape(bytes: &[u8]) -> String {
+    let mut ret = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'\r' => ret.push_str("\\r"),
+            b'\n' => ret.push_str("\\n"),
+            b'\t' => ret.push_str("\\t"),
+            b'\\' => ret.push_str("\\\\"),
+            0x20..=0x7e => ret.push(b as char),
+            _ => {
+                let _ = write!(ret, "\\x{:02x}", b);
+            }
+        }
+    }
+    ret
+}
+
+/// Connect and wait for the greeting, many services send one before the client says anything.
+fn grab(
+    addr: SocketAddr,
+    connect_timeout: Duration,
+    wait: Duration,
+    length: usize,
+) -> Option<String> {
+    let greeting = service::exchange(addr, &[], connect_timeout, wait).ok()?;
+    if greeting.is_empty() {
+        return None;
+    }
+    Some(escape(&greeting[..greeting.len().min(length)]))
+}
+
+/// Read the banners of the open TCP ports in the results, when `--banner` is set.
+pub fn grab_banners(records: &mut [Record], args: &BannerArgs, timing: Timing) -> Result<()> {
+    if !args.banner {
+        return Ok(());
+    }
+    let connect_timeout = timing.timeout.unwrap_or(timing.max_rtt_timeout);
+    let wait = args.banner_wait;
+    let length = args.banner_length;
+    let jobs: Vec<(usize, SocketAddr)> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.protocol == Protocol::Tcp && r.state == "open")
+        .filter_map(|(i, r)| r.port.map(|port| (i, SocketAddr::new(r.target, port))))
+        .collect();
+    let rets = utils::run_jobs(jobs, timing.threads, move |(i, addr)| {
+        (i, grab(addr, connect_timeout, wait, length))
+    });
+    for (i, banner) in rets {
+        records[i].banner = banner;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape(b"SSH-2.0-OpenSSH_9.6\r\n"),
+            "SSH-2.0-OpenSSH_9.6\\r\\n"
+        );
+        assert_eq!(escape(b"\x00\xff\ta\\b"), "\\x00\\xff\\ta\\\\b");
+        assert_eq!(escape(b""), "");
+    }
+}
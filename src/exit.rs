@@ -0,0 +1,225 @@
+use crate::output::group_by_host;
+use crate::output::host_state;
+use crate::output::Record;
+use crate::InvalidMacDbError;
+use crate::InvalidOsDbError;
+use crate::InvalidPortError;
+use crate::InvalidProbeError;
+use crate::InvalidProtocolError;
+use crate::InvalidRateError;
+use crate::InvalidServiceProbesError;
+use crate::InvalidTargetError;
+use crate::InvalidTimeError;
+use crate::ReadInputListError;
+use crate::ReadMacDbError;
+use crate::ReadOsDbError;
+use crate::ReadServiceProbesError;
+use crate::ResolveHostError;
+use crate::SplitPortError;
+use crate::TooManyTopPortsError;
+use crate::UnknownServiceError;
+use crate::ValidationError;
+use std::io;
+
+/* Exit codes, a finished scan exits like grep: 0 when it found something, 1 when not */
+/// Open ports or up hosts were found
+pub const FOUND: i32 = 0;
+/// The scan finished without open ports or up hosts
+pub const NOT_FOUND: i32 = 1;
+/// Invalid arguments, clap exits with 2 as well
+pub const USAGE: i32 = 2;
+/// Raw sockets need root or CAP_NET_RAW
+pub const PERMISSION: i32 = 3;
+pub const INTERFACE_NOT_FOUND: i32 = 4;
+pub const UNREACHABLE: i32 = 5;
+/// Some probes failed, the results are incomplete
+pub const PARTIAL_FAILURE: i32 = 6;
+/// `--fail-on-open` or `--fail-on-down` matched
+pub const POLICY: i32 = 7;
+pub const ERROR: i32 = 8;
+
+/// The exit code of an error that stopped the run.
+pub fn error_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<ValidationError>() {
+            return match e {
+                ValidationError::NotDirectlyAttached { .. } => UNREACHABLE,
+                _ => USAGE,
+            };
+        }
+        if cause.is::<InvalidTargetError>()
+            || cause.is::<ReadInputListError>()
+            || cause.is::<SplitPortError>()
+            || cause.is::<InvalidPortError>()
+            || cause.is::<InvalidProtocolError>()
+            || cause.is::<InvalidTimeError>()
+            || cause.is::<InvalidRateError>()
+            || cause.is::<InvalidProbeError>()
+            || cause.is::<ReadServiceProbesError>()
+            || cause.is::<InvalidServiceProbesError>()
+            || cause.is::<ReadOsDbError>()
+            || cause.is::<InvalidOsDbError>()
+            || cause.is::<ReadMacDbError>()
+            || cause.is::<InvalidMacDbError>()
+            || cause.is::<UnknownServiceError>()
+            || cause.is::<TooManyTopPortsError>()
+        {
+            return USAGE;
+        }
+        if cause.is::<ResolveHostError>() {
+            return UNREACHABLE;
+        }
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            match e.kind() {
+                io::ErrorKind::PermissionDenied => return PERMISSION,
+                io::ErrorKind::NetworkUnreachable | io::ErrorKind::HostUnreachable => {
+                    return UNREACHABLE
+                }
+                _ => (),
+            }
+        }
+        // pistol does not export its interface errors
+        if cause.to_string().starts_with("can not found interface") {
+            return INTERFACE_NOT_FOUND;
+        }
+    }
+    ERROR
+}
+
+/// The exit code of a finished flood, it sends packets without looking for anything.
+pub fn flood_code(failed_jobs: usize) -> i32 {
+    if failed_jobs > 0 {
+        PARTIAL_FAILURE
+    } else {
+        FOUND
+    }
+}
+
+/// The exit code of a finished run.
+pub fn result_code(
+    records: &[Record],
+    failed_jobs: usize,
+    fail_on_open: bool,
+    fail_on_down: bool,
+) -> i32 {
+    let hosts = group_by_host(records);
+    let open = records.iter().any(|r| r.state == "open");
+    // an unknown host, like a -Pn host with only filtered ports, was not shown to be down
+    let down = hosts.values().any(|h| host_state(h) == "down");
+    // port scans look for open ports, pings for up hosts
+    let found = match records.iter().any(|r| r.port.is_some()) {
+        true => open,
+        false => hosts.values().any(|h| host_state(h) == "up"),
+    };
+    if (fail_on_open && open) || (fail_on_down && down) {
+        POLICY
+    } else if failed_jobs > 0 {
+        PARTIAL_FAILURE
+    } else if found {
+        FOUND
+    } else {
+        NOT_FOUND
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Protocol;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn record(addr: &str, port: Option<u16>, state: &'static str) -> Record {
+        let protocol = if port.is_some() {
+            Protocol::Tcp
+        } else {
+            Protocol::Icmp
+        };
+        Record::new(addr.parse().unwrap(), port, protocol, "syn", state, None)
+    }
+
+    #[test]
+    fn test_result_code() {
+        let open = [
+            record("10.0.0.1", None, "up"),
+            record("10.0.0.1", Some(22), "open"),
+        ];
+        let closed = [
+            record("10.0.0.1", None, "up"),
+            record("10.0.0.1", Some(22), "closed"),
+        ];
+        let down = [
+            record("10.0.0.1", None, "up"),
+            record("10.0.0.2", None, "down"),
+        ];
+        // -Pn and every port filtered, the host is unknown
+        let unknown = [record("10.0.0.1", Some(22), "filtered")];
+        assert_eq!(result_code(&open, 0, false, false), FOUND);
+        assert_eq!(result_code(&closed, 0, false, false), NOT_FOUND);
+        assert_eq!(result_code(&down, 0, false, false), FOUND);
+        assert_eq!(result_code(&down[1..], 0, false, false), NOT_FOUND);
+        assert_eq!(result_code(&unknown, 0, false, false), NOT_FOUND);
+        assert_eq!(result_code(&[], 0, false, false), NOT_FOUND);
+        assert_eq!(result_code(&open, 1, false, false), PARTIAL_FAILURE);
+        assert_eq!(result_code(&open, 1, true, false), POLICY);
+        assert_eq!(result_code(&closed, 0, true, false), NOT_FOUND);
+        assert_eq!(result_code(&down, 0, false, true), POLICY);
+        assert_eq!(result_code(&open, 0, false, true), FOUND);
+        assert_eq!(result_code(&unknown, 0, false, true), NOT_FOUND);
+    }
+
+    #[test]
+    fn test_flood_code() {
+        assert_eq!(flood_code(0), FOUND);
+        assert_eq!(flood_code(2), PARTIAL_FAILURE);
+    }
+
+    #[test]
+    fn test_error_code() {
+        let usage: [anyhow::Error; 4] = [
+            ValidationError::NoTargets.into(),
+            InvalidPortError::new("x".to_string()).into(),
+            InvalidTimeError::new("x".to_string()).into(),
+            TooManyTopPortsError::new(200, 100, "tcp").into(),
+        ];
+        for e in &usage {
+            assert_eq!(error_code(e), USAGE, "{}", e);
+        }
+        let not_attached = ValidationError::NotDirectlyAttached {
+            target: "10.0.0.1".to_string(),
+        };
+        assert_eq!(error_code(&not_attached.into()), UNREACHABLE);
+        assert_eq!(
+            error_code(&ResolveHostError::new("x".to_string()).into()),
+            UNREACHABLE
+        );
+        let unreachable = io::Error::from(io::ErrorKind::HostUnreachable);
+        assert_eq!(error_code(&unreachable.into()), UNREACHABLE);
+        let permission = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(error_code(&permission.into()), PERMISSION);
+        // the cause decides, not the context around it
+        let permission = anyhow::Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(
+            error_code(&permission.context("open raw socket")),
+            PERMISSION
+        );
+        assert_eq!(error_code(&anyhow::anyhow!("something else")), ERROR);
+    }
+
+    #[test]
+    fn test_interface_not_found() {
+        // pistol does not export its error, this pins the message it is recognized by
+        let e = pistol::tcp_syn_scan_single_port(
+            None,
+            None,
+            Ipv4Addr::LOCALHOST,
+            80,
+            Some("pistolcli-none0"),
+            false,
+            Some(Duration::from_millis(1)),
+            Some(1),
+        )
+        .unwrap_err();
+        assert_eq!(error_code(&e), INTERFACE_NOT_FOUND);
+    }
+}
@@ -0,0 +1,1059 @@
+use crate::utils;
+use anyhow::Result;
+use pnet_packet::icmp;
+use pnet_packet::icmp::echo_request::MutableEchoRequestPacket;
+use pnet_packet::icmp::IcmpCode;
+use pnet_packet::icmp::IcmpPacket;
+use pnet_packet::icmp::IcmpTypes;
+use pnet_packet::ip::IpNextHeaderProtocol;
+use pnet_packet::ip::IpNextHeaderProtocols;
+use pnet_packet::ipv4;
+use pnet_packet::ipv4::Ipv4Flags;
+use pnet_packet::ipv4::Ipv4Packet;
+use pnet_packet::ipv4::MutableIpv4Packet;
+use pnet_packet::tcp;
+use pnet_packet::tcp::MutableTcpPacket;
+use pnet_packet::tcp::TcpFlags;
+use pnet_packet::tcp::TcpPacket;
+use pnet_packet::udp;
+use pnet_packet::udp::MutableUdpPacket;
+use pnet_packet::udp::UdpPacket;
+use pnet_packet::MutablePacket;
+use pnet_packet::Packet;
+use pnet_transport::ipv4_packet_iter;
+use pnet_transport::transport_channel;
+use pnet_transport::TransportChannelType;
+use pnet_transport::TransportReceiver;
+use pnet_transport::TransportSender;
+use rand::Rng;
+use std::fmt;
+use std::fmt::Write as _;
+use std::net::Ipv4Addr;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+const IPV4_HEADER_LEN: usize = 20;
+const TCP_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const ICMP_ECHO_HEADER_LEN: usize = 8;
+/// The unused field of a port unreachable, before the quoted packet
+const ICMP_UNUSED_LEN: usize = 4;
+const BUFF_SIZE: usize = 65535;
+const TTL: u8 = 64;
+/// nmap sends the SEQ probes 100 ms apart, the sequence and timestamp rates depend on it
+const SEQ_INTERVAL: Duration = Duration::from_millis(100);
+const IE_SEQUENCE: u16 = 295;
+const IP_TOS_RELIABILITY: u8 = 0x04;
+const U1_IP_ID: u16 = 0x1042;
+const U1_DATA: u8 = b'C';
+const U1_DATA_LEN: usize = 300;
+
+/// A TCP probe of nmap's OS detection, it goes to the open port unless `closed`.
+struct TcpProbe {
+    flags: u8,
+    window: u16,
+    /// Options in wire format, their length is a multiple of 4
+    options: &'static [u8],
+    df: bool,
+    closed: bool,
+    reserved: u8,
+    urgent: u16,
+}
+
+const fn syn_probe(window: u16, options: &'static [u8]) -> TcpProbe {
+    TcpProbe {
+        flags: TcpFlags::SYN,
+        window,
+        options,
+        df: false,
+        closed: false,
+        reserved: 0,
+        urgent: 0,
+    }
+}
+
+const fn t_probe(flags: u8, window: u16, df: bool, closed: bool) -> TcpProbe {
+    TcpProbe {
+        flags,
+        window,
+        options: T_OPTIONS,
+        df,
+        closed,
+        reserved: 0,
+        urgent: 0,
+    }
+}
+
+/// WScale 10, NOP, MSS 265, timestamp, SACK permitted
+const T_OPTIONS: &[u8] =
+    b"\x03\x03\x0a\x01\x02\x04\x01\x09\x08\x0a\xff\xff\xff\xff\x00\x00\x00\x00\x04\x02";
+/// T7 has a window scale of 15
+const T7_OPTIONS: &[u8] =
+    b"\x03\x03\x0f\x01\x02\x04\x01\x09\x08\x0a\xff\xff\xff\xff\x00\x00\x00\x00\x04\x02";
+
+/// The six SYN probes of the SEQ, OPS and WIN tests, the first one is T1 as well
+const SEQ_PROBES: [TcpProbe; 6] = [
+    syn_probe(
+        1,
+        b"\x03\x03\x0a\x01\x02\x04\x05\xb4\x08\x0a\xff\xff\xff\xff\x00\x00\x00\x00\x04\x02",
+    ),
+    syn_probe(
+        63,
+        b"\x02\x04\x05\x78\x04\x02\x08\x0a\xff\xff\xff\xff\x00\x00\x00\x00\x03\x03\x00\x00",
+    ),
+    syn_probe(
+        4,
+        b"\x08\x0a\xff\xff\xff\xff\x00\x00\x00\x00\x01\x01\x03\x03\x05\x01\x02\x04\x02\x80",
+    ),
+    syn_probe(
+        4,
+        b"\x04\x02\x08\x0a\xff\xff\xff\xff\x00\x00\x00\x00\x03\x03\x0a\x00",
+    ),
+    syn_probe(
+        16,
+        b"\x02\x04\x02\x18\x04\x02\x08\x0a\xff\xff\xff\xff\x00\x00\x00\x00\x03\x03\x0a\x00",
+    ),
+    syn_probe(
+        512,
+        b"\x02\x04\x01\x09\x04\x02\x08\x0a\xff\xff\xff\xff\x00\x00\x00\x00",
+    ),
+];
+
+/// A SYN with ECE and CWR, the reserved bit before CWR and an urgent pointer without URG
+const ECN_PROBE: TcpProbe = TcpProbe {
+    flags: TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR,
+    window: 3,
+    options: b"\x03\x03\x0a\x01\x02\x04\x05\xb4\x04\x02\x01\x01",
+    df: false,
+    closed: false,
+    reserved: 1,
+    urgent: 0xf7f5,
+};
+
+/// T2 to T7, odd flag combinations to the open and the closed port
+const T_PROBES: [TcpProbe; 6] = [
+    t_probe(0, 128, true, false),
+    t_probe(
+        TcpFlags::SYN | TcpFlags::FIN | TcpFlags::URG | TcpFlags::PSH,
+        256,
+        false,
+        false,
+    ),
+    t_probe(TcpFlags::ACK, 1024, true, false),
+    t_probe(TcpFlags::SYN, 31337, false, true),
+    t_probe(TcpFlags::ACK, 32768, true, true),
+    TcpProbe {
+        options: T7_OPTIONS,
+        ..t_probe(
+            TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG,
+            65535,
+            false,
+            true,
+        )
+    },
+];
+
+/// A line of a fingerprint like `T1(R=Y%DF=Y%T=40)`, in nmap-os-db the values are expressions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Test {
+    pub name: String,
+    pub values: Vec<(String, String)>,
+}
+
+impl Test {
+    fn new(name: &str) -> Test {
+        Test {
+            name: name.to_string(),
+            values: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, attr: &str, value: impl Into<String>) {
+        self.values.push((attr.to_string(), value.into()));
+    }
+
+    pub fn get(&self, attr: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(a, _)| a == attr)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parse `T1(R=Y%DF=Y%T=40)`.
+    pub fn parse(line: &str) -> Option<Test> {
+        let (name, rest) = line.split_once('(')?;
+        let values = rest.strip_suffix(')')?;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let mut test = Test::new(name);
+        for value in values.split('%').filter(|v| !v.is_empty()) {
+            let (attr, value) = value.split_once('=')?;
+            test.push(attr, value);
+        }
+        Some(test)
+    }
+}
+
+impl fmt::Display for Test {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<String> = self
+            .values
+            .iter()
+            .map(|(attr, value)| format!("{}={}", attr, value))
+            .collect();
+        write!(f, "{}({})", self.name, values.join("%"))
+    }
+}
+
+/// Numbers in fingerprints are upper case hex without leading zeros.
+fn hex(n: impl fmt::UpperHex) -> String {
+    format!("{:X}", n)
+}
+
+fn yes_no(b: bool) -> &'static str {
+    if b {
+        "Y"
+    } else {
+        "N"
+    }
+}
+
+/// A probe as it was sent, to recognize and judge its response.
+#[derive(Debug, Clone, Copy)]
+struct Sent {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    time: Instant,
+}
+
+#[derive(Debug, Clone)]
+struct TcpResponse {
+    ttl: u8,
+    df: bool,
+    ip_id: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    reserved: u8,
+    window: u16,
+    urgent: u16,
+    /// The options in nmap notation, like `M5B4ST11NW7`
+    options: String,
+    tsval: Option<u32>,
+    data: Vec<u8>,
+}
+
+impl TcpResponse {
+    fn new(ip: &Ipv4Packet, tcp: &TcpPacket) -> TcpResponse {
+        let (options, tsval) = options_str(tcp.get_options_raw());
+        TcpResponse {
+            ttl: ip.get_ttl(),
+            df: ip.get_flags() & Ipv4Flags::DontFragment != 0,
+            ip_id: ip.get_identification(),
+            seq: tcp.get_sequence(),
+            ack: tcp.get_acknowledgement(),
+            flags: tcp.get_flags(),
+            reserved: tcp.get_reserved(),
+            window: tcp.get_window(),
+            urgent: tcp.get_urgent_ptr(),
+            options,
+            tsval,
+            data: tcp.payload().to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct EchoResponse {
+    ttl: u8,
+    df: bool,
+    ip_id: u16,
+    code: u8,
+}
+
+#[derive(Debug, Clone)]
+struct UnreachableResponse {
+    ttl: u8,
+    df: bool,
+    ip_len: u16,
+    unused: u32,
+    /// The IP packet of the probe as the target quotes it
+    quoted: Vec<u8>,
+}
+
+/// The options in nmap notation and the timestamp value, if there is one.
+fn options_str(options: &[u8]) -> (String, Option<u32>) {
+    let mut ret = String::new();
+    let mut tsval = None;
+    let mut i = 0;
+    while i < options.len() {
+        let kind = options[i];
+        if kind == 0 {
+            ret.push('L');
+            break;
+        }
+        if kind == 1 {
+            ret.push('N');
+            i += 1;
+            continue;
+        }
+        let len = match options.get(i + 1) {
+            Some(&len) if len >= 2 && i + len as usize <= options.len() => len as usize,
+            _ => break,
+        };
+        let data = &options[i + 2..i + len];
+        match (kind, data.len()) {
+            (2, 2) => {
+                let _ = write!(ret, "M{:X}", u16::from_be_bytes([data[0], data[1]]));
+            }
+            (3, 1) => {
+                let _ = write!(ret, "W{:X}", data[0]);
+            }
+            (4, 0) => ret.push('S'),
+            (8, 8) => {
+                let value = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                let echo = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+                let _ = write!(ret, "T{}{}", (value != 0) as u8, (echo != 0) as u8);
+                tsval = Some(value);
+            }
+            _ => (),
+        }
+        i += len;
+    }
+    (ret, tsval)
+}
+
+/// The TCP flags in nmap's order, like `AS` for a SYN/ACK.
+fn flags_str(flags: u8) -> String {
+    [
+        (TcpFlags::ECE, 'E'),
+        (TcpFlags::URG, 'U'),
+        (TcpFlags::ACK, 'A'),
+        (TcpFlags::PSH, 'P'),
+        (TcpFlags::RST, 'R'),
+        (TcpFlags::SYN, 'S'),
+        (TcpFlags::FIN, 'F'),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags & flag != 0)
+    .map(|(_, c)| c)
+    .collect()
+}
+
+/// The IP ID sequence class of the responses, `None` when it fits no class.
+/// Random IDs only count for TCP, nmap does not look for them in ICMP.
+fn ipid_class(ids: &[u16], tcp: bool) -> Option<String> {
+    if ids.iter().all(|&id| id == 0) {
+        return Some("Z".to_string());
+    }
+    let diffs: Vec<u16> = ids.windows(2).map(|w| w[1].wrapping_sub(w[0])).collect();
+    if tcp && diffs.iter().any(|&d| d > 20000) {
+        return Some("RD".to_string());
+    }
+    if ids.iter().all(|&id| id == ids[0]) {
+        return Some(hex(ids[0]));
+    }
+    if diffs.iter().any(|&d| d > 1000 && d % 256 != 0) {
+        return Some("RI".to_string());
+    }
+    if diffs.iter().all(|&d| d % 256 == 0 && d <= 5120) {
+        return Some("BI".to_string());
+    }
+    if diffs.iter().all(|&d| d < 10) {
+        return Some("I".to_string());
+    }
+    None
+}
+
+fn incremental(class: &Option<String>) -> bool {
+    matches!(class.as_deref(), Some("RI" | "BI" | "I"))
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// The binary log times 8 that ISR and SP report, 0 below 1.
+fn log_scale(value: f64) -> u32 {
+    if value < 1.0 {
+        0
+    } else {
+        (8.0 * value.log2()).round() as u32
+    }
+}
+
+/// Seconds between two probes, never zero so it can divide.
+fn elapsed_secs(from: Instant, to: Instant) -> f64 {
+    to.duration_since(from).as_secs_f64().max(0.001)
+}
+
+/// CRC-32 of the data some stacks put in their resets.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & 0u32.wrapping_sub(crc & 1));
+        }
+    }
+    !crc
+}
+
+/// How the target's TTLs are reported, the initial TTL when the hop distance is known,
+/// a guess of it otherwise.
+#[derive(Debug, Clone, Copy)]
+struct Ttl {
+    distance: Option<u8>,
+}
+
+impl Ttl {
+    fn push(&self, test: &mut Test, ttl: u8) {
+        match self.distance {
+            Some(distance) => test.push("T", hex(ttl.saturating_add(distance))),
+            None => {
+                let guess: u8 = match ttl {
+                    0..=32 => 32,
+                    33..=64 => 64,
+                    65..=128 => 128,
+                    _ => 255,
+                };
+                test.push("TG", hex(guess));
+            }
+        }
+    }
+}
+
+fn seq_test(seq: &[(Sent, &TcpResponse)], closed_ids: &[u16], echo_ids: &[u16]) -> Test {
+    let mut test = Test::new("SEQ");
+    if seq.len() >= 2 {
+        // the smaller of the two ways around, ISNs may go backwards
+        let diffs: Vec<u32> = seq
+            .windows(2)
+            .map(|w| {
+                let d = w[1].1.seq.wrapping_sub(w[0].1.seq);
+                d.min(w[0].1.seq.wrapping_sub(w[1].1.seq))
+            })
+            .collect();
+        let gcd = diffs.iter().fold(0, |a, &d| gcd(a, d));
+        let rates: Vec<f64> = seq
+            .windows(2)
+            .zip(&diffs)
+            .map(|(w, &d)| d as f64 / elapsed_secs(w[0].0.time, w[1].0.time))
+            .collect();
+        if seq.len() >= 4 {
+            let divisor = if gcd > 9 { gcd as f64 } else { 1.0 };
+            let rates: Vec<f64> = rates.iter().map(|r| r / divisor).collect();
+            let avg = mean(&rates);
+            let variance =
+                rates.iter().map(|r| (r - avg).powi(2)).sum::<f64>() / rates.len() as f64;
+            let sd = variance.sqrt();
+            test.push("SP", hex(if sd <= 1.0 { 0 } else { log_scale(sd) }));
+        }
+        test.push("GCD", hex(gcd));
+        test.push("ISR", hex(log_scale(mean(&rates))));
+    }
+    let mut ti = None;
+    if seq.len() >= 3 {
+        let ids: Vec<u16> = seq.iter().map(|(_, r)| r.ip_id).collect();
+        ti = ipid_class(&ids, true);
+        if let Some(class) = &ti {
+            test.push("TI", class.as_str());
+        }
+    }
+    if closed_ids.len() >= 2 {
+        if let Some(class) = ipid_class(closed_ids, true) {
+            test.push("CI", class);
+        }
+    }
+    let mut ii = None;
+    if echo_ids.len() == 2 {
+        ii = ipid_class(echo_ids, false);
+        if let Some(class) = &ii {
+            test.push("II", class.as_str());
+        }
+    }
+    // whether TCP and ICMP share one IP ID counter
+    if incremental(&ti) && incremental(&ii) {
+        let first = seq[0].1.ip_id;
+        let last = seq[seq.len() - 1].1.ip_id;
+        let avg = last.wrapping_sub(first) as f64 / (seq.len() - 1) as f64;
+        let shared = (echo_ids[0] as f64) < last as f64 + 3.0 * avg;
+        test.push("SS", if shared { "S" } else { "O" });
+    }
+    if seq.len() >= 2 {
+        let tsvals: Option<Vec<u32>> = seq.iter().map(|(_, r)| r.tsval).collect();
+        let ts = match tsvals {
+            None => "U".to_string(),
+            Some(tsvals) if tsvals.contains(&0) => "0".to_string(),
+            Some(tsvals) => {
+                let rates: Vec<f64> = seq
+                    .windows(2)
+                    .zip(tsvals.windows(2))
+                    .map(|(w, ts)| {
+                        ts[1].wrapping_sub(ts[0]) as f64 / elapsed_secs(w[0].0.time, w[1].0.time)
+                    })
+                    .collect();
+                let hz = mean(&rates);
+                let ts = if hz <= 5.66 {
+                    1
+                } else if 70.0 < hz && hz <= 150.0 {
+                    7
+                } else if 150.0 < hz && hz <= 350.0 {
+                    8
+                } else {
+                    hz.log2().round() as u32
+                };
+                hex(ts)
+            }
+        };
+        test.push("TS", ts);
+    }
+    test
+}
+
+/// T1 to T7 and ECN, the ECN test leaves out the sequence numbers and flags for CC.
+fn tcp_test(name: &str, sent: &Sent, response: Option<&TcpResponse>, ttl: Ttl) -> Test {
+    let mut test = Test::new(name);
+    let Some(r) = response else {
+        test.push("R", "N");
+        return test;
+    };
+    let ecn = name == "ECN";
+    test.push("R", "Y");
+    test.push("DF", yes_no(r.df));
+    ttl.push(&mut test, r.ttl);
+    // T1 has its window and options in WIN and OPS
+    if name != "T1" {
+        test.push("W", hex(r.window));
+    }
+    if !ecn {
+        let s = match r.seq {
+            0 => "Z",
+            seq if seq == sent.ack => "A",
+            seq if seq == sent.ack.wrapping_add(1) => "A+",
+            _ => "O",
+        };
+        test.push("S", s);
+        let a = match r.ack {
+            0 => "Z",
+            ack if ack == sent.seq => "S",
+            ack if ack == sent.seq.wrapping_add(1) => "S+",
+            _ => "O",
+        };
+        test.push("A", a);
+        test.push("F", flags_str(r.flags));
+    }
+    if name != "T1" {
+        test.push("O", r.options.as_str());
+    }
+    if ecn {
+        let cc = match (r.flags & TcpFlags::ECE != 0, r.flags & TcpFlags::CWR != 0) {
+            (true, false) => "Y",
+            (false, false) => "N",
+            (true, true) => "S",
+            (false, true) => "O",
+        };
+        test.push("CC", cc);
+    } else {
+        let rd = match r.flags & TcpFlags::RST != 0 && !r.data.is_empty() {
+            true => crc32(&r.data),
+            false => 0,
+        };
+        test.push("RD", hex(rd));
+    }
+    let mut quirks = String::new();
+    if r.reserved != 0 {
+        quirks.push('R');
+    }
+    if r.urgent != 0 && r.flags & TcpFlags::URG == 0 {
+        quirks.push('U');
+    }
+    test.push("Q", quirks);
+    test
+}
+
+fn u1_test(response: Option<&UnreachableResponse>, udp_checksum: u16, ttl: Ttl) -> Test {
+    let mut test = Test::new("U1");
+    let Some(r) = response else {
+        test.push("R", "N");
+        return test;
+    };
+    test.push("R", "Y");
+    test.push("DF", yes_no(r.df));
+    ttl.push(&mut test, r.ttl);
+    test.push("IPL", hex(r.ip_len));
+    test.push("UN", hex(r.unused));
+    // the response was matched on the quoted header, so it is there
+    let Some(quoted) = Ipv4Packet::new(&r.quoted) else {
+        return test;
+    };
+    let sent_len = (IPV4_HEADER_LEN + UDP_HEADER_LEN + U1_DATA_LEN) as u16;
+    let ripl = quoted.get_total_length();
+    test.push(
+        "RIPL",
+        if ripl == sent_len {
+            "G".to_string()
+        } else {
+            hex(ripl)
+        },
+    );
+    let rid = quoted.get_identification();
+    test.push(
+        "RID",
+        if rid == U1_IP_ID {
+            "G".to_string()
+        } else {
+            hex(rid)
+        },
+    );
+    // routers change the TTL and fix the checksum, so a valid one is a good one
+    let ripck = match quoted.get_checksum() {
+        0 => "Z",
+        c if c == ipv4::checksum(&quoted) => "G",
+        _ => "I",
+    };
+    test.push("RIPCK", ripck);
+    if let Some(udp) = UdpPacket::new(quoted.payload()) {
+        let ruck = udp.get_checksum();
+        test.push(
+            "RUCK",
+            if ruck == udp_checksum {
+                "G".to_string()
+            } else {
+                hex(ruck)
+            },
+        );
+        let intact = udp.payload().iter().all(|&b| b == U1_DATA);
+        test.push("RUD", if intact { "G" } else { "I" });
+    }
+    test
+}
+
+fn ie_test(responses: &[Option<EchoResponse>; 2], ttl: Ttl) -> Test {
+    let mut test = Test::new("IE");
+    let [Some(first), Some(second)] = responses else {
+        test.push("R", "N");
+        return test;
+    };
+    test.push("R", "Y");
+    // the first probe has DF set and the second not
+    let dfi = match (first.df, second.df) {
+        (false, false) => "N",
+        (true, false) => "S",
+        (true, true) => "Y",
+        (false, true) => "O",
+    };
+    test.push("DFI", dfi);
+    ttl.push(&mut test, first.ttl);
+    let cd = match (first.code, second.code) {
+        (0, 0) => "Z".to_string(),
+        (9, 0) => "S".to_string(),
+        (a, b) if a == b => hex(a),
+        _ => "O".to_string(),
+    };
+    test.push("CD", cd);
+    test
+}
+
+/// A raw socket that sends and receives whole IPv4 packets, with our own header.
+pub fn channel(protocol: IpNextHeaderProtocol) -> Result<(TransportSender, TransportReceiver)> {
+    Ok(transport_channel(
+        BUFF_SIZE,
+        TransportChannelType::Layer3(protocol),
+    )?)
+}
+
+/// An IPv4 packet with our own header, nmap sets DF, TOS and IP ID per probe.
+pub fn ipv4_packet(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: IpNextHeaderProtocol,
+    id: u16,
+    df: bool,
+    tos: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut buff = vec![0u8; IPV4_HEADER_LEN + payload.len()];
+    // the buffer has the exact packet size, so the packet can always be built
+    let mut ip = MutableIpv4Packet::new(&mut buff).unwrap();
+    ip.set_version(4);
+    ip.set_header_length((IPV4_HEADER_LEN / 4) as u8);
+    ip.set_dscp(tos >> 2);
+    ip.set_ecn(tos & 0x03);
+    ip.set_total_length((IPV4_HEADER_LEN + payload.len()) as u16);
+    ip.set_identification(id);
+    ip.set_flags(if df { Ipv4Flags::DontFragment } else { 0 });
+    ip.set_ttl(TTL);
+    ip.set_next_level_protocol(protocol);
+    ip.set_source(src);
+    ip.set_destination(dst);
+    ip.set_payload(payload);
+    let checksum = ipv4::checksum(&ip.to_immutable());
+    ip.set_checksum(checksum);
+    buff
+}
+
+#[allow(clippy::too_many_arguments)]
+fn send_ipv4(
+    tx: &mut TransportSender,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: IpNextHeaderProtocol,
+    id: u16,
+    df: bool,
+    tos: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let buff = ipv4_packet(src, dst, protocol, id, df, tos, payload);
+    utils::pace();
+    tx.send_to(Ipv4Packet::new(&buff).unwrap(), dst.into())?;
+    Ok(())
+}
+
+fn tcp_segment(src: Ipv4Addr, dst: Ipv4Addr, probe: &TcpProbe, sent: &Sent) -> Vec<u8> {
+    let mut buff = vec![0u8; TCP_HEADER_LEN + probe.options.len()];
+    let mut tcp = MutableTcpPacket::new(&mut buff).unwrap();
+    tcp.set_source(sent.src_port);
+    tcp.set_destination(sent.dst_port);
+    tcp.set_sequence(sent.seq);
+    tcp.set_acknowledgement(sent.ack);
+    tcp.set_data_offset(((TCP_HEADER_LEN + probe.options.len()) / 4) as u8);
+    tcp.set_reserved(probe.reserved);
+    tcp.set_flags(probe.flags);
+    tcp.set_window(probe.window);
+    tcp.set_urgent_ptr(probe.urgent);
+    tcp.packet_mut()[TCP_HEADER_LEN..].copy_from_slice(probe.options);
+    let checksum = tcp::ipv4_checksum(&tcp.to_immutable(), &src, &dst);
+    tcp.set_checksum(checksum);
+    buff
+}
+
+fn echo_request(identifier: u16, sequence: u16, code: u8, len: usize) -> Vec<u8> {
+    let mut buff = vec![0u8; ICMP_ECHO_HEADER_LEN + len];
+    let mut echo = MutableEchoRequestPacket::new(&mut buff).unwrap();
+    echo.set_icmp_type(IcmpTypes::EchoRequest);
+    echo.set_icmp_code(IcmpCode(code));
+    echo.set_identifier(identifier);
+    echo.set_sequence_number(sequence);
+    let checksum = icmp::checksum(&IcmpPacket::new(echo.packet()).unwrap());
+    echo.set_checksum(checksum);
+    buff
+}
+
+/// The U1 datagram and its checksum, the target quotes it back.
+fn udp_datagram(src: Ipv4Addr, dst: Ipv4Addr, src_port: u16, dst_port: u16) -> (Vec<u8>, u16) {
+    let mut buff = vec![0u8; UDP_HEADER_LEN + U1_DATA_LEN];
+    let mut udp = MutableUdpPacket::new(&mut buff).unwrap();
+    udp.set_source(src_port);
+    udp.set_destination(dst_port);
+    udp.set_length((UDP_HEADER_LEN + U1_DATA_LEN) as u16);
+    udp.set_payload(&[U1_DATA; U1_DATA_LEN]);
+    let checksum = udp::ipv4_checksum(&udp.to_immutable(), &src, &dst);
+    udp.set_checksum(checksum);
+    (buff, checksum)
+}
+
+fn remaining(deadline: Instant) -> Option<Duration> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|d| !d.is_zero())
+}
+
+/// The first response to every sent TCP probe.
+fn receive_tcp(
+    rx: &mut TransportReceiver,
+    dst: Ipv4Addr,
+    sent: &[Sent],
+    deadline: Instant,
+) -> Result<Vec<Option<TcpResponse>>> {
+    let mut responses = vec![None; sent.len()];
+    let mut iter = ipv4_packet_iter(rx);
+    while let Some(remaining) = remaining(deadline) {
+        let Some((ip, _)) = iter.next_with_timeout(remaining)? else {
+            break;
+        };
+        if ip.get_source() != dst || ip.get_next_level_protocol() != IpNextHeaderProtocols::Tcp {
+            continue;
+        }
+        let Some(tcp) = TcpPacket::new(ip.payload()) else {
+            continue;
+        };
+        let probe = sent
+            .iter()
+            .position(|s| tcp.get_destination() == s.src_port && tcp.get_source() == s.dst_port);
+        if let Some(i) = probe {
+            responses[i].get_or_insert_with(|| TcpResponse::new(&ip, &tcp));
+        }
+        if responses.iter().all(Option::is_some) {
+            break;
+        }
+    }
+    Ok(responses)
+}
+
+/// The echo replies to the IE probes and the port unreachable of U1.
+fn receive_icmp(
+    rx: &mut TransportReceiver,
+    dst: Ipv4Addr,
+    identifier: u16,
+    udp_ports: (u16, u16),
+    deadline: Instant,
+) -> Result<([Option<EchoResponse>; 2], Option<UnreachableResponse>)> {
+    let mut echo = [None, None];
+    let mut unreachable = None;
+    let mut iter = ipv4_packet_iter(rx);
+    while let Some(remaining) = remaining(deadline) {
+        let Some((ip, _)) = iter.next_with_timeout(remaining)? else {
+            break;
+        };
+        if ip.get_source() != dst {
+            continue;
+        }
+        let Some(icmp_packet) = IcmpPacket::new(ip.payload()) else {
+            continue;
+        };
+        let payload = icmp_packet.payload();
+        let icmp_type = icmp_packet.get_icmp_type();
+        if icmp_type == IcmpTypes::EchoReply && payload.len() >= 4 {
+            let id = u16::from_be_bytes([payload[0], payload[1]]);
+            let sequence = u16::from_be_bytes([payload[2], payload[3]]);
+            for (i, response) in echo.iter_mut().enumerate() {
+                let i = i as u16;
+                if id == identifier.wrapping_add(i) && sequence == IE_SEQUENCE + i {
+                    response.get_or_insert(EchoResponse {
+                        ttl: ip.get_ttl(),
+                        df: ip.get_flags() & Ipv4Flags::DontFragment != 0,
+                        ip_id: ip.get_identification(),
+                        code: icmp_packet.get_icmp_code().0,
+                    });
+                }
+            }
+        } else if icmp_type == IcmpTypes::DestinationUnreachable
+            && payload.len() >= ICMP_UNUSED_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN
+        {
+            let quoted = &payload[ICMP_UNUSED_LEN..];
+            let is_u1 = Ipv4Packet::new(quoted).is_some_and(|q| {
+                let udp = UdpPacket::new(q.payload());
+                q.get_destination() == dst
+                    && q.get_next_level_protocol() == IpNextHeaderProtocols::Udp
+                    && udp.is_some_and(|u| (u.get_source(), u.get_destination()) == udp_ports)
+            });
+            if is_u1 {
+                unreachable.get_or_insert(UnreachableResponse {
+                    ttl: ip.get_ttl(),
+                    df: ip.get_flags() & Ipv4Flags::DontFragment != 0,
+                    ip_len: ip.get_total_length(),
+                    unused: u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]),
+                    quoted: quoted.to_vec(),
+                });
+            }
+        }
+        if echo.iter().all(Option::is_some) && unreachable.is_some() {
+            break;
+        }
+    }
+    Ok((echo, unreachable))
+}
+
+/// Send nmap's OS detection probes to `dst` and compute the tests of its fingerprint,
+/// `wait` is how long to wait for responses after the last probe.
+pub fn fingerprint(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    open_port: u16,
+    closed_port: u16,
+    closed_udp_port: u16,
+    wait: Duration,
+) -> Result<Vec<Test>> {
+    let (mut tcp_tx, mut tcp_rx) = channel(IpNextHeaderProtocols::Tcp)?;
+    let (mut icmp_tx, mut icmp_rx) = channel(IpNextHeaderProtocols::Icmp)?;
+    let (mut udp_tx, _) = channel(IpNextHeaderProtocols::Udp)?;
+
+    let mut rng = rand::thread_rng();
+    let base_port: u16 = rng.gen_range(32768..60000);
+    let base_seq: u32 = rng.gen();
+    let ack: u32 = rng.gen();
+    let probes: Vec<&TcpProbe> = SEQ_PROBES
+        .iter()
+        .chain([&ECN_PROBE])
+        .chain(T_PROBES.iter())
+        .collect();
+    let mut sent = Vec::new();
+    for (i, probe) in probes.iter().enumerate() {
+        if 0 < i && i < SEQ_PROBES.len() {
+            thread::sleep(SEQ_INTERVAL);
+        }
+        let mut s = Sent {
+            src_port: base_port + i as u16,
+            dst_port: if probe.closed { closed_port } else { open_port },
+            seq: base_seq.wrapping_add(i as u32),
+            ack,
+            time: Instant::now(),
+        };
+        let segment = tcp_segment(src, dst, probe, &s);
+        let protocol = IpNextHeaderProtocols::Tcp;
+        send_ipv4(
+            &mut tcp_tx,
+            src,
+            dst,
+            protocol,
+            rng.gen(),
+            probe.df,
+            0,
+            &segment,
+        )?;
+        s.time = Instant::now();
+        sent.push(s);
+    }
+
+    let identifier: u16 = rng.gen();
+    let protocol = IpNextHeaderProtocols::Icmp;
+    let echo = echo_request(identifier, IE_SEQUENCE, 9, 120);
+    send_ipv4(&mut icmp_tx, src, dst, protocol, rng.gen(), true, 0, &echo)?;
+    let echo = echo_request(identifier.wrapping_add(1), IE_SEQUENCE + 1, 0, 150);
+    let tos = IP_TOS_RELIABILITY;
+    send_ipv4(
+        &mut icmp_tx,
+        src,
+        dst,
+        protocol,
+        rng.gen(),
+        false,
+        tos,
+        &echo,
+    )?;
+
+    let udp_ports = (base_port + probes.len() as u16, closed_udp_port);
+    let (datagram, udp_checksum) = udp_datagram(src, dst, udp_ports.0, udp_ports.1);
+    let protocol = IpNextHeaderProtocols::Udp;
+    send_ipv4(
+        &mut udp_tx,
+        src,
+        dst,
+        protocol,
+        U1_IP_ID,
+        false,
+        0,
+        &datagram,
+    )?;
+
+    let deadline = Instant::now() + wait;
+    let tcp_responses = receive_tcp(&mut tcp_rx, dst, &sent, deadline)?;
+    // the ICMP responses wait in the socket, it only needs the time that is left
+    let deadline = deadline.max(Instant::now() + Duration::from_millis(10));
+    let (echo, unreachable) = receive_icmp(&mut icmp_rx, dst, identifier, udp_ports, deadline)?;
+
+    // the port unreachable quotes our probe with the TTL it arrived with
+    let distance = unreachable
+        .as_ref()
+        .and_then(|r| Ipv4Packet::new(&r.quoted).map(|q| TTL.saturating_sub(q.get_ttl())));
+    let ttl = Ttl { distance };
+
+    let seq_count = SEQ_PROBES.len();
+    let seq: Vec<(Sent, &TcpResponse)> = sent[..seq_count]
+        .iter()
+        .zip(&tcp_responses[..seq_count])
+        .filter_map(|(s, r)| r.as_ref().map(|r| (*s, r)))
+        .collect();
+    // T5 to T7 go to the closed port
+    let closed_ids: Vec<u16> = tcp_responses[seq_count + 4..]
+        .iter()
+        .flatten()
+        .map(|r| r.ip_id)
+        .collect();
+    let echo_ids: Vec<u16> = echo.iter().flatten().map(|r| r.ip_id).collect();
+
+    let mut tests = Vec::new();
+    let seq_test = seq_test(&seq, &closed_ids, &echo_ids);
+    if !seq_test.values.is_empty() {
+        tests.push(seq_test);
+    }
+    if !seq.is_empty() {
+        let mut ops = Test::new("OPS");
+        let mut win = Test::new("WIN");
+        for (i, r) in tcp_responses[..seq_count].iter().enumerate() {
+            if let Some(r) = r {
+                ops.push(&format!("O{}", i + 1), r.options.as_str());
+                win.push(&format!("W{}", i + 1), hex(r.window));
+            }
+        }
+        tests.push(ops);
+        tests.push(win);
+    }
+    let ecn = seq_count;
+    tests.push(tcp_test(
+        "ECN",
+        &sent[ecn],
+        tcp_responses[ecn].as_ref(),
+        ttl,
+    ));
+    tests.push(tcp_test("T1", &sent[0], tcp_responses[0].as_ref(), ttl));
+    for i in 0..T_PROBES.len() {
+        let name = format!("T{}", i + 2);
+        let j = seq_count + 1 + i;
+        tests.push(tcp_test(&name, &sent[j], tcp_responses[j].as_ref(), ttl));
+    }
+    tests.push(u1_test(unreachable.as_ref(), udp_checksum, ttl));
+    tests.push(ie_test(&echo, ttl));
+    Ok(tests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_str() {
+        let (options, tsval) = options_str(SEQ_PROBES[0].options);
+        assert_eq!(options, "WANM5B4T10S");
+        assert_eq!(tsval, Some(0xffffffff));
+        let (options, _) = options_str(SEQ_PROBES[1].options);
+        assert_eq!(options, "M578ST10W0L");
+        assert_eq!(
+            options_str(b"\x02\x04\xff\xd7\x01\x01\x04\x02").0,
+            "MFFD7NNS"
+        );
+        // a truncated option ends the list
+        assert_eq!(options_str(b"\x01\x02\x04\x05").0, "N");
+    }
+
+    #[test]
+    fn test_ipid_class() {
+        assert_eq!(ipid_class(&[0, 0, 0], true).as_deref(), Some("Z"));
+        assert_eq!(ipid_class(&[100, 101, 103], true).as_deref(), Some("I"));
+        assert_eq!(ipid_class(&[256, 512, 1024], true).as_deref(), Some("BI"));
+        assert_eq!(ipid_class(&[100, 1500, 3000], true).as_deref(), Some("RI"));
+        assert_eq!(ipid_class(&[100, 40000, 7], true).as_deref(), Some("RD"));
+        assert_eq!(ipid_class(&[100, 40000], false).as_deref(), Some("RI"));
+        assert_eq!(ipid_class(&[0x42, 0x42], false).as_deref(), Some("42"));
+        assert_eq!(ipid_class(&[100, 200, 300], true), None);
+    }
+
+    #[test]
+    fn test_parse_test() {
+        let test = Test::parse("T5(R=Y%DF=Y%T=40%W=0%S=Z%A=S+%F=AR%O=%RD=0%Q=)").unwrap();
+        assert_eq!(test.name, "T5");
+        assert_eq!(test.get("A"), Some("S+"));
+        assert_eq!(test.get("O"), Some(""));
+        assert_eq!(test.get("X"), None);
+        assert_eq!(
+            test.to_string(),
+            "T5(R=Y%DF=Y%T=40%W=0%S=Z%A=S+%F=AR%O=%RD=0%Q=)"
+        );
+        assert_eq!(Test::parse("T2(R=N)").unwrap().values.len(), 1);
+        assert!(Test::parse("T2 R=N").is_none());
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+}
@@ -1,134 +1,142 @@
-use crate::Args;
-use crate::AutoInferScanTypeError;
-use crate::GetTargetPortFailed;
-use crate::SplitPortError;
-use crate::NULL_VALUE;
+use crate::targets;
+use crate::timing::Timing;
+use crate::utils;
+use crate::validate;
+use crate::validate::Inputs;
+use crate::validate::Technique;
+use crate::FloodArgs;
+use crate::FloodMethods;
+use crate::IcmpFloodArgs;
 use anyhow::Result;
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
-use std::str::FromStr;
+use std::net::Ipv6Addr;
 
-#[derive(PartialEq)]
-enum InferScanType {
-    Host,
-    Unknown,
-}
-
-struct Parameters {
-    src_ipv4: Option<Ipv4Addr>,
-    src_port: Option<u16>,
-    dst_ipv4: Option<Ipv4Addr>,
-    dst_port: Option<u16>,
-    start_port: Option<u16>,
-    end_port: Option<u16>,
-    interface: Option<String>,
-    inter_scan_type: InferScanType,
-}
-
-impl Parameters {
-    fn new_empty() -> Parameters {
-        Parameters {
-            src_ipv4: None,
-            src_port: None,
-            dst_ipv4: None,
-            dst_port: None,
-            start_port: None,
-            end_port: None,
-            interface: None,
-            inter_scan_type: InferScanType::Unknown,
-        }
-    }
-}
+type HostFunc = fn(
+    Option<Ipv4Addr>,
+    Option<u16>,
+    Ipv4Addr,
+    Option<u16>,
+    Option<&str>,
+    usize,
+    bool,
+    usize,
+    usize,
+) -> Result<()>;
 
-pub fn start_flood(args: Args) -> Result<()> {
-    let mut parameters = Parameters::new_empty();
-
-    let src_ipv4 = if args.source_host != NULL_VALUE {
-        Some(Ipv4Addr::from_str(&args.source_host)?)
-    } else {
-        None
-    };
-    parameters.src_ipv4 = src_ipv4;
+type HostFunc6 = fn(
+    Option<Ipv6Addr>,
+    Option<u16>,
+    Ipv6Addr,
+    Option<u16>,
+    Option<&str>,
+    usize,
+    bool,
+    usize,
+    usize,
+) -> Result<()>;
 
-    let src_port = if args.source_port != 0 {
-        Some(args.source_port)
-    } else {
-        None
-    };
-    parameters.src_port = src_port;
+const PRINT_RESULT: bool = true;
+const MAX_SAME_PACKET: usize = 1024;
+const MAX_FLOOD_PACKET: usize = 1024;
 
-    if args.host != NULL_VALUE {
-        let dst_ipv4 = Ipv4Addr::from_str(&args.host)?;
-        parameters.dst_ipv4 = Some(dst_ipv4);
+pub fn start_flood(method: FloodMethods, timing: Timing) -> Result<()> {
+    match method {
+        FloodMethods::Syn(args) => flood(
+            args,
+            timing,
+            pistol::tcp_syn_flood_host,
+            pistol::tcp_syn_flood_host6,
+        ),
+        FloodMethods::Ack(args) => flood(
+            args,
+            timing,
+            pistol::tcp_ack_flood_host,
+            pistol::tcp_ack_flood_host6,
+        ),
+        FloodMethods::Udp(args) => flood(
+            args,
+            timing,
+            pistol::udp_flood_host,
+            pistol::udp_flood_host6,
+        ),
+        FloodMethods::Icmp(args) => icmp_flood(args, timing),
     }
+}
 
-    if args.port != NULL_VALUE {
-        if args.port.contains("-") {
-            let port_split_vec: Vec<&str> = args.port.split("-").collect();
-            if port_split_vec.len() == 2 {
-                let start_port: u16 = port_split_vec[0].parse()?;
-                let end_port: u16 = port_split_vec[1].parse()?;
-                parameters.start_port = Some(start_port);
-                parameters.end_port = Some(end_port);
-                parameters.inter_scan_type = InferScanType::Host;
-            } else {
-                return Err(SplitPortError::new(args.port).into());
-            }
-        } else {
-            let dst_port: u16 = args.port.parse()?;
-            parameters.dst_port = Some(dst_port);
-        }
-    } else {
-        return Err(GetTargetPortFailed::new().into());
+fn flood(args: FloodArgs, timing: Timing, func: HostFunc, func6: HostFunc6) -> Result<()> {
+    let targets = targets::parse_targets(&args.target)?;
+    let inputs = Inputs {
+        targets: &targets,
+        interface: args.source.interface.as_deref(),
+        source_host: args.source.source_host,
+        ..Default::default()
     };
+    validate::validate(Technique::Flood, &inputs)?;
+    let (src_ipv4, src_ipv6) =
+        targets::source_addrs(args.source.source_host, args.source.interface.as_deref());
+    let interface = args.source.interface.as_deref();
+    // a failing target leaves the others to flood
+    let rets = targets.into_iter().map(|target| match target.addr {
+        IpAddr::V4(dst_ipv4) => func(
+            src_ipv4,
+            args.source_port,
+            dst_ipv4,
+            args.port,
+            interface,
+            timing.threads,
+            PRINT_RESULT,
+            MAX_SAME_PACKET,
+            MAX_FLOOD_PACKET,
+        ),
+        IpAddr::V6(dst_ipv6) => func6(
+            src_ipv6.or_else(|| utils::route_source6(dst_ipv6)),
+            args.source_port,
+            dst_ipv6,
+            args.port,
+            interface,
+            timing.threads,
+            PRINT_RESULT,
+            MAX_SAME_PACKET,
+            MAX_FLOOD_PACKET,
+        ),
+    });
+    utils::collect_jobs(rets.collect())?;
+    Ok(())
+}
 
-    let interface = if args.interface != NULL_VALUE {
-        Some(args.interface)
-    } else {
-        None
+fn icmp_flood(args: IcmpFloodArgs, timing: Timing) -> Result<()> {
+    let targets = targets::parse_targets(&args.target)?;
+    let inputs = Inputs {
+        targets: &targets,
+        interface: args.source.interface.as_deref(),
+        source_host: args.source.source_host,
+        ..Default::default()
     };
-    parameters.interface = interface;
-
-    let print_result = true;
-    let threads_num = 0; // auto detect
-    let max_same_packet = 1024;
-    let max_flood_packet = 1024;
-
-    match parameters.inter_scan_type {
-        InferScanType::Unknown => return Err(AutoInferScanTypeError::new().into()),
-        InferScanType::Host => {
-            let func = if args.syn {
-                pistol::tcp_syn_flood_host
-            } else if args.ack {
-                pistol::tcp_ack_flood_host
-            } else if args.icmp {
-                let _ = pistol::icmp_flood_host(
-                    src_ipv4,
-                    parameters.dst_ipv4.unwrap(),
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    max_same_packet,
-                    max_flood_packet,
-                )?;
-                return Ok(());
-            } else if args.udp {
-                pistol::udp_flood_host
-            } else {
-                pistol::tcp_syn_flood_host
-            };
-
-            let _ = func(
-                src_ipv4,
-                src_port,
-                parameters.dst_ipv4.unwrap(),
-                parameters.dst_port,
-                parameters.interface.as_deref(),
-                threads_num,
-                print_result,
-                max_same_packet,
-                max_flood_packet,
-            )?;
-        }
-    }
+    validate::validate(Technique::Flood, &inputs)?;
+    let (src_ipv4, src_ipv6) =
+        targets::source_addrs(args.source.source_host, args.source.interface.as_deref());
+    let interface = args.source.interface.as_deref();
+    let rets = targets.into_iter().map(|target| match target.addr {
+        IpAddr::V4(dst_ipv4) => pistol::icmp_flood_host(
+            src_ipv4,
+            dst_ipv4,
+            interface,
+            timing.threads,
+            PRINT_RESULT,
+            MAX_SAME_PACKET,
+            MAX_FLOOD_PACKET,
+        ),
+        IpAddr::V6(dst_ipv6) => pistol::icmp_flood_host6(
+            src_ipv6.or_else(|| utils::route_source6(dst_ipv6)),
+            dst_ipv6,
+            interface,
+            timing.threads,
+            PRINT_RESULT,
+            MAX_SAME_PACKET,
+            MAX_FLOOD_PACKET,
+        ),
+    });
+    utils::collect_jobs(rets.collect())?;
     Ok(())
 }
@@ -0,0 +1,160 @@
+use crate::output::group_by_host;
+use crate::output::host_state;
+use crate::output::protocol_str;
+use crate::output::service_name;
+use crate::output::summary;
+use crate::output::time_str;
+use crate::output::Record;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use std::fmt::Write as _;
+use std::fs;
+
+fn status_str(state: &str) -> &'static str {
+    match state {
+        "up" => "Up",
+        "down" => "Down",
+        _ => "Unknown",
+    }
+}
+
+/// Render the results like nmap `-oG`, one line per host.
+pub fn to_grepable(records: &[Record], start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    let args: Vec<String> = std::env::args().collect();
+    let hosts = group_by_host(records);
+
+    let mut grepable = String::new();
+    let _ = writeln!(
+        grepable,
+        "# pistolcli {} scan initiated {} as: {}",
+        env!("CARGO_PKG_VERSION"),
+        time_str(&start),
+        args.join(" "),
+    );
+    let mut up = 0;
+    for (addr, host_records) in &hosts {
+        let state = host_state(host_records);
+        if state == "up" {
+            up += 1;
+        }
+        let ports: Vec<String> = host_records
+            .iter()
+            .filter_map(|r| {
+                r.port.map(|port| {
+                    let service = service_name(r);
+                    let version_info = r.service.as_ref().map(|s| s.version_info());
+                    // port/state/protocol/owner/service/rpc info/version info/
+                    format!(
+                        "{}/{}/{}//{}//{}/",
+                        port,
+                        r.state,
+                        protocol_str(r.protocol),
+                        service.unwrap_or_default(),
+                        version_info.unwrap_or_default().replace('/', "|")
+                    )
+                })
+            })
+            .collect();
+        let hostname = host_records
+            .iter()
+            .find_map(|r| r.hostname.as_deref())
+            .unwrap_or_default();
+        // a status line for every host and a ports line when it has ports, like nmap
+        let _ = writeln!(
+            grepable,
+            "Host: {} ({})\tStatus: {}",
+            addr,
+            hostname,
+            status_str(state)
+        );
+        if !ports.is_empty() {
+            let _ = write!(
+                grepable,
+                "Host: {} ({})\tPorts: {}",
+                addr,
+                hostname,
+                ports.join(", ")
+            );
+            // the best guess, like nmap
+            let os = host_records
+                .iter()
+                .find_map(|r| r.os.as_ref())
+                .and_then(|os| os.matches.first());
+            if let Some(m) = os {
+                let _ = write!(grepable, "\tOS: {}", m.name);
+            }
+            grepable.push('\n');
+        }
+    }
+    let elapsed = (end - start).num_milliseconds() as f64 / 1000.0;
+    let _ = writeln!(
+        grepable,
+        "# pistolcli done at {} -- {}",
+        time_str(&end),
+        summary(hosts.len(), up, elapsed),
+    );
+    grepable
+}
+
+/// Write the grepable report to `path`, `-` means stdout.
+pub fn write_grepable(
+    records: &[Record],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    path: &str,
+) -> Result<()> {
+    let grepable = to_grepable(records, start, end);
+    if path == "-" {
+        print!("{}", grepable);
+    } else {
+        fs::write(path, grepable)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Protocol;
+    use crate::service::Service;
+
+    #[test]
+    fn test_to_grepable() {
+        let record = |addr: &str, port, protocol, state| {
+            Record::new(addr.parse().unwrap(), port, protocol, "syn", state, None)
+        };
+        let mut ssh = record("192.0.2.1", Some(22), Protocol::Tcp, "open");
+        ssh.hostname = Some("host.example".to_string());
+        ssh.service = Some(Service {
+            name: "ssh".to_string(),
+            product: Some("OpenSSH".to_string()),
+            version: Some("9.6".to_string()),
+            extrainfo: Some("protocol 2.0/3".to_string()),
+        });
+        let records = [
+            record("192.0.2.1", None, Protocol::Icmp, "up"),
+            ssh,
+            record("192.0.2.1", Some(53), Protocol::Udp, "open|filtered"),
+            record("192.0.2.2", None, Protocol::Icmp, "down"),
+        ];
+        let start = "2026-01-02T03:04:05Z".parse().unwrap();
+        let end = "2026-01-02T03:04:07Z".parse().unwrap();
+        let grepable = to_grepable(&records, start, end);
+        let lines: Vec<&str> = grepable.lines().collect();
+        let header = format!(
+            "# pistolcli {} scan initiated Fri Jan  2 03:04:05 2026 as: ",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(lines[0].starts_with(&header));
+        assert_eq!(
+            lines[1..],
+            [
+                "Host: 192.0.2.1 (host.example)\tStatus: Up",
+                "Host: 192.0.2.1 (host.example)\tPorts: 22/open/tcp//ssh//OpenSSH 9.6 (protocol 2.0|3)/, 53/open|filtered/udp//domain///",
+                "Host: 192.0.2.2 ()\tStatus: Down",
+                "# pistolcli done at Fri Jan  2 03:04:07 2026 -- 2 IP addresses (1 host up) scanned in 2.00 seconds",
+            ]
+        );
+    }
+}
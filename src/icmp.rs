@@ -0,0 +1,221 @@
+use crate::fingerprint;
+use crate::utils;
+use anyhow::Result;
+use chrono::Timelike;
+use chrono::Utc;
+use pistol::PingResults;
+use pistol::PingStatus;
+use pnet_packet::icmp;
+use pnet_packet::icmp::IcmpCode;
+use pnet_packet::icmp::IcmpPacket;
+use pnet_packet::icmp::IcmpType;
+use pnet_packet::icmp::IcmpTypes;
+use pnet_packet::icmp::MutableIcmpPacket;
+use pnet_packet::ip::IpNextHeaderProtocols;
+use pnet_packet::ipv4::Ipv4Packet;
+use pnet_packet::Packet;
+use pnet_transport::ipv4_packet_iter;
+use std::net::Ipv4Addr;
+use std::process;
+use std::sync::atomic::AtomicU16;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+const ICMP_HEADER_LEN: usize = 4;
+/// Identifier, sequence number and the originate, receive and transmit timestamps
+const TIMESTAMP_LEN: usize = 16;
+/// Identifier, sequence number and the address mask
+const ADDRESS_MASK_LEN: usize = 8;
+
+/// Every query gets its own sequence number, so a late reply to a retry
+/// or to another target does not count for this one
+static SEQUENCE_NUMBER: AtomicU16 = AtomicU16::new(0);
+
+/// An ICMP query and the reply it waits for.
+#[derive(Debug, Clone, Copy)]
+struct Query {
+    request: IcmpType,
+    reply: IcmpType,
+    /// Length of the message after the ICMP header
+    len: usize,
+}
+
+const TIMESTAMP: Query = Query {
+    request: IcmpTypes::Timestamp,
+    reply: IcmpTypes::TimestampReply,
+    len: TIMESTAMP_LEN,
+};
+
+const ADDRESS_MASK: Query = Query {
+    request: IcmpTypes::AddressMaskRequest,
+    reply: IcmpTypes::AddressMaskReply,
+    len: ADDRESS_MASK_LEN,
+};
+
+/// Milliseconds since midnight UT, the time format of ICMP timestamps.
+fn originate_timestamp() -> u32 {
+    let now = Utc::now();
+    now.num_seconds_from_midnight() * 1000 + now.timestamp_subsec_millis()
+}
+
+/// The ICMP message of a query, with its checksum.
+fn request(query: Query, identifier: u16, sequence: u16) -> Vec<u8> {
+    let mut icmp_buff = vec![0u8; ICMP_HEADER_LEN + query.len];
+    // the buffer has the exact packet size, so the packet can always be built
+    let mut icmp_packet = MutableIcmpPacket::new(&mut icmp_buff).unwrap();
+    icmp_packet.set_icmp_type(query.request);
+    icmp_packet.set_icmp_code(IcmpCode(0));
+    let mut message = vec![0u8; query.len];
+    message[0..2].copy_from_slice(&identifier.to_be_bytes());
+    message[2..4].copy_from_slice(&sequence.to_be_bytes());
+    if query.request == IcmpTypes::Timestamp {
+        message[4..8].copy_from_slice(&originate_timestamp().to_be_bytes());
+    }
+    icmp_packet.set_payload(&message);
+    let checksum = icmp::checksum(&icmp_packet.to_immutable());
+    icmp_packet.set_checksum(checksum);
+    icmp_buff
+}
+
+/// Send one ICMP query to `dst_ipv4` and wait for its reply,
+/// the host is up when the reply carries our identifier and sequence number.
+fn query(
+    src_ipv4: Option<Ipv4Addr>,
+    dst_ipv4: Ipv4Addr,
+    interface: Option<&str>,
+    query: Query,
+    timeout: Duration,
+) -> Result<PingStatus> {
+    let (mut tx, mut rx) = fingerprint::channel(IpNextHeaderProtocols::Icmp)?;
+    // the kernel fills in a zero source address
+    let src_ipv4 = utils::source4(src_ipv4, dst_ipv4, interface).unwrap_or(Ipv4Addr::UNSPECIFIED);
+    let identifier = process::id() as u16;
+    let sequence = SEQUENCE_NUMBER.fetch_add(1, Ordering::Relaxed);
+    let message = request(query, identifier, sequence);
+    let protocol = IpNextHeaderProtocols::Icmp;
+    let buff = fingerprint::ipv4_packet(
+        src_ipv4,
+        dst_ipv4,
+        protocol,
+        rand::random(),
+        false,
+        0,
+        &message,
+    );
+    // the buffer holds a whole IPv4 packet
+    tx.send_to(Ipv4Packet::new(&buff).unwrap(), dst_ipv4.into())?;
+
+    let mut iter = ipv4_packet_iter(&mut rx);
+    let start = Instant::now();
+    while let Some(remaining) = timeout.checked_sub(start.elapsed()) {
+        let (ip, _) = match iter.next_with_timeout(remaining)? {
+            Some(r) => r,
+            None => break,
+        };
+        if ip.get_source() != dst_ipv4 || ip.get_next_level_protocol() != protocol {
+            continue;
+        }
+        let reply = IcmpPacket::new(ip.payload());
+        if reply.is_some_and(|r| is_reply(&r, query, identifier, sequence)) {
+            return Ok(PingStatus::Up);
+        }
+    }
+    Ok(PingStatus::Down)
+}
+
+fn is_reply(icmp_packet: &IcmpPacket, query: Query, identifier: u16, sequence: u16) -> bool {
+    let payload = icmp_packet.payload();
+    icmp_packet.get_icmp_type() == query.reply
+        && payload.len() >= 4
+        && payload[0..2] == identifier.to_be_bytes()
+        && payload[2..4] == sequence.to_be_bytes()
+}
+
+/// ICMP timestamp ping (type 13), sent from the source host or the address of the interface.
+pub fn timestamp_ping_host(
+    src_ipv4: Option<Ipv4Addr>,
+    dst_ipv4: Ipv4Addr,
+    interface: Option<&str>,
+    timeout: Duration,
+) -> Result<PingResults> {
+    let status = query(src_ipv4, dst_ipv4, interface, TIMESTAMP, timeout)?;
+    Ok(PingResults {
+        addr: dst_ipv4.into(),
+        status,
+    })
+}
+
+/// ICMP address mask ping (type 17), sent from the source host or the address of the interface.
+pub fn address_mask_ping_host(
+    src_ipv4: Option<Ipv4Addr>,
+    dst_ipv4: Ipv4Addr,
+    interface: Option<&str>,
+    timeout: Duration,
+) -> Result<PingResults> {
+    let status = query(src_ipv4, dst_ipv4, interface, ADDRESS_MASK, timeout)?;
+    Ok(PingResults {
+        addr: dst_ipv4.into(),
+        status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The reply of a host to a request, with the type of the reply.
+    fn reply(request: &[u8], reply_type: IcmpType) -> Vec<u8> {
+        let mut buff = request.to_vec();
+        let mut icmp_packet = MutableIcmpPacket::new(&mut buff).unwrap();
+        icmp_packet.set_icmp_type(reply_type);
+        buff
+    }
+
+    #[test]
+    fn test_request() {
+        let buff = request(TIMESTAMP, 0x1234, 7);
+        assert_eq!(buff.len(), ICMP_HEADER_LEN + TIMESTAMP_LEN);
+        let icmp_packet = IcmpPacket::new(&buff).unwrap();
+        assert_eq!(icmp_packet.get_icmp_type(), IcmpTypes::Timestamp);
+        assert_eq!(icmp_packet.get_checksum(), icmp::checksum(&icmp_packet));
+        assert_eq!(&icmp_packet.payload()[0..4], &[0x12, 0x34, 0, 7]);
+        let buff = request(ADDRESS_MASK, 0x1234, 7);
+        assert_eq!(buff.len(), ICMP_HEADER_LEN + ADDRESS_MASK_LEN);
+        assert_eq!(buff[0], IcmpTypes::AddressMaskRequest.0);
+    }
+
+    #[test]
+    fn test_is_reply() {
+        let buff = reply(&request(TIMESTAMP, 0x1234, 7), IcmpTypes::TimestampReply);
+        let icmp_packet = IcmpPacket::new(&buff).unwrap();
+        assert!(is_reply(&icmp_packet, TIMESTAMP, 0x1234, 7));
+        // another query, a retry or another target
+        assert!(!is_reply(&icmp_packet, TIMESTAMP, 0x1234, 8));
+        assert!(!is_reply(&icmp_packet, TIMESTAMP, 0x4321, 7));
+        assert!(!is_reply(&icmp_packet, ADDRESS_MASK, 0x1234, 7));
+        // our own request looped back
+        let buff = request(TIMESTAMP, 0x1234, 7);
+        assert!(!is_reply(
+            &IcmpPacket::new(&buff).unwrap(),
+            TIMESTAMP,
+            0x1234,
+            7
+        ));
+        let buff = reply(&request(ADDRESS_MASK, 1, 2), IcmpTypes::AddressMaskReply);
+        assert!(is_reply(
+            &IcmpPacket::new(&buff).unwrap(),
+            ADDRESS_MASK,
+            1,
+            2
+        ));
+        // too short to carry the identifier and sequence number
+        let buff = reply(&request(ADDRESS_MASK, 1, 2), IcmpTypes::AddressMaskReply);
+        assert!(!is_reply(
+            &IcmpPacket::new(&buff[..6]).unwrap(),
+            ADDRESS_MASK,
+            1,
+            2
+        ));
+    }
+}
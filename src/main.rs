@@ -1,112 +1,600 @@
+use anyhow::Result;
+use chrono::Utc;
+use clap::ArgGroup;
 use clap::Parser;
+use clap::Subcommand;
+use output::OutputFormat;
+use ping::Probe;
+use ports::PortSet;
+use std::collections::BTreeSet;
 use std::error::Error;
 use std::fmt;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::time::Duration;
 
+mod arp;
+mod banner;
+mod exit;
+mod fingerprint;
 mod flood;
+mod grepable;
+mod icmp;
+mod os;
+mod oui;
+mod output;
+mod payloads;
 mod ping;
+mod ports;
 mod scan;
-
-pub const NULL_VALUE: &str = "null";
+mod service;
+mod targets;
+mod timing;
+mod tls;
+mod utils;
+mod validate;
+mod x509;
+mod xml;
 
 /// Simple nmap-like program with 100% rust
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    command: Commands,
+    /// Output format of the results
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+    /// Also write an nmap compatible XML report to this file (like nmap -oX, `-` for stdout)
+    #[arg(long, global = true, value_name = "FILE")]
+    output_xml: Option<String>,
+    /// Also write a grepable report to this file (like nmap -oG, `-` for stdout)
+    #[arg(long, global = true, value_name = "FILE")]
+    output_grepable: Option<String>,
+    /// Never do reverse DNS resolution
+    #[arg(short = 'n', global = true, conflicts_with = "always_resolve")]
+    no_resolve: bool,
+    /// Do reverse DNS resolution for all targets, not only for the up hosts
+    #[arg(short = 'R', global = true)]
+    always_resolve: bool,
+    #[command(flatten)]
+    timing: TimingArgs,
+    /// Exit with code 7 when an open port is found
+    #[arg(long, global = true)]
+    fail_on_open: bool,
+    /// Exit with code 7 when a host is down
+    #[arg(long, global = true)]
+    fail_on_down: bool,
+    /// Read the MAC address vendors of ARP from this nmap-mac-prefixes file instead of the built-in ones
+    #[arg(long, global = true, value_name = "FILE")]
+    mac_db: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
     /// Scan (port scanning)
-    #[arg(short, long, action)]
-    scan: bool,
+    Scan {
+        #[command(subcommand)]
+        method: ScanMethods,
+    },
     /// Ping (host discovery)
-    #[arg(short, long, action)]
-    ping: bool,
+    Ping {
+        #[command(subcommand)]
+        method: PingMethods,
+    },
     /// Flood (flood attack)
-    #[arg(short, long, action)]
-    flood: bool,
+    Flood {
+        #[command(subcommand)]
+        method: FloodMethods,
+    },
+}
 
+#[derive(Subcommand, Debug)]
+pub enum ScanMethods {
+    /// TCP SYN scan
+    Syn(TcpScanArgs),
+    /// TCP ACK scan
+    Ack(TcpScanArgs),
+    /// TCP connect() scan
+    Connect(TcpScanArgs),
+    /// TCP FIN scan
+    Fin(TcpScanArgs),
+    /// TCP Null scan
+    Null(TcpScanArgs),
+    /// TCP Xmas scan
+    Xmas(TcpScanArgs),
+    /// TCP Window scan
+    Window(TcpScanArgs),
+    /// TCP Maimon scan
+    Maimon(TcpScanArgs),
+    /// TCP Idle scan (through a zombie host)
+    Idle(IdleScanArgs),
+    /// UDP scan
+    Udp(ScanArgs),
+    /// IP protocol scan
+    Ip(IpScanArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PingMethods {
+    /// TCP SYN ping
+    Syn(PingArgs),
+    /// TCP ACK ping
+    Ack(PingArgs),
+    /// UDP ping
+    Udp(PingArgs),
+    /// ICMP echo ping
+    Icmp(IcmpPingArgs),
+    /// ICMP timestamp ping (IPv4 only)
+    Timestamp(IcmpPingArgs),
+    /// ICMP address mask ping (IPv4 only)
+    Mask(IcmpPingArgs),
+    /// ARP ping (directly attached networks only)
+    Arp(ArpPingArgs),
+    /// Combined ping, a host is up when any of the -P probes gets a response
+    Combined(CombinedPingArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FloodMethods {
+    /// TCP SYN flood
+    Syn(FloodArgs),
+    /// TCP ACK flood
+    Ack(FloodArgs),
+    /// UDP flood
+    Udp(FloodArgs),
+    /// ICMP flood
+    Icmp(IcmpFloodArgs),
+}
+
+/// At least one target must be given, all of them are merged into one target set
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+#[command(group(ArgGroup::new("target").required(true).multiple(true)))]
+pub struct TargetArgs {
+    /// Targets (like 192.168.1.0/24, 192.168.1.1-50, 10.0-3.1.1, fd00::/120 or example.com)
+    #[arg(group = "target")]
+    pub targets: Vec<String>,
     /// Target host
-    #[arg(long, default_value = NULL_VALUE)]
-    host: String,
-    /// Target subnet
-    #[arg(long, default_value = NULL_VALUE)]
-    subnet: String,
-    /// Target port (like 80 or 80-8000)
-    #[arg(long, default_value = NULL_VALUE)]
-    port: String,
+    #[arg(long, group = "target")]
+    pub host: Vec<String>,
+    /// Target subnet (like 192.168.1.0/24 or fd00::/120)
+    #[arg(long, group = "target")]
+    pub subnet: Vec<String>,
+    /// Read targets from this file, one or more per line (like nmap -iL, `-` for stdin)
+    #[arg(long, value_name = "FILE", group = "target")]
+    pub input_list: Option<String>,
+    /// Exclude targets (like 192.168.1.1,192.168.1.0/28)
+    #[arg(long, value_delimiter = ',')]
+    pub exclude: Vec<String>,
+    /// Exclude targets read from this file, same format as the input list
+    #[arg(long, value_name = "FILE")]
+    pub excludefile: Option<String>,
+    /// Scan every address of a hostname instead of only the first one
+    #[arg(long)]
+    pub resolve_all: bool,
+    /// Use IPv6, hostnames resolve to IPv6 addresses and IPv4 targets are refused
+    #[arg(short = '6')]
+    pub ipv6: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TimingArgs {
+    /// Timing template from 0 (paranoid) to 5 (insane), like nmap -T
+    #[arg(short = 'T', global = true, value_parser = clap::value_parser!(u8).range(0..=5))]
+    pub timing: Option<u8>,
+    /// A fixed time to wait for a reply (like 500ms, 2s or 1m) instead of one that follows the round trip times
+    #[arg(long, global = true, value_parser = timing::parse_time)]
+    pub timeout: Option<Duration>,
+    /// Time to wait for a reply from a host without a measured round trip time
+    #[arg(long, global = true, value_parser = timing::parse_time)]
+    pub initial_rtt_timeout: Option<Duration>,
+    /// The round trip time based timeout is at least this
+    #[arg(long, global = true, value_parser = timing::parse_time)]
+    pub min_rtt_timeout: Option<Duration>,
+    /// The round trip time based timeout is at most this
+    #[arg(long, global = true, value_parser = timing::parse_time)]
+    pub max_rtt_timeout: Option<Duration>,
+    /// How often a probe without a response is sent again
+    #[arg(long, global = true)]
+    pub max_retries: Option<usize>,
+    /// Probes running at the same time, 0 is as many as cpus
+    #[arg(long, global = true)]
+    pub threads: Option<usize>,
+    /// Send at most this many probes per second
+    #[arg(long, global = true, value_parser = timing::parse_rate)]
+    pub max_rate: Option<f64>,
+    /// Probes that may go out at once before --max-rate spaces them, the size of its token bucket
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_burst: Option<u32>,
+    /// A hint, not a guarantee: run enough probes at once (at most 256) that this many
+    /// probes per second could go out, and never space them further apart than this
+    #[arg(long, global = true, value_parser = timing::parse_rate)]
+    pub min_rate: Option<f64>,
+    /// Wait at least this long between two probes (like 100ms or 1s)
+    #[arg(long, global = true, value_parser = timing::parse_time)]
+    pub scan_delay: Option<Duration>,
+}
 
+#[derive(clap::Args, Debug)]
+pub struct SourceArgs {
     /// Source host
-    #[arg(long, default_value = NULL_VALUE)]
-    source_host: String,
+    #[arg(long)]
+    pub source_host: Option<IpAddr>,
+    /// System interface (like ens33)
+    #[arg(short, long)]
+    pub interface: Option<String>,
+}
+
+/// A host is up when any of the probes gets a response, scans only scan the hosts that are up
+#[derive(clap::Args, Debug)]
+pub struct DiscoveryArgs {
+    /// Host discovery probes (like -PE, -PP, -PM, -PS22,443, -PA80, -PU53 or -PR, -Pn skips the discovery of a scan), -PE -PS443 -PA80 -PP by default
+    #[arg(short = 'P', value_name = "PROBE", value_parser = ping::parse_probe)]
+    pub probes: Vec<Probe>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ScanArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    /// Target ports (like 22,80-8000,-1024,60000-,T:443,U:53,http)
+    #[arg(
+        short,
+        long,
+        required_unless_present = "top_ports",
+        allow_hyphen_values = true,
+        value_parser = ports::parse_port_spec
+    )]
+    pub port: Option<PortSet>,
+    /// Scan the N most common ports, up to 100 for TCP and 50 for UDP
+    #[arg(long, value_name = "N", conflicts_with = "port")]
+    pub top_ports: Option<usize>,
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Source port
+    #[arg(long)]
+    pub source_port: Option<u16>,
+    #[command(flatten)]
+    pub discovery: DiscoveryArgs,
+}
+
+/// The TCP techniques can also look closer at the open ports they find
+#[derive(clap::Args, Debug)]
+pub struct TcpScanArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+    #[command(flatten)]
+    pub post_scan: PostScanArgs,
+}
+
+/// What runs on the open TCP ports after the scan
+#[derive(clap::Args, Debug)]
+pub struct PostScanArgs {
+    #[command(flatten)]
+    pub service: ServiceArgs,
+    #[command(flatten)]
+    pub os: OsArgs,
+    #[command(flatten)]
+    pub banner: BannerArgs,
+    #[command(flatten)]
+    pub tls: TlsArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ServiceArgs {
+    /// Probe the open TCP ports for their service and version (like nmap -sV),
+    /// services behind TLS are found as ssl only since the probes are not sent through TLS
+    #[arg(long)]
+    pub service_version: bool,
+    /// Only send the probes up to this rarity, from 0 (light) to 9 (all)
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub version_intensity: u8,
+    /// Read the service probes from this nmap-service-probes file instead of the built-in ones
+    #[arg(long, value_name = "FILE")]
+    pub version_db: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct OsArgs {
+    /// Fingerprint the TCP/IP stack of the hosts with an open TCP port to guess their OS (like nmap -O)
+    #[arg(short = 'O', long)]
+    pub os_detection: bool,
+    /// Read the OS fingerprints from this nmap-os-db file, the built-in ones only know Linux 6
+    #[arg(long, value_name = "FILE")]
+    pub os_db: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BannerArgs {
+    /// Read the greeting that the services on the open TCP ports send
+    #[arg(long)]
+    pub banner: bool,
+    /// Time to wait for a greeting (like 500ms or 2s)
+    #[arg(long, default_value = "2s", value_parser = timing::parse_time)]
+    pub banner_wait: Duration,
+    /// Keep at most this many bytes of a greeting
+    #[arg(long, default_value_t = 80)]
+    pub banner_length: usize,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TlsArgs {
+    /// Start a TLS handshake with the open TCP ports and report their certificates
+    #[arg(long)]
+    pub tls: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct IdleScanArgs {
+    #[command(flatten)]
+    pub scan: TcpScanArgs,
+    /// Zombie host
+    #[arg(long)]
+    pub zombie_host: Ipv4Addr,
+    /// Zombie port
+    #[arg(long)]
+    pub zombie_port: u16,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct IpScanArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    /// Target IP protocols, all of them by default (like 1,6,17, 0-50 or icmp,tcp,udp)
+    #[arg(short, long, allow_hyphen_values = true, value_parser = ports::parse_protocol_spec)]
+    pub protocol: Option<BTreeSet<u8>>,
+    #[command(flatten)]
+    pub source: SourceArgs,
+    #[command(flatten)]
+    pub discovery: DiscoveryArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PingArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    /// Target port
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    #[command(flatten)]
+    pub source: SourceArgs,
     /// Source port
-    #[arg(long, default_value_t = 0)]
-    source_port: u16,
+    #[arg(long)]
+    pub source_port: Option<u16>,
+    /// Do not switch to ARP for targets on a directly attached network
+    #[arg(long)]
+    pub send_ip: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct IcmpPingArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Do not switch to ARP for targets on a directly attached network
+    #[arg(long)]
+    pub send_ip: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ArpPingArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
     /// System interface (like ens33)
-    #[arg(short, long, default_value = NULL_VALUE)]
-    interface: String,
-
-    /// Zombie host (Idle scan)
-    #[arg(long, default_value = NULL_VALUE)]
-    zombie_host: String,
-    /// Zombie port (Idle scan)
-    #[arg(long, default_value_t = 0)]
-    zombie_port: u16,
-
-    /// Syn flag
-    #[arg(long, action)]
-    syn: bool,
-    /// Ack flag
-    #[arg(long, action)]
-    ack: bool,
-    /// Connect flag
-    #[arg(long, action)]
-    connect: bool,
-    /// Fin flag
-    #[arg(long, action)]
-    fin: bool,
-    /// Null flag
-    #[arg(long, action)]
-    null: bool,
-    /// Xmas flag
-    #[arg(long, action)]
-    xmas: bool,
-    /// Window flag
-    #[arg(long, action)]
-    window: bool,
-    /// Maimon flag
-    #[arg(long, action)]
-    maimon: bool,
-    /// Idle flag
-    #[arg(long, action)]
-    idle: bool,
-    /// Udp flag
-    #[arg(long, action)]
-    udp: bool,
-    /// Ip flag
-    #[arg(long, action)]
-    ip: bool,
-    /// Icmp flag
-    #[arg(long, action)]
-    icmp: bool,
-    /// Arp flag
-    #[arg(long, action)]
-    arp: bool,
+    #[arg(short, long)]
+    pub interface: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CombinedPingArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    #[command(flatten)]
+    pub discovery: DiscoveryArgs,
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Source port
+    #[arg(long)]
+    pub source_port: Option<u16>,
+    /// Do not switch to ARP for targets on a directly attached network
+    #[arg(long)]
+    pub send_ip: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct FloodArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    /// Target port
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Source port
+    #[arg(long)]
+    pub source_port: Option<u16>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct IcmpFloodArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    #[command(flatten)]
+    pub source: SourceArgs,
 }
 
 /* GetTargetPortFailed */
-#[derive(Debug, Clone)]
-pub struct GetTargetPortFailed {}
+#[derive(Debug, Clone, PartialEq)]
+pub enum GetTargetPortFailed {
+    NoPort,
+    /// Ports were given, but none of the protocol the technique sends
+    NoProtocolPort {
+        protocol: String,
+    },
+}
 
 impl fmt::Display for GetTargetPortFailed {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "please set target port")
+        match self {
+            GetTargetPortFailed::NoPort => write!(f, "please set target port"),
+            GetTargetPortFailed::NoProtocolPort { protocol } => {
+                write!(f, "please set target {} port", protocol)
+            }
+        }
     }
 }
 
-impl GetTargetPortFailed {
-    pub fn new() -> GetTargetPortFailed {
-        GetTargetPortFailed {}
+impl Error for GetTargetPortFailed {}
+
+/* IdleScanValueError */
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdleScanValueError {
+    NoZombie,
+    ZombieIsTarget { zombie: String },
+}
+
+impl fmt::Display for IdleScanValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdleScanValueError::NoZombie => write!(f, "can not find the zombie host or port"),
+            IdleScanValueError::ZombieIsTarget { zombie } => {
+                write!(f, "zombie host {} can not be a target", zombie)
+            }
+        }
     }
 }
 
-impl Error for GetTargetPortFailed {}
+impl Error for IdleScanValueError {}
+
+/* ValidationError */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    TargetPort(GetTargetPortFailed),
+    MissingInterface { method: String },
+    IdleScan(IdleScanValueError),
+    Ipv6NotSupported { method: String },
+    AddressFamily { addr: String },
+    NotDirectlyAttached { target: String },
+    NoTargets,
+    TooManyAddresses { target: String, max: usize },
+    RttTimeouts { min: String, max: String },
+    Rates { min: String, max: String },
+    NoProbes,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::TargetPort(e) => e.fmt(f),
+            ValidationError::MissingInterface { method } => write!(
+                f,
+                "{} needs an interface (-i) or a source host for IPv4 targets",
+                method
+            ),
+            ValidationError::IdleScan(e) => e.fmt(f),
+            ValidationError::Ipv6NotSupported { method } => {
+                write!(f, "{} does not support IPv6", method)
+            }
+            ValidationError::AddressFamily { addr } => {
+                write!(f, "address family of {} does not match", addr)
+            }
+            ValidationError::NotDirectlyAttached { target } => {
+                write!(f, "{} is not on a directly attached network", target)
+            }
+            ValidationError::NoTargets => write!(f, "no targets left to scan"),
+            ValidationError::TooManyAddresses { target, max } => write!(
+                f,
+                "target {} has more than {} addresses, split it into smaller ones",
+                target, max
+            ),
+            ValidationError::RttTimeouts { min, max } => write!(
+                f,
+                "min rtt timeout {} is larger than max rtt timeout {}",
+                min, max
+            ),
+            ValidationError::Rates { min, max } => {
+                write!(f, "min rate {} is larger than max rate {}", min, max)
+            }
+            ValidationError::NoProbes => write!(f, "no host discovery probes to send"),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+impl From<GetTargetPortFailed> for ValidationError {
+    fn from(e: GetTargetPortFailed) -> ValidationError {
+        ValidationError::TargetPort(e)
+    }
+}
+
+impl From<IdleScanValueError> for ValidationError {
+    fn from(e: IdleScanValueError) -> ValidationError {
+        ValidationError::IdleScan(e)
+    }
+}
+
+/* InvalidTargetError */
+#[derive(Debug, Clone)]
+pub struct InvalidTargetError {
+    target: String,
+}
+
+impl fmt::Display for InvalidTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid target {}", self.target)
+    }
+}
+
+impl InvalidTargetError {
+    pub fn new(target: String) -> InvalidTargetError {
+        InvalidTargetError { target }
+    }
+}
+
+impl Error for InvalidTargetError {}
+
+/* ResolveHostError */
+#[derive(Debug, Clone)]
+pub struct ResolveHostError {
+    host: String,
+}
+
+impl fmt::Display for ResolveHostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can not resolve host {}", self.host)
+    }
+}
+
+impl ResolveHostError {
+    pub fn new(host: String) -> ResolveHostError {
+        ResolveHostError { host }
+    }
+}
+
+impl Error for ResolveHostError {}
+
+/* ReadInputListError */
+#[derive(Debug, Clone)]
+pub struct ReadInputListError {
+    path: String,
+}
+
+impl fmt::Display for ReadInputListError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can not read input list {}", self.path)
+    }
+}
+
+impl ReadInputListError {
+    pub fn new(path: String) -> ReadInputListError {
+        ReadInputListError { path }
+    }
+}
+
+impl Error for ReadInputListError {}
 
 /* SplitPortError */
 #[derive(Debug, Clone)]
@@ -128,64 +616,397 @@ impl SplitPortError {
 
 impl Error for SplitPortError {}
 
-/* AutoInferScanTypeError */
+/* InvalidPortError */
+#[derive(Debug, Clone)]
+pub struct InvalidPortError {
+    portstr: String,
+}
+
+impl fmt::Display for InvalidPortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid port {}", self.portstr)
+    }
+}
+
+impl InvalidPortError {
+    pub fn new(portstr: String) -> InvalidPortError {
+        InvalidPortError { portstr }
+    }
+}
+
+impl Error for InvalidPortError {}
+
+/* InvalidProtocolError */
+#[derive(Debug, Clone)]
+pub struct InvalidProtocolError {
+    protostr: String,
+}
+
+impl fmt::Display for InvalidProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid ip protocol {}", self.protostr)
+    }
+}
+
+impl InvalidProtocolError {
+    pub fn new(protostr: String) -> InvalidProtocolError {
+        InvalidProtocolError { protostr }
+    }
+}
+
+impl Error for InvalidProtocolError {}
+
+/* UnknownServiceError */
 #[derive(Debug, Clone)]
-pub struct AutoInferScanTypeError {}
+pub struct UnknownServiceError {
+    name: String,
+}
 
-impl fmt::Display for AutoInferScanTypeError {
+impl fmt::Display for UnknownServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown service name {}", self.name)
+    }
+}
+
+impl UnknownServiceError {
+    pub fn new(name: String) -> UnknownServiceError {
+        UnknownServiceError { name }
+    }
+}
+
+impl Error for UnknownServiceError {}
+
+/* TooManyTopPortsError */
+#[derive(Debug, Clone)]
+pub struct TooManyTopPortsError {
+    n: usize,
+    known: usize,
+    protocol: &'static str,
+}
+
+impl fmt::Display for TooManyTopPortsError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "can not auto infer scan type, please set host, port or interface at least one"
+            "--top-ports {} is more than the {} most common {} ports known",
+            self.n, self.known, self.protocol
         )
     }
 }
 
-impl AutoInferScanTypeError {
-    pub fn new() -> AutoInferScanTypeError {
-        AutoInferScanTypeError {}
+impl TooManyTopPortsError {
+    pub fn new(n: usize, known: usize, protocol: &'static str) -> TooManyTopPortsError {
+        TooManyTopPortsError { n, known, protocol }
     }
 }
 
-impl Error for AutoInferScanTypeError {}
+impl Error for TooManyTopPortsError {}
 
-/* IdleScanValueError */
+/* InvalidTimeError */
 #[derive(Debug, Clone)]
-pub struct IdleScanValueError {}
+pub struct InvalidTimeError {
+    time: String,
+}
 
-impl fmt::Display for IdleScanValueError {
+impl fmt::Display for InvalidTimeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "can not find the zombie host or port")
+        write!(f, "invalid time {}", self.time)
     }
 }
 
-impl IdleScanValueError {
-    pub fn new() -> IdleScanValueError {
-        IdleScanValueError {}
+impl InvalidTimeError {
+    pub fn new(time: String) -> InvalidTimeError {
+        InvalidTimeError { time }
     }
 }
 
-impl Error for IdleScanValueError {}
+impl Error for InvalidTimeError {}
 
-fn main() {
-    let args = Args::parse();
-    if args.scan {
+/* InvalidRateError */
+#[derive(Debug, Clone)]
+pub struct InvalidRateError {
+    rate: String,
+}
+
+impl fmt::Display for InvalidRateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid rate {}", self.rate)
+    }
+}
+
+impl InvalidRateError {
+    pub fn new(rate: String) -> InvalidRateError {
+        InvalidRateError { rate }
+    }
+}
+
+impl Error for InvalidRateError {}
+
+/* InvalidProbeError */
+#[derive(Debug, Clone)]
+pub struct InvalidProbeError {
+    probe: String,
+}
+
+impl fmt::Display for InvalidProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid host discovery probe -P{}", self.probe)
+    }
+}
+
+impl InvalidProbeError {
+    pub fn new(probe: String) -> InvalidProbeError {
+        InvalidProbeError { probe }
+    }
+}
+
+impl Error for InvalidProbeError {}
+
+/* ReadServiceProbesError */
+#[derive(Debug, Clone)]
+pub struct ReadServiceProbesError {
+    path: String,
+}
+
+impl fmt::Display for ReadServiceProbesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can not read service probes {}", self.path)
+    }
+}
+
+impl ReadServiceProbesError {
+    pub fn new(path: String) -> ReadServiceProbesError {
+        ReadServiceProbesError { path }
+    }
+}
+
+impl Error for ReadServiceProbesError {}
+
+/* InvalidServiceProbesError */
+#[derive(Debug, Clone)]
+pub struct InvalidServiceProbesError {
+    line: usize,
+}
+
+impl fmt::Display for InvalidServiceProbesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid service probes at line {}", self.line)
+    }
+}
+
+impl InvalidServiceProbesError {
+    pub fn new(line: usize) -> InvalidServiceProbesError {
+        InvalidServiceProbesError { line }
+    }
+}
+
+impl Error for InvalidServiceProbesError {}
+
+/* ReadOsDbError */
+#[derive(Debug, Clone)]
+pub struct ReadOsDbError {
+    path: String,
+}
+
+impl fmt::Display for ReadOsDbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can not read OS fingerprints {}", self.path)
+    }
+}
+
+impl ReadOsDbError {
+    pub fn new(path: String) -> ReadOsDbError {
+        ReadOsDbError { path }
+    }
+}
+
+impl Error for ReadOsDbError {}
+
+/* InvalidOsDbError */
+#[derive(Debug, Clone)]
+pub struct InvalidOsDbError {
+    line: usize,
+}
+
+impl fmt::Display for InvalidOsDbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid OS fingerprints at line {}", self.line)
+    }
+}
+
+impl InvalidOsDbError {
+    pub fn new(line: usize) -> InvalidOsDbError {
+        InvalidOsDbError { line }
+    }
+}
+
+impl Error for InvalidOsDbError {}
+
+/* ReadMacDbError */
+#[derive(Debug, Clone)]
+pub struct ReadMacDbError {
+    path: String,
+}
+
+impl fmt::Display for ReadMacDbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can not read MAC prefixes {}", self.path)
+    }
+}
+
+impl ReadMacDbError {
+    pub fn new(path: String) -> ReadMacDbError {
+        ReadMacDbError { path }
+    }
+}
+
+impl Error for ReadMacDbError {}
+
+/* InvalidMacDbError */
+#[derive(Debug, Clone)]
+pub struct InvalidMacDbError {
+    line: usize,
+}
+
+impl fmt::Display for InvalidMacDbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid MAC prefixes at line {}", self.line)
+    }
+}
+
+impl InvalidMacDbError {
+    pub fn new(line: usize) -> InvalidMacDbError {
+        InvalidMacDbError { line }
+    }
+}
+
+impl Error for InvalidMacDbError {}
+
+/// Run the command and return the exit code of its results.
+fn run(args: Args) -> Result<i32> {
+    let timing = timing::Timing::new(&args.timing)?;
+    utils::init_pacer(&timing);
+    if let Some(path) = &args.mac_db {
+        oui::load(path)?;
+    }
+    let start = Utc::now();
+    let mut records = match args.command {
         // start scan
-        match scan::start_scan(args) {
-            Err(e) => println!("{}", e),
-            _ => (),
-        }
-    } else if args.ping {
+        Commands::Scan { method } => scan::start_scan(method, timing)?,
         // start ping
-        match ping::start_ping(args) {
-            Err(e) => println!("{}", e),
-            _ => (),
-        }
-    } else if args.flood {
+        Commands::Ping { method } => ping::start_ping(method, timing)?,
         // start flood attack
-        match flood::start_flood(args) {
-            Err(e) => println!("{}", e),
-            _ => (),
+        Commands::Flood { method } => {
+            flood::start_flood(method, timing)?;
+            return Ok(exit::flood_code(utils::failed_jobs()));
+        }
+    };
+    let end = Utc::now();
+    records.sort_by_key(|r| (r.target, r.port));
+    if !args.no_resolve {
+        targets::reverse_dns(&mut records, args.always_resolve);
+    }
+
+    let to_stdout = |path: &Option<String>| path.as_deref() == Some("-");
+    // a report written to stdout takes over the normal output
+    if !to_stdout(&args.output_xml) && !to_stdout(&args.output_grepable) {
+        output::print_records(&records, args.output_format)?;
+    }
+    if let Some(path) = &args.output_xml {
+        xml::write_xml(&records, start, end, path)?;
+    }
+    if let Some(path) = &args.output_grepable {
+        grepable::write_grepable(&records, start, end, path)?;
+    }
+    Ok(exit::result_code(
+        &records,
+        utils::failed_jobs(),
+        args.fail_on_open,
+        args.fail_on_down,
+    ))
+}
+
+fn main() {
+    let args = Args::parse();
+    let code = match run(args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit::error_code(&e)
+        }
+    };
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from([&["pistolcli"], args].concat())
+    }
+
+    #[test]
+    fn test_command() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_technique_args() {
+        let target = ["127.0.0.1", "-p", "80"];
+        for flag in ["-O", "--service-version", "--banner", "--tls"] {
+            assert!(parse(&[&["scan", "syn", flag], &target[..]].concat()).is_ok());
+            assert!(parse(&[&["scan", "udp", flag], &target[..]].concat()).is_err());
+            assert!(parse(&["scan", "ip", flag, "127.0.0.1"]).is_err());
         }
+        assert!(parse(&[&["scan", "udp"], &target[..]].concat()).is_ok());
+        assert!(parse(&["scan", "idle", "127.0.0.1", "-p", "80", "-O"]).is_err());
+        let idle = [
+            "scan",
+            "idle",
+            "127.0.0.1",
+            "-p",
+            "80",
+            "--zombie-host",
+            "127.0.0.2",
+            "--zombie-port",
+            "80",
+            "-O",
+        ];
+        assert!(parse(&idle).is_ok());
+        // zombie options only on the idle scan, ports not on the ICMP ping
+        assert!(parse(&[&["scan", "syn", "--zombie-port", "80"], &target[..]].concat()).is_err());
+        assert!(parse(&["ping", "icmp", "127.0.0.1", "-p", "80"]).is_err());
+        assert!(parse(&["scan", "syn", "127.0.0.1"]).is_err());
+    }
+    #[test]
+    fn test_hostname_reaches_records() {
+        // localhost comes from /etc/hosts, the connect scan needs no privileges
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let args = parse(&[
+            "scan",
+            "connect",
+            "localhost",
+            "-p",
+            &port,
+            "--source-host",
+            "127.0.0.1",
+            "-Pn",
+            "-T4",
+        ])
+        .unwrap();
+        let timing = timing::Timing::new(&args.timing).unwrap();
+        let Commands::Scan { method } = args.command else {
+            panic!("not a scan");
+        };
+        let mut records = scan::start_scan(method, timing).unwrap();
+        targets::reverse_dns(&mut records, args.always_resolve);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.target, IpAddr::from(Ipv4Addr::LOCALHOST));
+        assert_eq!(record.hostname.as_deref(), Some("localhost"));
+        assert_eq!(record.state, "open");
     }
 }
@@ -0,0 +1,435 @@
+use crate::fingerprint;
+use crate::fingerprint::Test;
+use crate::output::group_by_host;
+use crate::output::Protocol;
+use crate::output::Record;
+use crate::timing::HostTimeouts;
+use crate::timing::Timing;
+use crate::utils;
+use crate::InvalidOsDbError;
+use crate::OsArgs;
+use crate::ReadOsDbError;
+use anyhow::Result;
+use rand::Rng;
+use serde::Serialize;
+use std::fs;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+/// The built-in fingerprints, in nmap-os-db format, only the ones captured from real hosts
+const OS_DB: &str = include_str!("os-db");
+/// nmap only guesses an OS that matches this well
+const GUESS_THRESHOLD: f64 = 0.85;
+const MAX_GUESSES: usize = 5;
+
+/// A `Class` line with its `CPE` lines.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OsClass {
+    pub vendor: String,
+    pub family: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<String>,
+    pub device_type: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cpe: Vec<String>,
+}
+
+/// A fingerprint of the database and how well the host matches it, in percent.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OsMatch {
+    pub name: String,
+    pub accuracy: u8,
+    pub classes: Vec<OsClass>,
+}
+
+/// The best matches of a host and its own fingerprint, to look up or submit.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OsDetection {
+    pub matches: Vec<OsMatch>,
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Clone)]
+struct OsFingerprint {
+    name: String,
+    classes: Vec<OsClass>,
+    tests: Vec<Test>,
+}
+
+/// The `MatchPoints` and the fingerprints of an nmap-os-db file.
+#[derive(Debug, Clone, Default)]
+pub struct OsDb {
+    match_points: Vec<Test>,
+    fingerprints: Vec<OsFingerprint>,
+}
+
+/// Parse `Linux | Linux | 5.X | general purpose`.
+fn parse_class(line: &str) -> Option<OsClass> {
+    let fields: Vec<&str> = line.split('|').map(|f| f.trim()).collect();
+    let [vendor, family, generation, device_type] = fields[..] else {
+        return None;
+    };
+    Some(OsClass {
+        vendor: vendor.to_string(),
+        family: family.to_string(),
+        generation: (!generation.is_empty()).then(|| generation.to_string()),
+        device_type: device_type.to_string(),
+        cpe: Vec::new(),
+    })
+}
+
+/// Hex numbers, like the values of fingerprints.
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+/// Whether a value matches an expression of the database, like `M5B4ST11NW7|M5B4ST11NW6`,
+/// `FA-108` or `>5`.
+fn expr_match(value: &str, expr: &str) -> bool {
+    expr.split('|').any(|alt| {
+        let number = parse_hex(value);
+        if let (Some(n), Some(bound)) = (number, alt.strip_prefix('>').and_then(parse_hex)) {
+            return n > bound;
+        }
+        if let (Some(n), Some(bound)) = (number, alt.strip_prefix('<').and_then(parse_hex)) {
+            return n < bound;
+        }
+        if let Some((low, high)) = alt.split_once('-') {
+            if let (Some(n), Some(low), Some(high)) = (number, parse_hex(low), parse_hex(high)) {
+                return low <= n && n <= high;
+            }
+        }
+        alt == value
+    })
+}
+
+impl OsDb {
+    /// Parse a database in nmap-os-db format.
+    pub fn parse(content: &str) -> Result<OsDb> {
+        let mut db = OsDb::default();
+        let mut in_match_points = false;
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || InvalidOsDbError::new(i + 1);
+            let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match directive {
+                "MatchPoints" => in_match_points = true,
+                "Fingerprint" => {
+                    in_match_points = false;
+                    db.fingerprints.push(OsFingerprint {
+                        name: rest.trim().to_string(),
+                        classes: Vec::new(),
+                        tests: Vec::new(),
+                    });
+                }
+                "Class" => {
+                    let fp = db.fingerprints.last_mut().ok_or_else(invalid)?;
+                    fp.classes.push(parse_class(rest).ok_or_else(invalid)?);
+                }
+                "CPE" => {
+                    let fp = db.fingerprints.last_mut().ok_or_else(invalid)?;
+                    let class = fp.classes.last_mut().ok_or_else(invalid)?;
+                    // `auto` marks CPEs nmap generated from the class
+                    let cpe = rest.trim().trim_end_matches(" auto");
+                    class.cpe.push(cpe.to_string());
+                }
+                _ => {
+                    let test = Test::parse(line).ok_or_else(invalid)?;
+                    if in_match_points {
+                        db.match_points.push(test);
+                    } else {
+                        let fp = db.fingerprints.last_mut().ok_or_else(invalid)?;
+                        fp.tests.push(test);
+                    }
+                }
+            }
+        }
+        Ok(db)
+    }
+
+    /// The built-in database, or the one in `path`.
+    pub fn load(path: Option<&str>) -> Result<OsDb> {
+        match path {
+            Some(path) => {
+                let content =
+                    fs::read_to_string(path).map_err(|_| ReadOsDbError::new(path.to_string()))?;
+                OsDb::parse(&content)
+            }
+            None => OsDb::parse(OS_DB),
+        }
+    }
+
+    /// The points of the attributes that match, out of all the points of the attributes
+    /// both the host and the reference have.
+    fn accuracy(&self, tests: &[Test], reference: &[Test]) -> f64 {
+        let mut possible = 0;
+        let mut matched = 0;
+        for ref_test in reference {
+            let Some(test) = tests.iter().find(|t| t.name == ref_test.name) else {
+                continue;
+            };
+            let Some(points) = self.match_points.iter().find(|t| t.name == ref_test.name) else {
+                continue;
+            };
+            for (attr, expr) in &ref_test.values {
+                let Some(value) = test.get(attr) else {
+                    continue;
+                };
+                let points: u32 = points.get(attr).and_then(|p| p.parse().ok()).unwrap_or(0);
+                possible += points;
+                if expr_match(value, expr) {
+                    matched += points;
+                }
+            }
+        }
+        match possible {
+            0 => 0.0,
+            _ => matched as f64 / possible as f64,
+        }
+    }
+
+    /// The best guesses for a fingerprint, most accurate first.
+    pub fn matches(&self, tests: &[Test]) -> Vec<OsMatch> {
+        let mut scores: Vec<(f64, &OsFingerprint)> = self
+            .fingerprints
+            .iter()
+            .map(|fp| (self.accuracy(tests, &fp.tests), fp))
+            .filter(|(accuracy, _)| *accuracy >= GUESS_THRESHOLD)
+            .collect();
+        scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scores
+            .into_iter()
+            .take(MAX_GUESSES)
+            .map(|(accuracy, fp)| OsMatch {
+                name: fp.name.clone(),
+                accuracy: (accuracy * 100.0).floor() as u8,
+                classes: fp.classes.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Fingerprint the hosts that have an open TCP port, their OS guesses go on the record
+/// of the open port the fingerprint was taken through.
+pub fn detect_os(
+    records: &mut [Record],
+    args: &OsArgs,
+    src_ipv4: Option<Ipv4Addr>,
+    interface: Option<&str>,
+    timeouts: &HostTimeouts,
+    timing: Timing,
+) -> Result<()> {
+    if !args.os_detection {
+        return Ok(());
+    }
+    let db = Arc::new(OsDb::load(args.os_db.as_deref())?);
+    let mut jobs = Vec::new();
+    for (addr, host_records) in group_by_host(records) {
+        let tcp_port = |state: &str| {
+            host_records
+                .iter()
+                .find(|r| r.protocol == Protocol::Tcp && r.state == state)
+                .and_then(|r| r.port)
+        };
+        // validation refuses IPv6 targets with -O
+        let IpAddr::V4(dst_ipv4) = addr else {
+            continue;
+        };
+        let Some(open_port) = tcp_port("open") else {
+            eprintln!("OS detection of {} skipped, no open TCP port found", addr);
+            continue;
+        };
+        let mut rng = rand::thread_rng();
+        let closed_port = tcp_port("closed").unwrap_or_else(|| {
+            eprintln!(
+                "OS detection of {} may be unreliable, no closed TCP port found",
+                addr
+            );
+            rng.gen_range(30000..60000)
+        });
+        let Some(src_ipv4) = utils::source4(src_ipv4, dst_ipv4, interface) else {
+            eprintln!("OS detection of {} skipped, no route to it", addr);
+            continue;
+        };
+        let closed_udp_port = rng.gen_range(30000..60000);
+        let ports = (open_port, closed_port, closed_udp_port);
+        jobs.push((src_ipv4, dst_ipv4, ports));
+    }
+    let timeouts = timeouts.clone();
+    let rets = utils::run_jobs(jobs, timing.threads, move |job| {
+        let (src_ipv4, dst_ipv4, (open_port, closed_port, closed_udp_port)) = job;
+        let wait = timeouts.timeout(dst_ipv4.into());
+        let tests = fingerprint::fingerprint(
+            src_ipv4,
+            dst_ipv4,
+            open_port,
+            closed_port,
+            closed_udp_port,
+            wait,
+        )?;
+        let fingerprint: Vec<String> = tests.iter().map(|t| t.to_string()).collect();
+        let detection = OsDetection {
+            matches: db.matches(&tests),
+            fingerprint: fingerprint.join("\n"),
+        };
+        if detection.matches.is_empty() {
+            eprintln!(
+                "No OS matches for {}, TCP/IP fingerprint:\n{}",
+                dst_ipv4, detection.fingerprint
+            );
+        }
+        Ok((IpAddr::from(dst_ipv4), open_port, detection))
+    });
+    for (addr, open_port, detection) in utils::collect_jobs(rets)? {
+        let record = records
+            .iter_mut()
+            .find(|r| r.target == addr && r.protocol == Protocol::Tcp && r.port == Some(open_port));
+        if let Some(record) = record {
+            record.os = Some(detection);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tests(lines: &str) -> Vec<Test> {
+        lines.lines().map(|l| Test::parse(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_expr_match() {
+        assert!(expr_match("M5B4ST11NW7", "M5B4ST11NW6|M5B4ST11NW7"));
+        assert!(expr_match("100", "FA-108"));
+        assert!(!expr_match("109", "FA-108"));
+        assert!(expr_match("6", ">5"));
+        assert!(!expr_match("5", ">5|<3"));
+        assert!(expr_match("S+", "S+"));
+        assert!(expr_match("", ""));
+        assert!(!expr_match("AS", "A"));
+    }
+
+    #[test]
+    fn test_builtin_db() {
+        let db = OsDb::load(None).unwrap();
+        assert!(db.match_points.iter().any(|t| t.name == "SEQ"));
+        assert!(!db.fingerprints.is_empty());
+        // every test of a fingerprint has its points
+        for fp in &db.fingerprints {
+            assert!(!fp.classes.is_empty(), "{}", fp.name);
+            for test in &fp.tests {
+                let points = db.match_points.iter().find(|t| t.name == test.name);
+                let points = points.unwrap_or_else(|| panic!("{} {}", fp.name, test.name));
+                for (attr, _) in &test.values {
+                    assert!(
+                        points.get(attr).is_some(),
+                        "{} {} {}",
+                        fp.name,
+                        test.name,
+                        attr
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let db = OsDb::load(None).unwrap();
+        // a Linux 6.18 host on the other end of a veth pair
+        let linux = tests(
+            "SEQ(SP=FD%GCD=1%ISR=109%TI=Z%CI=Z%II=I%TS=22)\n\
+             OPS(O1=M5B4ST11NWA%O2=M5B4ST11NWA%O3=M5B4NNT11NWA%O4=M5B4ST11NWA%O5=M5B4ST11NWA%O6=M5B4ST11)\n\
+             WIN(W1=FE88%W2=FE88%W3=FE88%W4=FE88%W5=FE88%W6=FE88)\n\
+             ECN(R=Y%DF=Y%T=40%W=FAF0%O=M5B4NNSNWA%CC=Y%Q=)\n\
+             T1(R=Y%DF=Y%T=40%S=O%A=S+%F=AS%RD=0%Q=)\n\
+             T2(R=N)\n\
+             T3(R=N)\n\
+             T4(R=Y%DF=Y%T=40%W=0%S=A%A=Z%F=R%O=%RD=0%Q=)\n\
+             T5(R=Y%DF=Y%T=40%W=0%S=Z%A=S+%F=AR%O=%RD=0%Q=)\n\
+             T6(R=Y%DF=Y%T=40%W=0%S=A%A=Z%F=R%O=%RD=0%Q=)\n\
+             T7(R=Y%DF=Y%T=40%W=0%S=Z%A=S+%F=AR%O=%RD=0%Q=)\n\
+             U1(R=Y%DF=N%T=40%IPL=164%UN=0%RIPL=G%RID=G%RIPCK=G%RUCK=G%RUD=G)\n\
+             IE(R=Y%DFI=N%T=40%CD=S)",
+        );
+        let matches = db.matches(&linux);
+        assert_eq!(matches[0].name, "Linux 6.X");
+        assert_eq!(matches[0].accuracy, 100);
+        assert_eq!(matches[0].classes[0].family, "Linux");
+        // nothing answers like that
+        let odd = tests("T1(R=Y%DF=N%T=FF%S=Z%A=Z%F=U%RD=1%Q=RU)\nT5(R=N)");
+        assert!(db.matches(&odd).is_empty());
+
+        // a Windows 10 host, the same tests with other windows, options, TTLs and IP IDs
+        let windows = tests(
+            "SEQ(SP=FE%GCD=1%ISR=10A%TI=I%CI=I%II=I%SS=S%TS=A)\n\
+             OPS(O1=M5B4NW8ST11%O2=M5B4NW8ST11%O3=M5B4NW8NNT11%O4=M5B4NW8ST11%O5=M5B4NW8ST11%O6=M5B4ST11)\n\
+             WIN(W1=FFFF%W2=FFFF%W3=FFFF%W4=FFFF%W5=FFFF%W6=FF70)\n\
+             ECN(R=Y%DF=Y%T=80%W=FFFF%O=M5B4NW8NNS%CC=N%Q=)\n\
+             T1(R=Y%DF=Y%T=80%S=O%A=S+%F=AS%RD=0%Q=)\n\
+             T2(R=Y%DF=Y%T=80%W=0%S=Z%A=S%F=AR%O=%RD=0%Q=)\n\
+             T3(R=Y%DF=Y%T=80%W=0%S=Z%A=O%F=AR%O=%RD=0%Q=)\n\
+             T4(R=Y%DF=Y%T=80%W=0%S=A%A=O%F=R%O=%RD=0%Q=)\n\
+             T5(R=Y%DF=Y%T=80%W=0%S=Z%A=S+%F=AR%O=%RD=0%Q=)\n\
+             T6(R=Y%DF=Y%T=80%W=0%S=A%A=O%F=R%O=%RD=0%Q=)\n\
+             T7(R=N)\n\
+             U1(R=N)\n\
+             IE(R=Y%DFI=N%T=80%CD=Z)",
+        );
+        let db_linux = &db.fingerprints[0];
+        assert!(db.accuracy(&windows, &db_linux.tests) < GUESS_THRESHOLD);
+        assert!(db.matches(&windows).is_empty());
+
+        assert!(OsDb::parse("Class a | b | c | d\n").is_err());
+        assert!(OsDb::parse("Fingerprint x\nClass a | b\n").is_err());
+        assert!(OsDb::parse("Fingerprint x\nSEQ SP=1\n").is_err());
+    }
+
+    #[test]
+    fn test_accuracy() {
+        let db = OsDb::parse(
+            "MatchPoints\n\
+             T1(R=100%DF=20%T=15)\n\
+             IE(R=50%CD=100)\n\
+             Fingerprint Exact\n\
+             Class A | A | 1 | general purpose\n\
+             T1(R=Y%DF=Y%T=3B-45)\n\
+             IE(R=Y%CD=S)\n\
+             Fingerprint Partial\n\
+             Class B | B | 1 | general purpose\n\
+             T1(R=Y%DF=N%T=3B-45)\n\
+             IE(R=Y%CD=S)\n\
+             Fingerprint Mismatched\n\
+             Class C | C | 1 | general purpose\n\
+             T1(R=Y%DF=Y%T=80)\n\
+             IE(R=Y%CD=Z)\n\
+             Fingerprint Nothing in common\n\
+             Class D | D | 1 | general purpose\n\
+             U1(R=Y)\n",
+        )
+        .unwrap();
+        let host = tests("T1(R=Y%DF=Y%T=40)\nIE(R=Y%CD=S)");
+        let accuracy = |i: usize| db.accuracy(&host, &db.fingerprints[i].tests);
+        // 285 points in all, DF is 20 of them and T and CD 115
+        assert_eq!(accuracy(0), 1.0);
+        assert_eq!(accuracy(1), 265.0 / 285.0);
+        assert_eq!(accuracy(2), 170.0 / 285.0);
+        assert_eq!(accuracy(3), 0.0);
+        let matches = db.matches(&host);
+        let names: Vec<(&str, u8)> = matches
+            .iter()
+            .map(|m| (m.name.as_str(), m.accuracy))
+            .collect();
+        assert_eq!(names, [("Exact", 100), ("Partial", 92)]);
+        // a test the host did not answer only leaves out its points
+        let host = tests("T1(R=Y%DF=N%T=40)");
+        assert_eq!(db.accuracy(&host, &db.fingerprints[1].tests), 1.0);
+        assert_eq!(db.accuracy(&host, &db.fingerprints[0].tests), 115.0 / 135.0);
+    }
+}
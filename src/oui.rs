@@ -0,0 +1,134 @@
+use crate::InvalidMacDbError;
+use crate::ReadMacDbError;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+
+/// Vendors of common OUIs (the first three octets of a MAC address),
+/// only a few virtual NICs and server vendors, `--mac-db` knows the others.
+const OUI_VENDORS: &[(&str, &str)] = &[
+    ("00:00:0c", "Cisco Systems"),
+    ("00:03:93", "Apple"),
+    ("00:04:4b", "Nvidia"),
+    ("00:05:69", "VMware"),
+    ("00:09:0f", "Fortinet"),
+    ("00:0c:29", "VMware"),
+    ("00:0d:3a", "Microsoft"),
+    ("00:14:22", "Dell"),
+    ("00:15:5d", "Microsoft Hyper-V"),
+    ("00:16:3e", "Xensource"),
+    ("00:1a:11", "Google"),
+    ("00:1b:17", "Palo Alto Networks"),
+    ("00:1b:21", "Intel Corporate"),
+    ("00:1b:63", "Apple"),
+    ("00:1c:14", "VMware"),
+    ("00:1c:42", "Parallels"),
+    ("00:25:90", "Super Micro Computer"),
+    ("00:50:56", "VMware"),
+    ("00:e0:4c", "Realtek Semiconductor"),
+    ("08:00:27", "Oracle VirtualBox virtual NIC"),
+    ("52:54:00", "QEMU virtual NIC"),
+    ("b8:27:eb", "Raspberry Pi Foundation"),
+    ("dc:a6:32", "Raspberry Pi Trading"),
+    ("e4:5f:01", "Raspberry Pi Trading"),
+];
+
+/// The shortest and longest prefix of nmap-mac-prefixes, in hex digits (MA-L and MA-S)
+const MIN_PREFIX_LEN: usize = 6;
+const MAX_PREFIX_LEN: usize = 9;
+
+/// The vendors of `--mac-db` by their upper case prefix, not set without it
+static MAC_DB: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Parse the nmap-mac-prefixes format, a hex prefix and its vendor per line.
+fn parse(content: &str) -> Result<HashMap<String, String>> {
+    let mut vendors = HashMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (prefix, vendor) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let vendor = vendor.trim();
+        if !(MIN_PREFIX_LEN..=MAX_PREFIX_LEN).contains(&prefix.len())
+            || !prefix.chars().all(|c| c.is_ascii_hexdigit())
+            || vendor.is_empty()
+        {
+            return Err(InvalidMacDbError::new(i + 1).into());
+        }
+        vendors.insert(prefix.to_ascii_uppercase(), vendor.to_string());
+    }
+    Ok(vendors)
+}
+
+/// Use the vendors of this nmap-mac-prefixes file instead of the built-in ones.
+pub fn load(path: &str) -> Result<()> {
+    // the vendor names are not always UTF-8
+    let content = fs::read(path).map_err(|_| ReadMacDbError::new(path.to_string()))?;
+    let vendors = parse(&String::from_utf8_lossy(&content))?;
+    let _ = MAC_DB.set(vendors);
+    Ok(())
+}
+
+/// The vendor of the longest prefix of `mac` in the vendors of a file.
+fn longest_prefix<'a>(vendors: &'a HashMap<String, String>, mac: &str) -> Option<&'a str> {
+    let hex: String = mac
+        .chars()
+        .filter(char::is_ascii_hexdigit)
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    (MIN_PREFIX_LEN..=MAX_PREFIX_LEN.min(hex.len()))
+        .rev()
+        .find_map(|len| vendors.get(&hex[..len]))
+        .map(String::as_str)
+}
+
+/// Look up the vendor of a MAC address like `00:50:56:c0:00:08` by its OUI.
+pub fn vendor(mac: &str) -> Option<&'static str> {
+    if let Some(vendors) = MAC_DB.get() {
+        return longest_prefix(vendors, mac);
+    }
+    let oui = mac.get(..8)?.to_ascii_lowercase();
+    OUI_VENDORS
+        .iter()
+        .find(|(prefix, _)| *prefix == oui)
+        .map(|(_, vendor)| *vendor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_vendor() {
+        assert_eq!(vendor("00:50:56:C0:00:08"), Some("VMware"));
+        assert_eq!(vendor("52:54:00:12:34:56"), Some("QEMU virtual NIC"));
+        assert_eq!(vendor("02:00:00:00:00:01"), None);
+        assert_eq!(vendor("00:50"), None);
+    }
+
+    #[test]
+    fn test_mac_db() {
+        let content = "# nmap-mac-prefixes\n\n000C29 VMware\n70B3D5 IEEE Registration Authority\n70B3D5F2F Acme Sensors\n";
+        let vendors = parse(content).unwrap();
+        assert_eq!(vendors.len(), 3);
+        assert_eq!(
+            longest_prefix(&vendors, "00:0c:29:aa:bb:cc"),
+            Some("VMware")
+        );
+        // the MA-S block wins over the OUI of the registration authority
+        assert_eq!(
+            longest_prefix(&vendors, "70:b3:d5:f2:f0:01"),
+            Some("Acme Sensors")
+        );
+        assert_eq!(
+            longest_prefix(&vendors, "70:b3:d5:00:00:01"),
+            Some("IEEE Registration Authority")
+        );
+        assert_eq!(longest_prefix(&vendors, "00:50:56:c0:00:08"), None);
+        for line in ["000C29", "000C2 VMware", "00:0C:29 VMware", "000C2G VMware"] {
+            assert!(parse(line).is_err(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use crate::os::OsDetection;
+use crate::oui;
+use crate::ports;
+use crate::service::Service;
+use crate::tls::TlsInfo;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use clap::ValueEnum;
+use pistol::IpScanResults;
+use pistol::IpScanStatus;
+use pistol::PingResults;
+use pistol::PingStatus;
+use pistol::TcpScanResults;
+use pistol::TcpScanStatus;
+use pistol::UdpScanResults;
+use pistol::UdpScanStatus;
+use pnet_datalink::MacAddr;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// One result per line
+    Text,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Arp,
+    Ip,
+}
+
+/// One result, a port of a host for scan and a host for ping.
+#[derive(Serialize, Debug, Clone)]
+pub struct Record {
+    pub target: IpAddr,
+    /// The name the target was given as or resolved back to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    pub protocol: Protocol,
+    pub technique: &'static str,
+    pub state: &'static str,
+    /// MAC address, only known from ARP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    /// Vendor of the MAC address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<&'static str>,
+    /// Service and version, only known from service detection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<Service>,
+    /// The first bytes the service sent, escaped, only known from banner grabbing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    /// TLS version, cipher and certificate, only known from the TLS probe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsInfo>,
+    /// OS guesses of the host, only known from OS detection and only on the record of
+    /// the open port the fingerprint was taken through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<OsDetection>,
+    /// The port of the TCP or UDP ping that found the host up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping_port: Option<u16>,
+    /// Round trip time in milliseconds, only known when a single probe was sent
+    pub rtt_ms: Option<f64>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Record {
+    pub fn new(
+        target: IpAddr,
+        port: Option<u16>,
+        protocol: Protocol,
+        technique: &'static str,
+        state: &'static str,
+        rtt: Option<Duration>,
+    ) -> Record {
+        Record {
+            target,
+            hostname: None,
+            port,
+            protocol,
+            technique,
+            state,
+            mac: None,
+            vendor: None,
+            service: None,
+            banner: None,
+            tls: None,
+            os: None,
+            ping_port: None,
+            rtt_ms: rtt.map(|r| r.as_secs_f64() * 1000.0),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.target)?;
+        if let Some(hostname) = &self.hostname {
+            write!(f, " ({})", hostname)?;
+        }
+        let protocol = protocol_str(self.protocol);
+        match (self.port, self.ping_port) {
+            (Some(port), _) => write!(f, " {}/{} {}", port, protocol, self.state)?,
+            (None, Some(ping_port)) => write!(
+                f,
+                " {} ({} ping to {}/{})",
+                self.state, self.technique, ping_port, protocol
+            )?,
+            (None, None) => write!(f, " {} ({} ping)", self.state, self.technique)?,
+        }
+        if let Some(service) = &self.service {
+            write!(f, " {}", service.name)?;
+            let version_info = service.version_info();
+            if !version_info.is_empty() {
+                write!(f, " {}", version_info)?;
+            }
+        }
+        if let Some(banner) = &self.banner {
+            write!(f, " \"{}\"", banner)?;
+        }
+        if let Some(tls) = &self.tls {
+            let certificate = &tls.certificate;
+            write!(
+                f,
+                " tls {} {} {} valid until {}",
+                tls.version,
+                tls.cipher,
+                certificate.subject,
+                certificate.not_after.format("%Y-%m-%d")
+            )?;
+            if certificate.self_signed {
+                write!(f, " self-signed")?;
+            }
+        }
+        if let Some(os) = &self.os {
+            let guesses: Vec<String> = os
+                .matches
+                .iter()
+                .map(|m| format!("{} ({}%)", m.name, m.accuracy))
+                .collect();
+            match guesses.is_empty() {
+                true => write!(f, " os unknown")?,
+                false => write!(f, " os {}", guesses.join(", "))?,
+            }
+        }
+        if let Some(mac) = &self.mac {
+            write!(f, " {}", mac)?;
+            if let Some(vendor) = self.vendor {
+                write!(f, " ({})", vendor)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn protocol_str(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+        Protocol::Icmp => "icmp",
+        Protocol::Arp => "arp",
+        Protocol::Ip => "ip",
+    }
+}
+
+fn tcp_state(status: TcpScanStatus) -> &'static str {
+    match status {
+        TcpScanStatus::Open => "open",
+        TcpScanStatus::OpenOrFiltered => "open|filtered",
+        TcpScanStatus::Filtered => "filtered",
+        TcpScanStatus::Unfiltered => "unfiltered",
+        TcpScanStatus::Closed => "closed",
+        TcpScanStatus::Unreachable => "unreachable",
+        TcpScanStatus::ClosedOrFiltered => "closed|filtered",
+    }
+}
+
+fn udp_state(status: UdpScanStatus) -> &'static str {
+    match status {
+        UdpScanStatus::Open => "open",
+        UdpScanStatus::OpenOrFiltered => "open|filtered",
+        UdpScanStatus::Filtered => "filtered",
+        UdpScanStatus::Closed => "closed",
+    }
+}
+
+fn ip_state(status: IpScanStatus) -> &'static str {
+    match status {
+        IpScanStatus::Open => "open",
+        IpScanStatus::OpenOrFiltered => "open|filtered",
+        IpScanStatus::Filtered => "filtered",
+        IpScanStatus::Closed => "closed",
+    }
+}
+
+fn ping_state(status: PingStatus) -> &'static str {
+    match status {
+        PingStatus::Up => "up",
+        PingStatus::Down => "down",
+    }
+}
+
+/// None of the probes got a response, so they are worth sending again.
+pub fn no_response(records: &[Record]) -> bool {
+    records
+        .iter()
+        .all(|r| matches!(r.state, "filtered" | "open|filtered" | "down"))
+}
+
+pub fn tcp_records(
+    ret: &TcpScanResults,
+    technique: &'static str,
+    rtt: Option<Duration>,
+) -> Vec<Record> {
+    ret.results
+        .iter()
+        .map(|(port, status)| {
+            Record::new(
+                ret.addr,
+                Some(*port),
+                Protocol::Tcp,
+                technique,
+                tcp_state(*status),
+                rtt,
+            )
+        })
+        .collect()
+}
+
+pub fn udp_records(ret: &UdpScanResults, rtt: Option<Duration>) -> Vec<Record> {
+    ret.results
+        .iter()
+        .map(|(port, status)| {
+            Record::new(
+                ret.addr,
+                Some(*port),
+                Protocol::Udp,
+                "udp",
+                udp_state(*status),
+                rtt,
+            )
+        })
+        .collect()
+}
+
+/// IP protocol scan results, the protocol number takes the place of the port.
+pub fn ip_records(ret: &IpScanResults, rtt: Option<Duration>) -> Vec<Record> {
+    ret.results
+        .iter()
+        .map(|(protocol, status)| {
+            Record::new(
+                ret.addr.into(),
+                Some(protocol.0 as u16),
+                Protocol::Ip,
+                "ip",
+                ip_state(*status),
+                rtt,
+            )
+        })
+        .collect()
+}
+
+pub fn ping_record(
+    ret: &PingResults,
+    protocol: Protocol,
+    technique: &'static str,
+    dst_port: Option<u16>,
+    rtt: Option<Duration>,
+) -> Record {
+    let mut record = Record::new(
+        ret.addr,
+        None,
+        protocol,
+        technique,
+        ping_state(ret.status),
+        rtt,
+    );
+    if matches!(ret.status, PingStatus::Up) {
+        record.ping_port = dst_port;
+    }
+    record
+}
+
+/// An ARP answer, the host is up when its MAC address is known.
+pub fn arp_record(addr: Ipv4Addr, mac: Option<MacAddr>, rtt: Option<Duration>) -> Record {
+    let (state, rtt) = match mac {
+        Some(_) => ("up", rtt),
+        // no answer, so no round trip either
+        None => ("down", None),
+    };
+    let mut record = Record::new(addr.into(), None, Protocol::Arp, "arp", state, rtt);
+    record.mac = mac.map(|m| m.to_string());
+    record.vendor = record.mac.as_deref().and_then(oui::vendor);
+    record
+}
+
+/// The detected or well known service on the port of a record, or the name of its IP protocol.
+pub fn service_name(r: &Record) -> Option<&str> {
+    if let Some(service) = &r.service {
+        return Some(&service.name);
+    }
+    let port = r.port?;
+    match r.protocol {
+        Protocol::Ip => ports::protocol_name(port as u8),
+        protocol => ports::service_name(port, protocol == Protocol::Tcp),
+    }
+}
+
+/// Attach the hostname of a target to all of its records.
+pub fn with_hostname(mut records: Vec<Record>, hostname: &Option<String>) -> Vec<Record> {
+    for r in &mut records {
+        r.hostname = hostname.clone();
+    }
+    records
+}
+
+pub fn group_by_host(records: &[Record]) -> BTreeMap<IpAddr, Vec<&Record>> {
+    let mut hosts: BTreeMap<IpAddr, Vec<&Record>> = BTreeMap::new();
+    for r in records {
+        hosts.entry(r.target).or_default().push(r);
+    }
+    hosts
+}
+
+/// Host state as nmap sees it, a host that answered at least one probe is up.
+pub fn host_state(records: &[&Record]) -> &'static str {
+    if records
+        .iter()
+        .any(|r| r.port.is_none() && r.state == "down")
+    {
+        "down"
+    } else if records
+        .iter()
+        .any(|r| matches!(r.state, "up" | "open" | "closed" | "unfiltered"))
+    {
+        "up"
+    } else {
+        "unknown"
+    }
+}
+
+pub fn time_str(time: &DateTime<Utc>) -> String {
+    time.format("%a %b %e %H:%M:%S %Y").to_string()
+}
+
+/// The nmap style run summary, like `1 IP address (1 host up) scanned in 0.10 seconds`.
+pub fn summary(hosts_num: usize, up_num: usize, elapsed: f64) -> String {
+    format!(
+        "{} IP address{} ({} host{} up) scanned in {:.2} seconds",
+        hosts_num,
+        if hosts_num == 1 { "" } else { "es" },
+        up_num,
+        if up_num == 1 { "" } else { "s" },
+        elapsed
+    )
+}
+
+pub fn print_records(records: &[Record], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            for r in records {
+                println!("{}", r);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(records)?),
+        OutputFormat::Jsonl => {
+            for r in records {
+                println!("{}", serde_json::to_string(r)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(port: Option<u16>, protocol: Protocol, state: &'static str) -> Record {
+        let technique = match protocol {
+            Protocol::Icmp => "icmp",
+            Protocol::Udp => "udp",
+            _ => "syn",
+        };
+        let mut r = Record::new(
+            "192.0.2.1".parse().unwrap(),
+            port,
+            protocol,
+            technique,
+            state,
+            Some(Duration::from_micros(1500)),
+        );
+        r.timestamp = "2026-01-02T03:04:05Z".parse().unwrap();
+        r
+    }
+
+    #[test]
+    fn test_record_json() {
+        let mut r = record(Some(22), Protocol::Tcp, "open");
+        r.hostname = Some("host.example".to_string());
+        r.service = Some(Service {
+            name: "ssh".to_string(),
+            product: Some("OpenSSH".to_string()),
+            version: Some("9.6".to_string()),
+            extrainfo: None,
+        });
+        r.banner = Some("SSH-2.0-OpenSSH_9.6\\r\\n".to_string());
+        assert_eq!(
+            serde_json::to_value(&r).unwrap(),
+            json!({
+                "target": "192.0.2.1",
+                "hostname": "host.example",
+                "port": 22,
+                "protocol": "tcp",
+                "technique": "syn",
+                "state": "open",
+                "service": {"name": "ssh", "product": "OpenSSH", "version": "9.6"},
+                "banner": "SSH-2.0-OpenSSH_9.6\\r\\n",
+                "rtt_ms": 1.5,
+                "timestamp": "2026-01-02T03:04:05Z"
+            })
+        );
+
+        // a ping has no port, the fields nothing found are left out but the rtt
+        let mut ping = record(None, Protocol::Icmp, "down");
+        ping.rtt_ms = None;
+        assert_eq!(
+            serde_json::to_value(&ping).unwrap(),
+            json!({
+                "target": "192.0.2.1",
+                "protocol": "icmp",
+                "technique": "icmp",
+                "state": "down",
+                "rtt_ms": null,
+                "timestamp": "2026-01-02T03:04:05Z"
+            })
+        );
+
+        let mut arp = arp_record("192.0.2.1".parse().unwrap(), None, None);
+        arp.mac = Some("00:00:0c:01:02:03".to_string());
+        arp.vendor = Some("Cisco");
+        let value = serde_json::to_value(&arp).unwrap();
+        assert_eq!(value["protocol"], "arp");
+        assert_eq!(value["mac"], "00:00:0c:01:02:03");
+        assert_eq!(value["vendor"], "Cisco");
+
+        let mut syn = record(None, Protocol::Tcp, "up");
+        syn.ping_port = Some(443);
+        assert_eq!(serde_json::to_value(&syn).unwrap()["ping_port"], 443);
+    }
+
+    #[test]
+    fn test_record_text() {
+        let r = record(Some(53), Protocol::Udp, "open|filtered");
+        assert_eq!(r.to_string(), "192.0.2.1 53/udp open|filtered");
+        let mut ping = record(None, Protocol::Tcp, "up");
+        ping.hostname = Some("host.example".to_string());
+        ping.ping_port = Some(443);
+        assert_eq!(
+            ping.to_string(),
+            "192.0.2.1 (host.example) up (syn ping to 443/tcp)"
+        );
+        let ping = record(None, Protocol::Icmp, "down");
+        assert_eq!(ping.to_string(), "192.0.2.1 down (icmp ping)");
+    }
+
+    #[test]
+    fn test_host_state() {
+        let up = record(None, Protocol::Icmp, "up");
+        let down = record(None, Protocol::Icmp, "down");
+        let open = record(Some(22), Protocol::Tcp, "open");
+        let closed = record(Some(23), Protocol::Tcp, "closed");
+        let unfiltered = record(Some(80), Protocol::Tcp, "unfiltered");
+        let filtered = record(Some(24), Protocol::Tcp, "filtered");
+        let open_filtered = record(Some(53), Protocol::Udp, "open|filtered");
+        assert_eq!(host_state(&[&up]), "up");
+        assert_eq!(host_state(&[&down]), "down");
+        // a port that answers shows the host is up
+        assert_eq!(host_state(&[&up, &filtered, &open]), "up");
+        assert_eq!(host_state(&[&filtered, &closed]), "up");
+        assert_eq!(host_state(&[&unfiltered]), "up");
+        // the discovery found the host down, its ports were not scanned
+        assert_eq!(host_state(&[&down, &filtered]), "down");
+        // nothing answered and no ping was sent
+        assert_eq!(host_state(&[&filtered, &open_filtered]), "unknown");
+        assert_eq!(host_state(&[]), "unknown");
+    }
+}
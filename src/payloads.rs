@@ -0,0 +1,246 @@
+use crate::output::Protocol;
+use crate::output::Record;
+use crate::timing::HostTimeouts;
+use crate::timing::Timing;
+use crate::utils;
+use anyhow::Result;
+use std::io;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::UdpSocket;
+use std::time::Duration;
+use std::time::Instant;
+
+/* Protocol payloads for UDP ports, most services ignore an empty datagram and look filtered */
+/// Echo, anything is sent back
+const ECHO: &[u8] = b"\r\n\r\n";
+/// DNS `version.bind` TXT query in the CHAOS class
+const DNS_VERSION: &[u8] =
+    b"\x00\x06\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07version\x04bind\x00\x00\x10\x00\x03";
+/// ONC RPC NULL call to the portmapper (program 100000, version 2)
+const RPC_NULL: &[u8] = b"\x72\xfe\x1d\x13\x00\x00\x00\x00\x00\x00\x00\x02\x00\x01\x86\xa0\
+\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+/// NTP version 4 client request
+const NTP_CLIENT: &[u8] = b"\xe3\x00\x04\xfa\x00\x01\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+/// NetBIOS name service node status request for `*`
+const NBSTAT: &[u8] = b"\x80\xf0\x00\x10\x00\x01\x00\x00\x00\x00\x00\x00\
+\x20CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\x00\x00\x21\x00\x01";
+/// SNMPv1 get-request of sysDescr.0 with the community `public`
+const SNMP_GET: &[u8] = b"\x30\x29\x02\x01\x00\x04\x06public\xa0\x1c\x02\x04\x71\x64\xfe\xf1\
+\x02\x01\x00\x02\x01\x00\x30\x0e\x30\x0c\x06\x08\x2b\x06\x01\x02\x01\x01\x01\x00\x05\x00";
+/// XDMCP query without authentication names
+const XDMCP_QUERY: &[u8] = b"\x00\x01\x00\x02\x00\x01\x00";
+/// SQL Server browser request for the instances
+const MSSQL_PING: &[u8] = b"\x02";
+/// SSDP search for every device and service
+const SSDP_SEARCH: &[u8] = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+MAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n\r\n";
+/// NAT-PMP external address request
+const NATPMP_ADDRESS: &[u8] = b"\x00\x00";
+/// mDNS query of the services under `_services._dns-sd._udp.local`
+const MDNS_SERVICES: &[u8] = b"\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+\x09_services\x07_dns-sd\x04_udp\x05local\x00\x00\x0c\x00\x01";
+
+const PAYLOADS: &[(u16, &[u8])] = &[
+    (7, ECHO),
+    (53, DNS_VERSION),
+    (111, RPC_NULL),
+    (123, NTP_CLIENT),
+    (137, NBSTAT),
+    (161, SNMP_GET),
+    (177, XDMCP_QUERY),
+    (1434, MSSQL_PING),
+    (1900, SSDP_SEARCH),
+    (5351, NATPMP_ADDRESS),
+    (5353, MDNS_SERVICES),
+];
+
+/// The payload the service on this UDP port answers to.
+pub fn payload(port: u16) -> Option<&'static [u8]> {
+    PAYLOADS
+        .iter()
+        .find(|(p, _)| *p == port)
+        .map(|(_, payload)| *payload)
+}
+
+/// Send the payload from `src` and wait for a response, the state is `open` on a response
+/// and `closed` when the port unreachable comes back.
+fn send_payload(
+    src: IpAddr,
+    addr: SocketAddr,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<Option<(&'static str, Duration)>> {
+    let socket = UdpSocket::bind(SocketAddr::new(src, 0))?;
+    // a connected socket gets the ICMP port unreachable as an error
+    socket.connect(addr)?;
+    socket.set_read_timeout(Some(timeout))?;
+    let start = Instant::now();
+    socket.send(payload)?;
+    let mut buff = [0u8; 1500];
+    match socket.recv(&mut buff) {
+        Ok(_) => Ok(Some(("open", start.elapsed()))),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            Ok(Some(("closed", start.elapsed())))
+        }
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Probe the `open|filtered` UDP ports that have a payload again, with the payload,
+/// so the ports whose service answers turn `open`.
+/// The payloads go through sockets of the system, bound to the source host or else to
+/// the address of the interface, the system still picks the interface by its routes.
+pub fn probe_payloads(
+    records: &mut [Record],
+    src_ipv4: Option<Ipv4Addr>,
+    src_ipv6: Option<Ipv6Addr>,
+    interface: Option<&str>,
+    timeouts: &HostTimeouts,
+    timing: Timing,
+) -> Result<()> {
+    probe_payloads_with(
+        records, payload, src_ipv4, src_ipv6, interface, timeouts, timing,
+    )
+}
+
+/// `probe_payloads` with the payloads looked up by `payload_for`.
+fn probe_payloads_with<F>(
+    records: &mut [Record],
+    payload_for: F,
+    src_ipv4: Option<Ipv4Addr>,
+    src_ipv6: Option<Ipv6Addr>,
+    interface: Option<&str>,
+    timeouts: &HostTimeouts,
+    timing: Timing,
+) -> Result<()>
+where
+    F: Fn(u16) -> Option<&'static [u8]>,
+{
+    let src_ipv4 = src_ipv4
+        .map(IpAddr::V4)
+        .or_else(|| interface.and_then(|name| utils::interface_source(name, false)))
+        .unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+    let src_ipv6 = src_ipv6
+        .map(IpAddr::V6)
+        .or_else(|| interface.and_then(|name| utils::interface_source(name, true)))
+        .unwrap_or(Ipv6Addr::UNSPECIFIED.into());
+    let jobs: Vec<(usize, SocketAddr, &'static [u8])> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.protocol == Protocol::Udp && r.state == "open|filtered")
+        .filter_map(|(i, r)| {
+            let port = r.port?;
+            Some((i, SocketAddr::new(r.target, port), payload_for(port)?))
+        })
+        .collect();
+    let timeouts = timeouts.clone();
+    let rets = utils::run_jobs(jobs, timing.threads, move |(i, addr, payload)| {
+        let ret = utils::retry(
+            timing.max_retries,
+            |r: &Option<(&'static str, Duration)>| r.is_none(),
+            || {
+                utils::pace();
+                let src = if addr.is_ipv4() { src_ipv4 } else { src_ipv6 };
+                let ret = send_payload(src, addr, payload, timeouts.timeout(addr.ip()))?;
+                if let Some((_, rtt)) = ret {
+                    timeouts.update(addr.ip(), rtt);
+                }
+                Ok(ret)
+            },
+        );
+        (i, addr, ret)
+    });
+    for (i, addr, ret) in rets {
+        match ret {
+            Ok(Some((state, rtt))) => {
+                records[i].state = state;
+                records[i].rtt_ms = Some(rtt.as_secs_f64() * 1000.0);
+            }
+            Ok(None) => (),
+            // the port keeps the state of the scan
+            Err(e) => eprintln!("udp payload to {} failed: {}", addr, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_payloads() {
+        assert_eq!(payload(53), Some(DNS_VERSION));
+        assert_eq!(payload(54), None);
+        assert_eq!(NTP_CLIENT.len(), 48);
+        assert_eq!(RPC_NULL.len(), 40);
+        assert_eq!(NBSTAT.len(), 50);
+        // the BER lengths of the SNMP message and its PDU
+        assert_eq!(SNMP_GET[1] as usize, SNMP_GET.len() - 2);
+        assert_eq!(SNMP_GET[14] as usize, SNMP_GET.len() - 15);
+    }
+
+    #[test]
+    fn test_probe_payloads() {
+        // the loopback network is all local, an echo server on one address and nothing on the other
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buff = [0u8; 1500];
+            while let Ok((n, from)) = server.recv_from(&mut buff) {
+                let _ = server.send_to(&buff[..n], from);
+            }
+        });
+        let open = "127.0.0.1".parse().unwrap();
+        let closed = "127.0.0.2".parse().unwrap();
+        let record = |target, port| {
+            Record::new(
+                target,
+                Some(port),
+                Protocol::Udp,
+                "udp",
+                "open|filtered",
+                None,
+            )
+        };
+        let mut records = vec![
+            record(open, port),
+            record(closed, port),
+            // no payload for the port
+            record(open, port.wrapping_add(1)),
+        ];
+        let payload_for = |p: u16| (p == port).then_some(MSSQL_PING);
+        let mut timing = Timing::template(4);
+        timing.timeout = Some(Duration::from_secs(1));
+        let timeouts = HostTimeouts::new(timing);
+        let local = Some(Ipv4Addr::LOCALHOST);
+        probe_payloads_with(
+            &mut records,
+            payload_for,
+            local,
+            None,
+            None,
+            &timeouts,
+            timing,
+        )
+        .unwrap();
+        assert_eq!(records[0].state, "open");
+        assert!(records[0].rtt_ms.is_some());
+        assert_eq!(records[1].state, "closed");
+        assert_eq!(records[2].state, "open|filtered");
+    }
+}
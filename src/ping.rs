@@ -1,205 +1,636 @@
-use crate::Args;
-use crate::AutoInferScanTypeError;
-use crate::NULL_VALUE;
+use crate::arp;
+use crate::icmp;
+use crate::output::arp_record;
+use crate::output::no_response;
+use crate::output::ping_record;
+use crate::output::with_hostname;
+use crate::output::Protocol;
+use crate::output::Record;
+use crate::ports;
+use crate::targets;
+use crate::targets::Target;
+use crate::timing;
+use crate::timing::HostTimeouts;
+use crate::timing::Timing;
+use crate::utils;
+use crate::validate;
+use crate::validate::Inputs;
+use crate::validate::Technique;
+use crate::ArpPingArgs;
+use crate::CombinedPingArgs;
+use crate::IcmpPingArgs;
+use crate::InvalidProbeError;
+use crate::PingArgs;
+use crate::PingMethods;
+use crate::SourceArgs;
+use crate::ValidationError;
 use anyhow::Result;
+use pistol::PingResults;
+use pnet_datalink::NetworkInterface;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
-use std::str::FromStr;
+use std::net::Ipv6Addr;
 use std::time::Duration;
-use subnetwork::Ipv4Pool;
 
-#[derive(PartialEq)]
-enum InferScanType {
-    Host,
-    Subnet,
-    Unknown,
+type HostFunc = fn(
+    Option<Ipv4Addr>,
+    Option<u16>,
+    Ipv4Addr,
+    Option<u16>,
+    Option<&str>,
+    bool,
+    Option<Duration>,
+    Option<usize>,
+) -> Result<PingResults>;
+
+type HostFunc6 = fn(
+    Option<Ipv6Addr>,
+    Option<u16>,
+    Ipv6Addr,
+    Option<u16>,
+    Option<&str>,
+    bool,
+    Option<Duration>,
+    Option<usize>,
+) -> Result<PingResults>;
+
+/// The ICMP queries that pistol does not have, IPv4 only.
+type QueryFunc = fn(Option<Ipv4Addr>, Ipv4Addr, Option<&str>, Duration) -> Result<PingResults>;
+
+/// How a ping is sent.
+#[derive(Debug, Clone, Copy)]
+enum Sender {
+    /// A pistol ping, `None` when it is IPv4 only
+    Pistol(HostFunc, Option<HostFunc6>),
+    /// One of our own ICMP queries, they find their source address without `-i`
+    Query(QueryFunc),
 }
 
 struct Parameters {
-    src_ipv4: Option<Ipv4Addr>,
+    src_addr: Option<IpAddr>,
     src_port: Option<u16>,
-    dst_ipv4: Option<Ipv4Addr>,
-    dst_port: Option<u16>,
-    start_port: Option<u16>,
-    end_port: Option<u16>,
-    subnet: Option<Ipv4Pool>,
     interface: Option<String>,
-    inter_scan_type: InferScanType,
-}
-
-impl Parameters {
-    fn new_empty() -> Parameters {
-        Parameters {
-            src_ipv4: None,
-            src_port: None,
-            dst_ipv4: None,
-            dst_port: None,
-            start_port: None,
-            end_port: None,
-            subnet: None,
-            interface: None,
-            inter_scan_type: InferScanType::Unknown,
+    send_ip: bool,
+    timing: Timing,
+}
+
+/// A host discovery probe, named after the nmap `-P` options.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Probe {
+    /// No host discovery, every target is scanned (`-Pn`)
+    Skip,
+    /// ICMP echo (`-PE`)
+    Icmp,
+    /// ICMP timestamp, IPv4 only (`-PP`)
+    Timestamp,
+    /// ICMP address mask, IPv4 only (`-PM`)
+    AddressMask,
+    /// TCP SYN to every port (`-PS`)
+    Syn(BTreeSet<u16>),
+    /// TCP ACK to every port (`-PA`)
+    Ack(BTreeSet<u16>),
+    /// UDP to every port (`-PU`)
+    Udp(BTreeSet<u16>),
+    /// ARP, directly attached networks only (`-PR`)
+    Arp,
+}
+
+/// One packet of a probe, to a single port.
+#[derive(Debug, Clone, Copy)]
+struct Ping {
+    protocol: Protocol,
+    technique: &'static str,
+    sender: Sender,
+    dst_port: Option<u16>,
+}
+
+impl Ping {
+    fn ipv6(&self) -> bool {
+        matches!(self.sender, Sender::Pistol(_, Some(_)))
+    }
+}
+
+const PRINT_RESULT: bool = false;
+/// The nmap ports of `-PS`, `-PA` and `-PU` without ports
+const SYN_PING_PORT: u16 = 80;
+const ACK_PING_PORT: u16 = 80;
+const UDP_PING_PORT: u16 = 40125;
+
+/// Parse a `-P` option without the `P`, like `E`, `S22,443`, `U53` or `n`.
+/// TCP and UDP probes take an nmap style port list.
+pub fn parse_probe(spec: &str) -> Result<Probe> {
+    let split = spec.chars().next().map_or(0, |c| c.len_utf8());
+    let (kind, port_spec) = spec.split_at(split);
+    let port_set = |default: u16, udp: bool| -> Result<BTreeSet<u16>> {
+        if port_spec.is_empty() {
+            return Ok(BTreeSet::from([default]));
         }
+        let port_set = ports::parse_port_spec(port_spec)?;
+        let dst_ports = if udp { port_set.udp } else { port_set.tcp };
+        if dst_ports.is_empty() {
+            return Err(InvalidProbeError::new(spec.to_string()).into());
+        }
+        Ok(dst_ports)
+    };
+    match kind {
+        "n" if port_spec.is_empty() => Ok(Probe::Skip),
+        "E" if port_spec.is_empty() => Ok(Probe::Icmp),
+        "P" if port_spec.is_empty() => Ok(Probe::Timestamp),
+        "M" if port_spec.is_empty() => Ok(Probe::AddressMask),
+        "R" if port_spec.is_empty() => Ok(Probe::Arp),
+        "S" => Ok(Probe::Syn(port_set(SYN_PING_PORT, false)?)),
+        "A" => Ok(Probe::Ack(port_set(ACK_PING_PORT, false)?)),
+        "U" => Ok(Probe::Udp(port_set(UDP_PING_PORT, true)?)),
+        _ => Err(InvalidProbeError::new(spec.to_string()).into()),
     }
 }
 
-pub fn start_ping(args: Args) -> Result<()> {
-    let mut parameters = Parameters::new_empty();
+/// The probes that were given, or the nmap default `-PE -PS443 -PA80 -PP`.
+pub fn probes_or_default(probes: &[Probe]) -> Vec<Probe> {
+    if !probes.is_empty() {
+        return probes.to_vec();
+    }
+    vec![
+        Probe::Icmp,
+        Probe::Syn(BTreeSet::from([443])),
+        Probe::Ack(BTreeSet::from([80])),
+        Probe::Timestamp,
+    ]
+}
 
-    let src_ipv4 = if args.source_host != NULL_VALUE {
-        Some(Ipv4Addr::from_str(&args.source_host)?)
-    } else {
-        None
+/// Every packet the probes send to a host, in the order they were given.
+fn pings(probes: &[Probe]) -> Vec<Ping> {
+    let mut pings = Vec::new();
+    for probe in probes {
+        let (protocol, technique, sender, dst_ports) = match probe {
+            // ARP is sent to directly attached hosts anyway
+            Probe::Skip | Probe::Arp => continue,
+            Probe::Icmp => (
+                Protocol::Icmp,
+                "icmp",
+                Sender::Pistol(pistol::icmp_ping_host, Some(pistol::icmp_ping_host6)),
+                vec![None],
+            ),
+            Probe::Timestamp => (
+                Protocol::Icmp,
+                "timestamp",
+                Sender::Query(icmp::timestamp_ping_host),
+                vec![None],
+            ),
+            Probe::AddressMask => (
+                Protocol::Icmp,
+                "mask",
+                Sender::Query(icmp::address_mask_ping_host),
+                vec![None],
+            ),
+            Probe::Syn(dst_ports) => (
+                Protocol::Tcp,
+                "syn",
+                Sender::Pistol(pistol::tcp_syn_ping_host, Some(pistol::tcp_syn_ping_host6)),
+                dst_ports.iter().map(|&p| Some(p)).collect(),
+            ),
+            Probe::Ack(dst_ports) => (
+                Protocol::Tcp,
+                "ack",
+                Sender::Pistol(pistol::tcp_ack_ping_host, Some(pistol::tcp_ack_ping_host6)),
+                dst_ports.iter().map(|&p| Some(p)).collect(),
+            ),
+            Probe::Udp(dst_ports) => (
+                Protocol::Udp,
+                "udp",
+                Sender::Pistol(pistol::udp_ping_host, Some(pistol::udp_ping_host6)),
+                dst_ports.iter().map(|&p| Some(p)).collect(),
+            ),
+        };
+        for dst_port in dst_ports {
+            pings.push(Ping {
+                protocol,
+                technique,
+                sender,
+                dst_port,
+            });
+        }
+    }
+    pings
+}
+
+pub fn start_ping(method: PingMethods, timing: Timing) -> Result<Vec<Record>> {
+    match method {
+        PingMethods::Syn(args) => ping(
+            args,
+            timing,
+            Protocol::Tcp,
+            "syn",
+            pistol::tcp_syn_ping_host,
+            pistol::tcp_syn_ping_host6,
+        ),
+        PingMethods::Ack(args) => ping(
+            args,
+            timing,
+            Protocol::Tcp,
+            "ack",
+            pistol::tcp_ack_ping_host,
+            pistol::tcp_ack_ping_host6,
+        ),
+        PingMethods::Udp(args) => ping(
+            args,
+            timing,
+            Protocol::Udp,
+            "udp",
+            pistol::udp_ping_host,
+            pistol::udp_ping_host6,
+        ),
+        PingMethods::Icmp(args) => icmp_ping(args, timing, Probe::Icmp),
+        PingMethods::Timestamp(args) => icmp_ping(args, timing, Probe::Timestamp),
+        PingMethods::Mask(args) => icmp_ping(args, timing, Probe::AddressMask),
+        PingMethods::Arp(args) => arp_ping(args, timing),
+        PingMethods::Combined(args) => combined_ping(args, timing),
+    }
+}
+
+fn ping(
+    args: PingArgs,
+    timing: Timing,
+    protocol: Protocol,
+    technique: &'static str,
+    host_func: HostFunc,
+    host_func6: HostFunc6,
+) -> Result<Vec<Record>> {
+    let parameters = Parameters {
+        src_addr: args.source.source_host,
+        src_port: args.source_port,
+        interface: args.source.interface,
+        send_ip: args.send_ip,
+        timing,
+    };
+    let ping = Ping {
+        protocol,
+        technique,
+        sender: Sender::Pistol(host_func, Some(host_func6)),
+        dst_port: args.port,
     };
-    parameters.src_ipv4 = src_ipv4;
+    let targets = targets::parse_targets(&args.target)?;
+    run(targets, parameters, vec![ping], HostTimeouts::new(timing))
+}
 
-    let src_port = if args.source_port != 0 {
-        Some(args.source_port)
-    } else {
-        None
+fn icmp_ping(args: IcmpPingArgs, timing: Timing, probe: Probe) -> Result<Vec<Record>> {
+    let parameters = Parameters {
+        src_addr: args.source.source_host,
+        src_port: None,
+        interface: args.source.interface,
+        send_ip: args.send_ip,
+        timing,
     };
-    parameters.src_port = src_port;
+    let targets = targets::parse_targets(&args.target)?;
+    let pings = pings(&[probe]);
+    run(targets, parameters, pings, HostTimeouts::new(timing))
+}
 
-    if args.host != NULL_VALUE {
-        let dst_ipv4 = Ipv4Addr::from_str(&args.host)?;
-        parameters.dst_ipv4 = Some(dst_ipv4);
-    }
+fn combined_ping(args: CombinedPingArgs, timing: Timing) -> Result<Vec<Record>> {
+    let parameters = Parameters {
+        src_addr: args.source.source_host,
+        src_port: args.source_port,
+        interface: args.source.interface,
+        send_ip: args.send_ip,
+        timing,
+    };
+    let targets = targets::parse_targets(&args.target)?;
+    let probes = probes_or_default(&args.discovery.probes);
+    run_probes(targets, parameters, &probes, HostTimeouts::new(timing))
+}
+
+/// Ping the targets of a scan, the round trip times go into the timeouts of the scan.
+pub fn discover(
+    targets: Vec<Target>,
+    probes: &[Probe],
+    source: &SourceArgs,
+    timing: Timing,
+    timeouts: &HostTimeouts,
+) -> Result<Vec<Record>> {
+    let parameters = Parameters {
+        src_addr: source.source_host,
+        src_port: None,
+        interface: source.interface.clone(),
+        send_ip: false,
+        timing,
+    };
+    run_probes(targets, parameters, probes, timeouts.clone())
+}
 
-    if args.subnet != NULL_VALUE {
-        let subnet = Ipv4Pool::new(&args.subnet)?;
-        parameters.subnet = Some(subnet);
-        parameters.inter_scan_type = InferScanType::Subnet;
+fn run_probes(
+    targets: Vec<Target>,
+    parameters: Parameters,
+    probes: &[Probe],
+    timeouts: HostTimeouts,
+) -> Result<Vec<Record>> {
+    let pings = pings(probes);
+    if pings.is_empty() {
+        if !probes.contains(&Probe::Arp) {
+            return Err(ValidationError::NoProbes.into());
+        }
+        // only ARP, so every target has to be directly attached
+        let interface = parameters.interface.as_deref();
+        let inputs = Inputs {
+            targets: &targets,
+            interface,
+            ..Default::default()
+        };
+        validate::validate(Technique::ArpPing, &inputs)?;
+        return arp_ping_targets(targets, interface, parameters.timing, &timeouts);
     }
+    run(targets, parameters, pings, timeouts)
+}
 
-    if args.port != NULL_VALUE {
-        let dst_port: u16 = args.port.parse()?;
-        parameters.dst_port = Some(dst_port);
-        if parameters.inter_scan_type == InferScanType::Subnet {
-            parameters.start_port = Some(dst_port);
-            parameters.end_port = Some(dst_port);
-        } else {
-            parameters.inter_scan_type = InferScanType::Host;
+fn run(
+    targets: Vec<Target>,
+    parameters: Parameters,
+    pings: Vec<Ping>,
+    timeouts: HostTimeouts,
+) -> Result<Vec<Record>> {
+    let interface = parameters.interface;
+    // like nmap, hosts on a directly attached network are pinged with ARP
+    let mut jobs = Vec::new();
+    let mut ip_targets = Vec::new();
+    for target in targets {
+        let local_interface = match (target.addr, parameters.send_ip) {
+            (IpAddr::V4(dst_ipv4), false) => arp::local_interface(dst_ipv4, interface.as_deref()),
+            _ => None,
+        };
+        match local_interface {
+            Some(local_interface) => jobs.push((target, Some(local_interface))),
+            None => ip_targets.push(target),
         }
-    } else {
-        parameters.inter_scan_type = InferScanType::Host;
-        parameters.dst_port = None;
+    }
+    let inputs = Inputs {
+        targets: &ip_targets,
+        interface: interface.as_deref(),
+        source_host: parameters.src_addr,
+        ..Default::default()
     };
-
-    let interface = if args.interface != NULL_VALUE {
-        Some(args.interface)
+    let technique = if pings.iter().any(|p| matches!(p.sender, Sender::Pistol(..))) {
+        Technique::Ping
     } else {
-        None
+        Technique::IcmpQuery
     };
-    parameters.interface = interface;
-
-    let print_result = false;
-    let timeout = Some(Duration::from_secs_f32(0.1));
-    let max_loop = Some(64);
-    let threads_num = 0; // auto detect
-
-    match parameters.inter_scan_type {
-        InferScanType::Unknown => return Err(AutoInferScanTypeError::new().into()),
-        InferScanType::Host => {
-            let func = if args.syn {
-                pistol::tcp_syn_ping_host
-            } else if args.ack {
-                pistol::tcp_ack_ping_host
-            } else if args.udp {
-                let ret = pistol::udp_ping_host(
-                    src_ipv4,
-                    src_port,
-                    parameters.dst_ipv4.unwrap(),
-                    parameters.dst_port,
-                    parameters.interface.as_deref(),
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                println!("{}", ret);
-                return Ok(());
-            } else if args.icmp {
-                let ret = pistol::icmp_ping_host(
-                    src_ipv4,
-                    src_port,
-                    parameters.dst_ipv4.unwrap(),
-                    parameters.dst_port,
-                    parameters.interface.as_deref(),
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                println!("{}", ret);
-                return Ok(());
-            } else {
-                pistol::tcp_syn_ping_host
-            };
-            let ret = func(
-                src_ipv4,
-                src_port,
-                parameters.dst_ipv4.unwrap(),
-                parameters.dst_port,
-                parameters.interface.as_deref(),
-                print_result,
-                timeout,
-                max_loop,
-            )?;
-            println!("{}", ret);
+    validate::validate(technique, &inputs)?;
+    jobs.extend(ip_targets.into_iter().map(|target| (target, None)));
+
+    let (src_ipv4, src_ipv6) = targets::source_addrs(parameters.src_addr, interface.as_deref());
+    let src_port = parameters.src_port;
+    let timing = parameters.timing;
+    let rets = utils::run_jobs(jobs, timing.threads, move |(target, local_interface)| {
+        if let (IpAddr::V4(dst_ipv4), Some((i, src_ipv4))) = (target.addr, local_interface) {
+            let records = arp_ping_host(dst_ipv4, src_ipv4, &i, timing, &timeouts)?;
+            return Ok(with_hostname(records, &target.hostname));
         }
-        InferScanType::Subnet => {
-            let func = if args.syn {
-                pistol::tcp_syn_ping_subnet
-            } else if args.ack {
-                pistol::tcp_ack_ping_subnet
-            } else if args.udp {
-                let ret = pistol::udp_ping_subnet(
-                    src_ipv4,
-                    src_port,
-                    parameters.dst_port,
-                    parameters.subnet.unwrap(),
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                for k in ret.keys() {
-                    println!("{}", ret.get(k).unwrap());
+        let mut records = Vec::new();
+        let mut errors = Vec::new();
+        // the host is up as soon as one of the pings gets a response
+        for ping in &pings {
+            // the IPv4 only pings are left out for IPv6 hosts
+            if target.addr.is_ipv6() && !ping.ipv6() {
+                continue;
+            }
+            let send = |timeout| {
+                // the ICMP pings read one socket, the others read ICMP errors as well
+                let reads = if ping.protocol == Protocol::Icmp {
+                    1
+                } else {
+                    2
+                };
+                let (read_timeout, max_loop) = timing::pistol_wait(timeout, reads);
+                match (target.addr, ping.sender) {
+                    (IpAddr::V4(dst_ipv4), Sender::Pistol(host_func, _)) => host_func(
+                        src_ipv4,
+                        src_port,
+                        dst_ipv4,
+                        ping.dst_port,
+                        interface.as_deref(),
+                        PRINT_RESULT,
+                        Some(read_timeout),
+                        Some(max_loop),
+                    ),
+                    (IpAddr::V4(dst_ipv4), Sender::Query(query_func)) => {
+                        query_func(src_ipv4, dst_ipv4, interface.as_deref(), timeout)
+                    }
+                    (IpAddr::V6(dst_ipv6), Sender::Pistol(_, Some(host_func6))) => host_func6(
+                        src_ipv6.or_else(|| utils::route_source6(dst_ipv6)),
+                        src_port,
+                        dst_ipv6,
+                        ping.dst_port,
+                        interface.as_deref(),
+                        PRINT_RESULT,
+                        Some(read_timeout),
+                        Some(max_loop),
+                    ),
+                    (IpAddr::V6(_), _) => Err(ValidationError::Ipv6NotSupported {
+                        method: format!("{} ping", ping.technique),
+                    }
+                    .into()),
                 }
-                return Ok(());
-            } else if args.icmp {
-                let ret = pistol::icmp_ping_subnet(
-                    src_ipv4,
-                    src_port,
-                    parameters.dst_port,
-                    parameters.subnet.unwrap(),
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                for k in ret.keys() {
-                    println!("{}", ret.get(k).unwrap());
+            };
+            let ret = utils::probe(target.addr, timing, &timeouts, send, |ret, rtt| {
+                vec![ping_record(
+                    ret,
+                    ping.protocol,
+                    ping.technique,
+                    ping.dst_port,
+                    Some(rtt),
+                )]
+            });
+            // a failing ping leaves the others to decide
+            match ret {
+                Ok(r) => {
+                    let up = !no_response(&r);
+                    records.extend(r);
+                    if up {
+                        break;
+                    }
                 }
-                return Ok(());
-            } else {
-                pistol::tcp_syn_ping_subnet
+                Err(e) => errors.push((ping.technique, e)),
+            }
+        }
+        let Some(record) = reduce(records) else {
+            return match errors.is_empty() {
+                true => Ok(Vec::new()),
+                false => Err(errors.remove(0).1),
             };
-            let ret = func(
-                src_ipv4,
-                src_port,
-                parameters.dst_port,
-                parameters.subnet.unwrap(),
-                parameters.interface.as_deref(),
-                threads_num,
-                print_result,
-                timeout,
-                max_loop,
-            )?;
-            for k in ret.keys() {
-                println!("{}", ret.get(k).unwrap());
+        };
+        for (technique, e) in errors {
+            eprintln!("{} ping of {} failed: {}", technique, target.addr, e);
+        }
+        Ok(with_hostname(vec![record], &target.hostname))
+    });
+    Ok(utils::collect_jobs(rets)?.concat())
+}
+
+/// The record of a host from the records of its pings, up wins over down.
+/// The up record is the one of the ping that got the response, a down host keeps the last ping.
+fn reduce(records: Vec<Record>) -> Option<Record> {
+    let mut down = None;
+    for r in records {
+        if r.state == "up" {
+            return Some(r);
+        }
+        down = Some(r);
+    }
+    down
+}
+
+fn arp_ping_host(
+    dst_ipv4: Ipv4Addr,
+    src_ipv4: Ipv4Addr,
+    interface: &NetworkInterface,
+    timing: Timing,
+    timeouts: &HostTimeouts,
+) -> Result<Vec<Record>> {
+    let send = |timeout| arp::arp_ping(dst_ipv4, src_ipv4, interface, timeout);
+    utils::probe(dst_ipv4.into(), timing, timeouts, send, |mac, rtt| {
+        vec![arp_record(dst_ipv4, *mac, Some(rtt))]
+    })
+}
+
+fn arp_ping(args: ArpPingArgs, timing: Timing) -> Result<Vec<Record>> {
+    let targets = targets::parse_targets(&args.target)?;
+    let inputs = Inputs {
+        targets: &targets,
+        interface: args.interface.as_deref(),
+        ..Default::default()
+    };
+    validate::validate(Technique::ArpPing, &inputs)?;
+    let timeouts = HostTimeouts::new(timing);
+    arp_ping_targets(targets, args.interface.as_deref(), timing, &timeouts)
+}
+
+/// ARP ping every target, all of them have to be on a directly attached network.
+pub fn arp_ping_targets(
+    targets: Vec<Target>,
+    interface: Option<&str>,
+    timing: Timing,
+    timeouts: &HostTimeouts,
+) -> Result<Vec<Record>> {
+    let mut jobs = Vec::new();
+    for target in targets {
+        // callers validate the targets, ARP is IPv4 only
+        let IpAddr::V4(dst_ipv4) = target.addr else {
+            continue;
+        };
+        match arp::local_interface(dst_ipv4, interface) {
+            Some((i, src_ipv4)) => jobs.push((dst_ipv4, src_ipv4, i, target.hostname)),
+            None => {
+                return Err(ValidationError::NotDirectlyAttached {
+                    target: dst_ipv4.to_string(),
+                }
+                .into())
             }
         }
     }
+    let timeouts = timeouts.clone();
+    let rets = utils::run_jobs(
+        jobs,
+        timing.threads,
+        move |(dst_ipv4, src_ipv4, i, hostname)| {
+            let records = arp_ping_host(dst_ipv4, src_ipv4, &i, timing, &timeouts)?;
+            Ok(with_hostname(records, &hostname))
+        },
+    );
+    Ok(utils::collect_jobs(rets)?.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(ports: &[u16]) -> BTreeSet<u16> {
+        ports.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_parse_probe() {
+        assert_eq!(parse_probe("n").unwrap(), Probe::Skip);
+        assert_eq!(parse_probe("E").unwrap(), Probe::Icmp);
+        assert_eq!(parse_probe("P").unwrap(), Probe::Timestamp);
+        assert_eq!(parse_probe("M").unwrap(), Probe::AddressMask);
+        assert_eq!(parse_probe("R").unwrap(), Probe::Arp);
+        // the nmap default ports
+        assert_eq!(parse_probe("S").unwrap(), Probe::Syn(ports(&[80])));
+        assert_eq!(parse_probe("A").unwrap(), Probe::Ack(ports(&[80])));
+        assert_eq!(parse_probe("U").unwrap(), Probe::Udp(ports(&[40125])));
+        assert_eq!(
+            parse_probe("S22,443").unwrap(),
+            Probe::Syn(ports(&[22, 443]))
+        );
+        assert_eq!(
+            parse_probe("A80-82").unwrap(),
+            Probe::Ack(ports(&[80, 81, 82]))
+        );
+        assert_eq!(parse_probe("U53").unwrap(), Probe::Udp(ports(&[53])));
+        assert_eq!(parse_probe("UU:53,T:80").unwrap(), Probe::Udp(ports(&[53])));
+        // no ports of the protocol, ports on a probe without any, bad ports
+        for spec in [
+            "", "X", "E80", "n1", "ST:", "SU:53", "UT:80", "S0-", "S70000", "Sx",
+        ] {
+            assert!(parse_probe(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_probes_or_default() {
+        assert_eq!(
+            probes_or_default(&[]),
+            [
+                Probe::Icmp,
+                Probe::Syn(ports(&[443])),
+                Probe::Ack(ports(&[80])),
+                Probe::Timestamp
+            ]
+        );
+        let given = [Probe::Udp(ports(&[53])), Probe::Arp];
+        assert_eq!(probes_or_default(&given), given);
+    }
 
-    Ok(())
+    #[test]
+    fn test_pings() {
+        let probes = [
+            Probe::Icmp,
+            Probe::Syn(ports(&[22, 443])),
+            Probe::Arp,
+            Probe::Udp(ports(&[53])),
+        ];
+        let pings: Vec<(&str, Option<u16>)> = pings(&probes)
+            .iter()
+            .map(|p| (p.technique, p.dst_port))
+            .collect();
+        // ARP is sent on its own
+        assert_eq!(
+            pings,
+            [
+                ("icmp", None),
+                ("syn", Some(22)),
+                ("syn", Some(443)),
+                ("udp", Some(53))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reduce() {
+        let addr = "192.0.2.1".parse().unwrap();
+        let ping =
+            |technique, state| Record::new(addr, None, Protocol::Tcp, technique, state, None);
+        let mut syn = ping("syn", "up");
+        syn.ping_port = Some(443);
+        let records = vec![ping("icmp", "down"), syn, ping("ack", "up")];
+        let up = reduce(records).unwrap();
+        assert_eq!(
+            (up.technique, up.state, up.ping_port),
+            ("syn", "up", Some(443))
+        );
+
+        let records = vec![ping("icmp", "down"), ping("timestamp", "down")];
+        let down = reduce(records).unwrap();
+        assert_eq!((down.technique, down.state), ("timestamp", "down"));
+        assert!(reduce(Vec::new()).is_none());
+    }
 }
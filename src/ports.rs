@@ -0,0 +1,385 @@
+use crate::InvalidPortError;
+use crate::InvalidProtocolError;
+use crate::SplitPortError;
+use crate::TooManyTopPortsError;
+use crate::UnknownServiceError;
+use anyhow::Result;
+use std::collections::BTreeSet;
+
+/// Well known services, (name, port, tcp, udp).
+const SERVICES: &[(&str, u16, bool, bool)] = &[
+    ("echo", 7, true, true),
+    ("discard", 9, true, true),
+    ("daytime", 13, true, true),
+    ("ftp-data", 20, true, false),
+    ("ftp", 21, true, false),
+    ("ssh", 22, true, false),
+    ("telnet", 23, true, false),
+    ("smtp", 25, true, false),
+    ("time", 37, true, true),
+    ("domain", 53, true, true),
+    ("dhcps", 67, false, true),
+    ("dhcpc", 68, false, true),
+    ("tftp", 69, false, true),
+    ("finger", 79, true, false),
+    ("http", 80, true, true),
+    ("kerberos-sec", 88, true, true),
+    ("pop3", 110, true, false),
+    ("rpcbind", 111, true, true),
+    ("ident", 113, true, false),
+    ("nntp", 119, true, false),
+    ("ntp", 123, false, true),
+    ("msrpc", 135, true, true),
+    ("netbios-ns", 137, false, true),
+    ("netbios-dgm", 138, false, true),
+    ("netbios-ssn", 139, true, true),
+    ("imap", 143, true, false),
+    ("snmp", 161, false, true),
+    ("snmptrap", 162, false, true),
+    ("bgp", 179, true, false),
+    ("ldap", 389, true, true),
+    ("https", 443, true, true),
+    ("microsoft-ds", 445, true, true),
+    ("isakmp", 500, false, true),
+    ("login", 513, true, false),
+    ("shell", 514, true, false),
+    ("syslog", 514, false, true),
+    ("printer", 515, true, false),
+    ("route", 520, false, true),
+    ("submission", 587, true, false),
+    ("ipp", 631, true, true),
+    ("ldaps", 636, true, false),
+    ("rsync", 873, true, false),
+    ("ftps", 990, true, false),
+    ("imaps", 993, true, false),
+    ("pop3s", 995, true, false),
+    ("socks", 1080, true, false),
+    ("openvpn", 1194, true, true),
+    ("ms-sql-s", 1433, true, false),
+    ("ms-sql-m", 1434, false, true),
+    ("oracle", 1521, true, false),
+    ("l2tp", 1701, false, true),
+    ("pptp", 1723, true, false),
+    ("radius", 1812, false, true),
+    ("upnp", 1900, false, true),
+    ("nfs", 2049, true, true),
+    ("docker", 2375, true, false),
+    ("mysql", 3306, true, false),
+    ("ms-wbt-server", 3389, true, false),
+    ("nat-t-ike", 4500, false, true),
+    ("sip", 5060, true, true),
+    ("mdns", 5353, false, true),
+    ("postgresql", 5432, true, false),
+    ("vnc", 5900, true, false),
+    ("x11", 6000, true, false),
+    ("redis", 6379, true, false),
+    ("http-proxy", 8080, true, false),
+    ("https-alt", 8443, true, false),
+    ("jetdirect", 9100, true, false),
+    ("elasticsearch", 9200, true, false),
+    ("memcache", 11211, true, true),
+    ("mongodb", 27017, true, false),
+];
+
+/// Well known IP protocols, (name, number).
+const IP_PROTOCOLS: &[(&str, u8)] = &[
+    ("hopopt", 0),
+    ("icmp", 1),
+    ("igmp", 2),
+    ("ipv4", 4),
+    ("tcp", 6),
+    ("egp", 8),
+    ("igp", 9),
+    ("udp", 17),
+    ("ipv6", 41),
+    ("ipv6-route", 43),
+    ("ipv6-frag", 44),
+    ("rsvp", 46),
+    ("gre", 47),
+    ("esp", 50),
+    ("ah", 51),
+    ("ipv6-icmp", 58),
+    ("ipv6-nonxt", 59),
+    ("ipv6-opts", 60),
+    ("eigrp", 88),
+    ("ospfigp", 89),
+    ("pim", 103),
+    ("vrrp", 112),
+    ("l2tp", 115),
+    ("sctp", 132),
+    ("udplite", 136),
+];
+
+/// The most frequently open TCP ports, most common first (nmap-services order).
+const TOP_TCP_PORTS: &[u16] = &[
+    80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080, 1723, 111, 995, 993,
+    5900, 1025, 587, 8888, 199, 1720, 465, 548, 113, 81, 6001, 10000, 514, 5060, 179, 1026, 2000,
+    8443, 8000, 32768, 554, 26, 1433, 49152, 2001, 515, 8008, 49154, 1027, 5666, 646, 5000, 5631,
+    631, 49153, 8081, 2049, 88, 79, 5800, 106, 2121, 1110, 49155, 6000, 513, 990, 5357, 427, 49156,
+    543, 544, 5101, 144, 7, 389, 8009, 3128, 444, 9999, 5009, 7070, 5190, 3000, 5432, 1900, 3986,
+    13, 1029, 9, 5051, 6646, 49157, 1028, 873, 1755, 2717, 4899, 9100, 119, 37,
+];
+
+/// The most frequently open UDP ports, most common first (nmap-services order).
+const TOP_UDP_PORTS: &[u16] = &[
+    631, 161, 137, 123, 138, 1434, 445, 135, 67, 53, 139, 500, 68, 520, 1900, 4500, 514, 49152,
+    162, 69, 5353, 111, 49154, 1701, 998, 996, 997, 999, 3283, 49153, 1812, 136, 2222, 2049, 3278,
+    5060, 1025, 1433, 3456, 80, 20031, 1026, 7, 1646, 1645, 593, 518, 2048, 626, 1027,
+];
+
+/// Deduplicated ports to scan, per protocol.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortSet {
+    pub tcp: BTreeSet<u16>,
+    pub udp: BTreeSet<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProtocolPrefix {
+    Both,
+    Tcp,
+    Udp,
+}
+
+impl PortSet {
+    fn insert(&mut self, prefix: ProtocolPrefix, start_port: u16, end_port: u16) {
+        for port in start_port..=end_port {
+            if prefix != ProtocolPrefix::Udp {
+                self.tcp.insert(port);
+            }
+            if prefix != ProtocolPrefix::Tcp {
+                self.udp.insert(port);
+            }
+        }
+    }
+}
+
+/// The `n` most common TCP or UDP ports, more than the table knows is an error.
+pub fn top_ports(n: usize, udp: bool) -> Result<BTreeSet<u16>> {
+    let (table, protocol) = match udp {
+        true => (TOP_UDP_PORTS, "UDP"),
+        false => (TOP_TCP_PORTS, "TCP"),
+    };
+    if n > table.len() {
+        return Err(TooManyTopPortsError::new(n, table.len(), protocol).into());
+    }
+    Ok(table.iter().take(n).copied().collect())
+}
+
+/// Look up a port of a well known service by its name.
+pub fn service_port(name: &str, tcp: bool) -> Option<u16> {
+    SERVICES
+        .iter()
+        .find(|(n, _, t, u)| *n == name && if tcp { *t } else { *u })
+        .map(|(_, port, _, _)| *port)
+}
+
+/// Look up the name of the well known service on a port.
+pub fn service_name(port: u16, tcp: bool) -> Option<&'static str> {
+    SERVICES
+        .iter()
+        .find(|(_, p, t, u)| *p == port && if tcp { *t } else { *u })
+        .map(|(name, _, _, _)| *name)
+}
+
+/// Look up the name of an IP protocol by its number.
+pub fn protocol_name(number: u8) -> Option<&'static str> {
+    IP_PROTOCOLS
+        .iter()
+        .find(|(_, n)| *n == number)
+        .map(|(name, _)| *name)
+}
+
+fn parse_protocol(protocol: &str, item: &str) -> Result<u8> {
+    match protocol.parse() {
+        Ok(p) => Ok(p),
+        Err(_) => Err(InvalidProtocolError::new(item.to_string()).into()),
+    }
+}
+
+/// Parse an IP protocol specification like the ports one, like `1,6,17`, `0-50`, `100-` or `icmp,tcp`.
+pub fn parse_protocol_spec(spec: &str) -> Result<BTreeSet<u8>> {
+    let mut set = BTreeSet::new();
+    for item in spec.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            return Err(InvalidProtocolError::new(item.to_string()).into());
+        } else if item.chars().all(|c| c.is_ascii_digit()) {
+            set.insert(parse_protocol(item, item)?);
+        } else if let Some((start, end)) = item
+            .split_once('-')
+            .filter(|_| item.chars().all(|c| c.is_ascii_digit() || c == '-'))
+        {
+            let start = if start.is_empty() {
+                0
+            } else {
+                parse_protocol(start, item)?
+            };
+            let end = if end.is_empty() {
+                u8::MAX
+            } else {
+                parse_protocol(end, item)?
+            };
+            if start > end {
+                return Err(InvalidProtocolError::new(item.to_string()).into());
+            }
+            set.extend(start..=end);
+        } else {
+            match IP_PROTOCOLS.iter().find(|(name, _)| *name == item) {
+                Some((_, number)) => set.insert(*number),
+                None => return Err(InvalidProtocolError::new(item.to_string()).into()),
+            };
+        }
+    }
+    Ok(set)
+}
+
+fn parse_port(port: &str, item: &str) -> Result<u16> {
+    match port.parse() {
+        // port 0 can not be scanned
+        Ok(p) if p > 0 => Ok(p),
+        _ => Err(InvalidPortError::new(item.to_string()).into()),
+    }
+}
+
+/// Parse an nmap style port specification, like `22,80-85,-10,60000-,T:443,U:53,http`.
+/// A `T:` or `U:` prefix applies to the items after it until the next prefix,
+/// ports without a prefix are scanned with both protocols.
+pub fn parse_port_spec(spec: &str) -> Result<PortSet> {
+    let mut set = PortSet::default();
+    let mut prefix = ProtocolPrefix::Both;
+    for item in spec.split(',') {
+        let item = item.trim();
+        let value = if let Some(v) = item.strip_prefix("T:") {
+            prefix = ProtocolPrefix::Tcp;
+            v
+        } else if let Some(v) = item.strip_prefix("U:") {
+            prefix = ProtocolPrefix::Udp;
+            v
+        } else {
+            item
+        };
+
+        if value.is_empty() {
+            return Err(InvalidPortError::new(item.to_string()).into());
+        } else if value.chars().all(|c| c.is_ascii_digit()) {
+            let port = parse_port(value, item)?;
+            set.insert(prefix, port, port);
+        } else if let Some((start, end)) = value
+            .split_once('-')
+            .filter(|_| value.chars().all(|c| c.is_ascii_digit() || c == '-'))
+        {
+            let start_port = if start.is_empty() {
+                1
+            } else {
+                parse_port(start, item)?
+            };
+            let end_port = if end.is_empty() {
+                u16::MAX
+            } else {
+                parse_port(end, item)?
+            };
+            if start_port > end_port {
+                return Err(SplitPortError::new(item.to_string()).into());
+            }
+            set.insert(prefix, start_port, end_port);
+        } else {
+            let tcp_port = service_port(value, true).filter(|_| prefix != ProtocolPrefix::Udp);
+            let udp_port = service_port(value, false).filter(|_| prefix != ProtocolPrefix::Tcp);
+            if tcp_port.is_none() && udp_port.is_none() {
+                return Err(UnknownServiceError::new(value.to_string()).into());
+            }
+            if let Some(port) = tcp_port {
+                set.tcp.insert(port);
+            }
+            if let Some(port) = udp_port {
+                set.udp.insert(port);
+            }
+        }
+    }
+    Ok(set)
+}
+
+/// Compress ports into contiguous `(start, end)` ranges.
+pub fn to_ranges(ports: &BTreeSet<u16>) -> Vec<(u16, u16)> {
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+    for &port in ports {
+        match ranges.last_mut() {
+            Some((_, end)) if *end as u32 + 1 == port as u32 => *end = port,
+            _ => ranges.push((port, port)),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ports: &[u16]) -> BTreeSet<u16> {
+        ports.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_parse_port_spec() {
+        let ports = parse_port_spec("22,80-82,22").unwrap();
+        assert_eq!(ports.tcp, set(&[22, 80, 81, 82]));
+        assert_eq!(ports.udp, ports.tcp);
+
+        // a prefix holds until the next one
+        let ports = parse_port_spec("T:443,8443,U:53,161").unwrap();
+        assert_eq!(ports.tcp, set(&[443, 8443]));
+        assert_eq!(ports.udp, set(&[53, 161]));
+
+        let ports = parse_port_spec("-3,65534-").unwrap();
+        assert_eq!(ports.tcp, set(&[1, 2, 3, 65534, 65535]));
+        let ports = parse_port_spec("-").unwrap();
+        assert_eq!(ports.tcp.len(), 65535);
+        assert_eq!(ports.udp.len(), 65535);
+
+        // names only for the protocols the service uses
+        let ports = parse_port_spec("ssh,snmp").unwrap();
+        assert_eq!(ports.tcp, set(&[22]));
+        assert_eq!(ports.udp, set(&[161]));
+        let ports = parse_port_spec("T:domain").unwrap();
+        assert_eq!(ports.tcp, set(&[53]));
+        assert!(ports.udp.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_port_spec() {
+        for spec in ["0", "70000", "", "22,", "T:", "0-10"] {
+            let e = parse_port_spec(spec).unwrap_err();
+            assert!(e.is::<InvalidPortError>(), "{}", spec);
+        }
+        assert!(parse_port_spec("10-5").unwrap_err().is::<SplitPortError>());
+        assert!(parse_port_spec("nosuchservice")
+            .unwrap_err()
+            .is::<UnknownServiceError>());
+        // a UDP only service with the TCP prefix
+        assert!(parse_port_spec("T:snmp")
+            .unwrap_err()
+            .is::<UnknownServiceError>());
+    }
+
+    #[test]
+    fn test_top_ports() {
+        assert_eq!(top_ports(3, false).unwrap(), set(&[80, 23, 443]));
+        assert_eq!(top_ports(2, true).unwrap(), set(&[631, 161]));
+        assert_eq!(top_ports(100, false).unwrap().len(), 100);
+        assert!(top_ports(101, false)
+            .unwrap_err()
+            .is::<TooManyTopPortsError>());
+        assert!(top_ports(51, true)
+            .unwrap_err()
+            .is::<TooManyTopPortsError>());
+    }
+
+    #[test]
+    fn test_to_ranges() {
+        assert_eq!(
+            to_ranges(&set(&[1, 2, 3, 5, 7, 8])),
+            [(1, 3), (5, 5), (7, 8)]
+        );
+    }
+}
@@ -1,369 +1,466 @@
-use crate::Args;
-use crate::AutoInferScanTypeError;
-use crate::GetTargetPortFailed;
-use crate::IdleScanValueError;
-use crate::SplitPortError;
-use crate::NULL_VALUE;
+use crate::banner;
+use crate::os;
+use crate::output::ip_records;
+use crate::output::tcp_records;
+use crate::output::udp_records;
+use crate::output::with_hostname;
+use crate::output::Record;
+use crate::payloads;
+use crate::ping;
+use crate::ping::Probe;
+use crate::ports;
+use crate::service;
+use crate::targets;
+use crate::targets::Target;
+use crate::timing;
+use crate::timing::HostTimeouts;
+use crate::timing::Timing;
+use crate::tls;
+use crate::utils;
+use crate::validate;
+use crate::validate::Inputs;
+use crate::validate::Technique;
+use crate::DiscoveryArgs;
+use crate::IdleScanArgs;
+use crate::IpScanArgs;
+use crate::PostScanArgs;
+use crate::ScanArgs;
+use crate::ScanMethods;
+use crate::SourceArgs;
+use crate::TcpScanArgs;
 use anyhow::Result;
-use pistol;
+use pistol::TcpScanResults;
+use pnet_packet::ip::IpNextHeaderProtocol;
+use std::collections::HashSet;
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
-use std::str::FromStr;
+use std::net::Ipv6Addr;
 use std::time::Duration;
-use subnetwork::Ipv4Pool;
 
-#[derive(PartialEq)]
-enum InferScanType {
-    SinglePort,
-    RangePort,
-    Subnet,
-    Unknown,
-}
+type SinglePortFunc = fn(
+    Option<Ipv4Addr>,
+    Option<u16>,
+    Ipv4Addr,
+    u16,
+    Option<&str>,
+    bool,
+    Option<Duration>,
+    Option<usize>,
+) -> Result<TcpScanResults>;
+
+type SinglePortFunc6 = fn(
+    Option<Ipv6Addr>,
+    Option<u16>,
+    Ipv6Addr,
+    u16,
+    Option<&str>,
+    bool,
+    Option<Duration>,
+    Option<usize>,
+) -> Result<TcpScanResults>;
 
 struct Parameters {
     src_ipv4: Option<Ipv4Addr>,
+    src_ipv6: Option<Ipv6Addr>,
     src_port: Option<u16>,
-    dst_ipv4: Option<Ipv4Addr>,
-    dst_port: Option<u16>,
-    zombie_ipv4: Option<Ipv4Addr>,
-    zombie_port: Option<u16>,
-    start_port: Option<u16>,
-    end_port: Option<u16>,
-    subnet: Option<Ipv4Pool>,
     interface: Option<String>,
-    inter_scan_type: InferScanType,
+    /// One pistol call for every (host, port) pair
+    jobs: Vec<(Target, u16)>,
+    /// Seeded with the round trip times of the host discovery
+    timeouts: HostTimeouts,
+    /// Results of the host discovery
+    discovered: Vec<Record>,
 }
 
 impl Parameters {
-    fn new_empty() -> Parameters {
-        Parameters {
-            src_ipv4: None,
-            src_port: None,
-            dst_ipv4: None,
-            dst_port: None,
-            zombie_ipv4: None,
-            zombie_port: None,
-            start_port: None,
-            end_port: None,
-            subnet: None,
-            interface: None,
-            inter_scan_type: InferScanType::Unknown,
+    fn new(
+        args: ScanArgs,
+        technique: Technique,
+        zombie_host: Option<Ipv4Addr>,
+        os_detection: bool,
+        timing: Timing,
+    ) -> Result<Parameters> {
+        let udp = technique == Technique::UdpScan;
+        let dst_ports = match (args.port, args.top_ports) {
+            (Some(port_set), _) => Some(if udp { port_set.udp } else { port_set.tcp }),
+            (None, Some(n)) => Some(ports::top_ports(n, udp)?),
+            (None, None) => None,
+        };
+
+        let targets = targets::parse_targets(&args.target)?;
+        let inputs = Inputs {
+            targets: &targets,
+            ports: dst_ports.as_ref(),
+            interface: args.source.interface.as_deref(),
+            source_host: args.source.source_host,
+            zombie_host,
+            os_detection,
+        };
+        validate::validate(technique, &inputs)?;
+        let (src_ipv4, src_ipv6) =
+            targets::source_addrs(args.source.source_host, args.source.interface.as_deref());
+        let dst_ports = dst_ports.unwrap_or_default();
+        let timeouts = HostTimeouts::new(timing);
+        let (targets, discovered) =
+            live_targets(targets, &args.discovery, &args.source, timing, &timeouts)?;
+        let mut jobs = Vec::new();
+        for target in targets {
+            for &dst_port in &dst_ports {
+                jobs.push((target.clone(), dst_port));
+            }
         }
+
+        Ok(Parameters {
+            src_ipv4,
+            src_ipv6,
+            src_port: args.source_port,
+            interface: args.source.interface,
+            jobs,
+            timeouts,
+            discovered,
+        })
     }
 }
 
-pub fn start_scan(args: Args) -> Result<()> {
-    let mut parameters = Parameters::new_empty();
-
-    let src_ipv4 = if args.source_host != NULL_VALUE {
-        Some(Ipv4Addr::from_str(&args.source_host)?)
-    } else {
-        None
-    };
-    parameters.src_ipv4 = src_ipv4;
-
-    let src_port = if args.source_port != 0 {
-        Some(args.source_port)
-    } else {
-        None
-    };
-    parameters.src_port = src_port;
-
-    let zombie_ipv4 = if args.zombie_host != NULL_VALUE {
-        Some(Ipv4Addr::from_str(&args.zombie_host)?)
-    } else {
-        None
-    };
-    parameters.zombie_ipv4 = zombie_ipv4;
-
-    let zombie_port = if args.zombie_port != 0 {
-        Some(args.zombie_port)
-    } else {
-        None
-    };
-    parameters.zombie_port = zombie_port;
-
-    if args.host != NULL_VALUE {
-        let dst_ipv4 = Ipv4Addr::from_str(&args.host)?;
-        parameters.dst_ipv4 = Some(dst_ipv4);
+/// Ping the targets and keep the ones that are up, every target without host discovery.
+/// The results of the pings are returned too, so down hosts show up in the reports.
+fn live_targets(
+    targets: Vec<Target>,
+    discovery: &DiscoveryArgs,
+    source: &SourceArgs,
+    timing: Timing,
+    timeouts: &HostTimeouts,
+) -> Result<(Vec<Target>, Vec<Record>)> {
+    let probes = ping::probes_or_default(&discovery.probes);
+    if probes.contains(&Probe::Skip) {
+        return Ok((targets, Vec::new()));
     }
+    let records = ping::discover(targets.clone(), &probes, source, timing, timeouts)?;
+    let up: HashSet<IpAddr> = records
+        .iter()
+        .filter(|r| r.state == "up")
+        .map(|r| r.target)
+        .collect();
+    let targets = targets
+        .into_iter()
+        .filter(|t| up.contains(&t.addr))
+        .collect();
+    Ok((targets, records))
+}
 
-    if args.subnet != NULL_VALUE {
-        let subnet = Ipv4Pool::new(&args.subnet)?;
-        parameters.subnet = Some(subnet);
-        parameters.inter_scan_type = InferScanType::Subnet;
-    }
+const PRINT_RESULT: bool = false;
 
-    if args.port != NULL_VALUE {
-        if args.port.contains("-") {
-            let port_split_vec: Vec<&str> = args.port.split("-").collect();
-            if port_split_vec.len() == 2 {
-                let start_port: u16 = port_split_vec[0].parse()?;
-                let end_port: u16 = port_split_vec[1].parse()?;
-                parameters.start_port = Some(start_port);
-                parameters.end_port = Some(end_port);
-                parameters.inter_scan_type = InferScanType::RangePort;
-            } else {
-                return Err(SplitPortError::new(args.port).into());
-            }
-        } else {
-            let dst_port: u16 = args.port.parse()?;
-            parameters.dst_port = Some(dst_port);
-            if parameters.inter_scan_type == InferScanType::Subnet {
-                parameters.start_port = Some(dst_port);
-                parameters.end_port = Some(dst_port);
-            } else {
-                parameters.inter_scan_type = InferScanType::SinglePort;
-            }
-        }
-    } else {
-        return Err(GetTargetPortFailed::new().into());
-    };
+/// The scan results after the results of the host discovery.
+fn collect_records(rets: Vec<Result<Vec<Record>>>, discovered: Vec<Record>) -> Result<Vec<Record>> {
+    let mut records = discovered;
+    records.extend(utils::collect_jobs(rets)?.concat());
+    Ok(records)
+}
 
-    let interface = if args.interface != NULL_VALUE {
-        Some(args.interface)
-    } else {
-        None
-    };
-    parameters.interface = interface;
+pub fn start_scan(method: ScanMethods, timing: Timing) -> Result<Vec<Record>> {
+    match method {
+        ScanMethods::Syn(args) => tcp_scan(
+            args,
+            timing,
+            "syn",
+            pistol::tcp_syn_scan_single_port,
+            pistol::tcp_syn_scan_single_port6,
+        ),
+        ScanMethods::Ack(args) => tcp_scan(
+            args,
+            timing,
+            "ack",
+            pistol::tcp_ack_scan_single_port,
+            pistol::tcp_ack_scan_single_port6,
+        ),
+        ScanMethods::Connect(args) => tcp_scan(
+            args,
+            timing,
+            "connect",
+            pistol::tcp_connect_scan_single_port,
+            pistol::tcp_connect_scan_single_port6,
+        ),
+        ScanMethods::Fin(args) => tcp_scan(
+            args,
+            timing,
+            "fin",
+            pistol::tcp_fin_scan_single_port,
+            pistol::tcp_fin_scan_single_port6,
+        ),
+        ScanMethods::Null(args) => tcp_scan(
+            args,
+            timing,
+            "null",
+            pistol::tcp_null_scan_single_port,
+            pistol::tcp_null_scan_single_port6,
+        ),
+        ScanMethods::Xmas(args) => tcp_scan(
+            args,
+            timing,
+            "xmas",
+            pistol::tcp_xmas_scan_single_port,
+            pistol::tcp_xmas_scan_single_port6,
+        ),
+        ScanMethods::Window(args) => tcp_scan(
+            args,
+            timing,
+            "window",
+            pistol::tcp_window_scan_single_port,
+            pistol::tcp_window_scan_single_port6,
+        ),
+        ScanMethods::Maimon(args) => tcp_scan(
+            args,
+            timing,
+            "maimon",
+            pistol::tcp_maimon_scan_single_port,
+            pistol::tcp_maimon_scan_single_port6,
+        ),
+        ScanMethods::Idle(args) => idle_scan(args, timing),
+        ScanMethods::Udp(args) => udp_scan(args, timing),
+        ScanMethods::Ip(args) => ip_scan(args, timing),
+    }
+}
 
-    let print_result = true;
-    let timeout = Some(Duration::from_secs_f32(0.1));
-    let max_loop = Some(64);
-    let threads_num = 0; // auto detect
+/// Service detection, banners, TLS and OS detection of the open TCP ports that were found.
+fn post_scan(
+    records: &mut [Record],
+    args: &PostScanArgs,
+    src_ipv4: Option<Ipv4Addr>,
+    interface: Option<&str>,
+    timeouts: &HostTimeouts,
+    timing: Timing,
+) -> Result<()> {
+    service::detect_services(records, &args.service, timing)?;
+    banner::grab_banners(records, &args.banner, timing)?;
+    tls::inspect_tls(records, &args.tls, timing)?;
+    os::detect_os(records, &args.os, src_ipv4, interface, timeouts, timing)
+}
 
-    match parameters.inter_scan_type {
-        InferScanType::Unknown => return Err(AutoInferScanTypeError::new().into()),
-        InferScanType::SinglePort => {
-            // scan single port
-            let func = if args.syn {
-                // start syn scan
-                pistol::tcp_syn_scan_single_port
-            } else if args.ack {
-                // start ack scan
-                pistol::tcp_ack_scan_single_port
-            } else if args.connect {
-                pistol::tcp_connect_scan_single_port
-            } else if args.fin {
-                pistol::tcp_fin_scan_single_port
-            } else if args.null {
-                pistol::tcp_null_scan_single_port
-            } else if args.xmas {
-                pistol::tcp_xmas_scan_single_port
-            } else if args.window {
-                pistol::tcp_window_scan_single_port
-            } else if args.maimon {
-                pistol::tcp_maimon_scan_single_port
-            } else if args.idle {
-                if parameters.zombie_ipv4.is_none() || parameters.zombie_port.is_none() {
-                    return Err(IdleScanValueError::new().into());
+fn tcp_scan(
+    args: TcpScanArgs,
+    timing: Timing,
+    technique: &'static str,
+    single_port_func: SinglePortFunc,
+    single_port_func6: SinglePortFunc6,
+) -> Result<Vec<Record>> {
+    let os_detection = args.post_scan.os.os_detection;
+    let parameters = Parameters::new(args.scan, Technique::TcpScan, None, os_detection, timing)?;
+    let src_ipv4 = parameters.src_ipv4;
+    let src_ipv6 = parameters.src_ipv6;
+    let src_port = parameters.src_port;
+    let interface = parameters.interface.clone();
+    let timeouts = parameters.timeouts.clone();
+    let rets = utils::run_jobs(
+        parameters.jobs,
+        timing.threads,
+        move |(target, dst_port)| {
+            let send = |timeout| {
+                // a connect scan waits for the connection in one go, it reads no packets
+                let (read_timeout, max_loop) = match technique {
+                    "connect" => (timeout, 1),
+                    _ => timing::pistol_wait(timeout, 2),
+                };
+                match target.addr {
+                    IpAddr::V4(dst_ipv4) => single_port_func(
+                        src_ipv4,
+                        src_port,
+                        dst_ipv4,
+                        dst_port,
+                        interface.as_deref(),
+                        PRINT_RESULT,
+                        Some(read_timeout),
+                        Some(max_loop),
+                    ),
+                    IpAddr::V6(dst_ipv6) => single_port_func6(
+                        src_ipv6.or_else(|| utils::route_source6(dst_ipv6)),
+                        src_port,
+                        dst_ipv6,
+                        dst_port,
+                        interface.as_deref(),
+                        PRINT_RESULT,
+                        Some(read_timeout),
+                        Some(max_loop),
+                    ),
                 }
-                let _ = pistol::tcp_idle_scan_single_port(
-                    src_ipv4,
-                    src_port,
-                    parameters.dst_ipv4.unwrap(),
-                    parameters.dst_port.unwrap(),
-                    parameters.zombie_ipv4,
-                    parameters.zombie_port,
-                    parameters.interface.as_deref(),
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                return Ok(());
-            } else if args.udp {
-                let _ = pistol::udp_scan_single_port(
-                    src_ipv4,
-                    src_port,
-                    parameters.dst_ipv4.unwrap(),
-                    parameters.dst_port.unwrap(),
-                    parameters.interface.as_deref(),
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                return Ok(());
-            } else if args.ip {
-                // let _ = pistol::ip_protocol_scan_host(
-                //     src_ipv4,
-                //     parameters.dst_ipv4.unwrap(),
-                //     parameters.interface.as_deref(),
-                //     print_result,
-                //     timeout,
-                //     max_loop,
-                // )?;
-                return Ok(());
-            } else if args.arp {
-                let _ = pistol::arp_scan_subnet(
-                    parameters.subnet.unwrap(),
-                    None,
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    max_loop,
-                )?;
-                return Ok(());
-            } else {
-                pistol::tcp_syn_scan_single_port
             };
-            let _ = func(
-                src_ipv4,
-                src_port,
-                parameters.dst_ipv4.unwrap(),
-                parameters.dst_port.unwrap(),
-                parameters.interface.as_deref(),
-                print_result,
-                timeout,
-                max_loop,
-            )?;
-        }
-        InferScanType::RangePort => {
-            // scan range port
-            let func = if args.syn {
-                pistol::tcp_syn_scan_range_port
-            } else if args.ack {
-                pistol::tcp_ack_scan_range_port
-            } else if args.connect {
-                pistol::tcp_connect_scan_range_port
-            } else if args.fin {
-                pistol::tcp_fin_scan_range_port
-            } else if args.null {
-                pistol::tcp_null_scan_range_port
-            } else if args.xmas {
-                pistol::tcp_xmas_scan_range_port
-            } else if args.window {
-                pistol::tcp_window_scan_range_port
-            } else if args.maimon {
-                pistol::tcp_maimon_scan_range_port
-            } else if args.idle {
-                if parameters.zombie_ipv4.is_none() || parameters.zombie_port.is_none() {
-                    return Err(IdleScanValueError::new().into());
-                }
-                let _ = pistol::tcp_idle_scan_range_port(
-                    src_ipv4,
-                    src_port,
-                    parameters.dst_ipv4.unwrap(),
-                    parameters.zombie_ipv4,
-                    parameters.zombie_port,
-                    parameters.start_port.unwrap(),
-                    parameters.end_port.unwrap(),
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                return Ok(());
-            } else if args.udp {
-                let _ = pistol::udp_scan_range_port(
+            let records = utils::probe(target.addr, timing, &timeouts, send, |ret, rtt| {
+                tcp_records(ret, technique, Some(rtt))
+            })?;
+            Ok(with_hostname(records, &target.hostname))
+        },
+    );
+    let mut records = collect_records(rets, parameters.discovered)?;
+    post_scan(
+        &mut records,
+        &args.post_scan,
+        src_ipv4,
+        parameters.interface.as_deref(),
+        &parameters.timeouts,
+        timing,
+    )?;
+    Ok(records)
+}
+
+fn idle_scan(args: IdleScanArgs, timing: Timing) -> Result<Vec<Record>> {
+    let zombie_ipv4 = Some(args.zombie_host);
+    let zombie_port = Some(args.zombie_port);
+    let parameters = Parameters::new(
+        args.scan.scan,
+        Technique::IdleScan,
+        Some(args.zombie_host),
+        args.scan.post_scan.os.os_detection,
+        timing,
+    )?;
+    let src_ipv4 = parameters.src_ipv4;
+    let src_port = parameters.src_port;
+    let interface = parameters.interface.clone();
+    let mut jobs = Vec::new();
+    for (target, dst_port) in parameters.jobs {
+        // validation refuses IPv6 targets, pistol has no idle scan over IPv6
+        let IpAddr::V4(dst_ipv4) = target.addr else {
+            continue;
+        };
+        jobs.push((dst_ipv4, target.hostname, dst_port));
+    }
+    let timeouts = parameters.timeouts.clone();
+    let rets = utils::run_jobs(
+        jobs,
+        timing.threads,
+        move |(dst_ipv4, hostname, dst_port)| {
+            let send = |timeout| {
+                let (read_timeout, max_loop) = timing::pistol_wait(timeout, 2);
+                pistol::tcp_idle_scan_single_port(
                     src_ipv4,
                     src_port,
-                    parameters.dst_ipv4.unwrap(),
-                    parameters.start_port.unwrap(),
-                    parameters.end_port.unwrap(),
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                return Ok(());
-            } else if args.ip {
-                return Ok(());
-            } else if args.arp {
-                let _ = pistol::arp_scan_subnet(
-                    parameters.subnet.unwrap(),
-                    None,
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    max_loop,
-                )?;
-                return Ok(());
-            } else {
-                pistol::tcp_syn_scan_range_port
+                    dst_ipv4,
+                    dst_port,
+                    zombie_ipv4,
+                    zombie_port,
+                    interface.as_deref(),
+                    PRINT_RESULT,
+                    Some(read_timeout),
+                    Some(max_loop),
+                )
             };
-            let _ = func(
-                src_ipv4,
-                src_port,
-                parameters.dst_ipv4.unwrap(),
-                parameters.start_port.unwrap(),
-                parameters.end_port.unwrap(),
-                parameters.interface.as_deref(),
-                threads_num,
-                print_result,
-                timeout,
-                max_loop,
-            )?;
-        }
-        InferScanType::Subnet => {
-            // scan a subnet
-            let func = if args.syn {
-                pistol::tcp_syn_scan_subnet
-            } else if args.ack {
-                pistol::tcp_ack_scan_subnet
-            } else if args.connect {
-                pistol::tcp_connect_scan_subnet
-            } else if args.fin {
-                pistol::tcp_fin_scan_subnet
-            } else if args.null {
-                pistol::tcp_null_scan_subnet
-            } else if args.xmas {
-                pistol::tcp_xmas_scan_subnet
-            } else if args.window {
-                pistol::tcp_window_scan_subnet
-            } else if args.maimon {
-                pistol::tcp_maimon_scan_subnet
-            } else if args.idle {
-                if parameters.zombie_ipv4.is_none() || parameters.zombie_port.is_none() {
-                    return Err(IdleScanValueError::new().into());
+            let records = utils::probe(dst_ipv4.into(), timing, &timeouts, send, |ret, rtt| {
+                tcp_records(ret, "idle", Some(rtt))
+            })?;
+            Ok(with_hostname(records, &hostname))
+        },
+    );
+    let mut records = collect_records(rets, parameters.discovered)?;
+    post_scan(
+        &mut records,
+        &args.scan.post_scan,
+        src_ipv4,
+        parameters.interface.as_deref(),
+        &parameters.timeouts,
+        timing,
+    )?;
+    Ok(records)
+}
+
+fn udp_scan(args: ScanArgs, timing: Timing) -> Result<Vec<Record>> {
+    let parameters = Parameters::new(args, Technique::UdpScan, None, false, timing)?;
+    let src_ipv4 = parameters.src_ipv4;
+    let src_ipv6 = parameters.src_ipv6;
+    let src_port = parameters.src_port;
+    let interface = parameters.interface.clone();
+    let timeouts = parameters.timeouts.clone();
+    let rets = utils::run_jobs(
+        parameters.jobs,
+        timing.threads,
+        move |(target, dst_port)| {
+            let send = |timeout| {
+                let (read_timeout, max_loop) = timing::pistol_wait(timeout, 2);
+                match target.addr {
+                    IpAddr::V4(dst_ipv4) => pistol::udp_scan_single_port(
+                        src_ipv4,
+                        src_port,
+                        dst_ipv4,
+                        dst_port,
+                        interface.as_deref(),
+                        PRINT_RESULT,
+                        Some(read_timeout),
+                        Some(max_loop),
+                    ),
+                    IpAddr::V6(dst_ipv6) => pistol::udp_scan_single_port6(
+                        src_ipv6.or_else(|| utils::route_source6(dst_ipv6)),
+                        src_port,
+                        dst_ipv6,
+                        dst_port,
+                        interface.as_deref(),
+                        PRINT_RESULT,
+                        Some(read_timeout),
+                        Some(max_loop),
+                    ),
                 }
-                let _ = pistol::tcp_idle_scan_subnet(
-                    src_ipv4,
-                    src_port,
-                    parameters.zombie_ipv4,
-                    parameters.zombie_port,
-                    parameters.subnet.unwrap(),
-                    parameters.start_port.unwrap(),
-                    parameters.end_port.unwrap(),
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                return Ok(());
-            } else if args.udp {
-                let _ = pistol::udp_scan_subnet(
-                    src_ipv4,
-                    src_port,
-                    parameters.subnet.unwrap(),
-                    parameters.start_port.unwrap(),
-                    parameters.end_port.unwrap(),
-                    parameters.interface.as_deref(),
-                    threads_num,
-                    print_result,
-                    timeout,
-                    max_loop,
-                )?;
-                return Ok(());
-            } else if args.ip {
-                return Ok(());
-            } else {
-                pistol::tcp_syn_scan_subnet
             };
-            let _ = func(
-                src_ipv4,
-                src_port,
-                parameters.subnet.unwrap(),
-                parameters.start_port.unwrap(),
-                parameters.end_port.unwrap(),
-                parameters.interface.as_deref(),
-                threads_num,
-                print_result,
-                timeout,
-                max_loop,
-            )?;
+            let records = utils::probe(target.addr, timing, &timeouts, send, |ret, rtt| {
+                udp_records(ret, Some(rtt))
+            })?;
+            Ok(with_hostname(records, &target.hostname))
+        },
+    );
+    let mut records = collect_records(rets, parameters.discovered)?;
+    payloads::probe_payloads(
+        &mut records,
+        src_ipv4,
+        src_ipv6,
+        parameters.interface.as_deref(),
+        &parameters.timeouts,
+        timing,
+    )?;
+    Ok(records)
+}
+
+fn ip_scan(args: IpScanArgs, timing: Timing) -> Result<Vec<Record>> {
+    let protocols = args.protocol.unwrap_or_else(|| (0..=u8::MAX).collect());
+    let targets = targets::parse_targets(&args.target)?;
+    let inputs = Inputs {
+        targets: &targets,
+        interface: args.source.interface.as_deref(),
+        source_host: args.source.source_host,
+        ..Default::default()
+    };
+    validate::validate(Technique::IpScan, &inputs)?;
+    let (src_ipv4, _) =
+        targets::source_addrs(args.source.source_host, args.source.interface.as_deref());
+    let timeouts = HostTimeouts::new(timing);
+    let (targets, discovered) =
+        live_targets(targets, &args.discovery, &args.source, timing, &timeouts)?;
+    let mut jobs = Vec::new();
+    for target in targets {
+        // validation refuses IPv6 targets, pistol has no IP protocol scan over IPv6
+        let IpAddr::V4(dst_ipv4) = target.addr else {
+            continue;
+        };
+        for &protocol in &protocols {
+            jobs.push((dst_ipv4, target.hostname.clone(), protocol));
         }
     }
-    Ok(())
+    let interface = args.source.interface;
+    let rets = utils::run_jobs(
+        jobs,
+        timing.threads,
+        move |(dst_ipv4, hostname, protocol)| {
+            let send = |timeout| {
+                let (read_timeout, max_loop) = timing::pistol_wait(timeout, 2);
+                pistol::ip_protocol_scan_host(
+                    src_ipv4,
+                    dst_ipv4,
+                    IpNextHeaderProtocol::new(protocol),
+                    interface.as_deref(),
+                    PRINT_RESULT,
+                    Some(read_timeout),
+                    Some(max_loop),
+                )
+            };
+            let records = utils::probe(dst_ipv4.into(), timing, &timeouts, send, |ret, rtt| {
+                ip_records(ret, Some(rtt))
+            })?;
+            Ok(with_hostname(records, &hostname))
+        },
+    );
+    collect_records(rets, discovered)
 }
@@ -0,0 +1,605 @@
+use crate::output::Protocol;
+use crate::output::Record;
+use crate::ports;
+use crate::timing::Timing;
+use crate::utils;
+use crate::InvalidServiceProbesError;
+use crate::ReadServiceProbesError;
+use crate::ServiceArgs;
+use anyhow::Result;
+use regex::bytes::Captures;
+use regex::bytes::Regex;
+use regex::bytes::RegexBuilder;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The built-in probes, in nmap-service-probes format
+const SERVICE_PROBES: &str = include_str!("service-probes");
+/// nmap waits this long when a probe has no `totalwaitms`
+const DEFAULT_TOTAL_WAIT: Duration = Duration::from_millis(5000);
+/// A response is complete when nothing more arrives for this long
+const READ_GAP: Duration = Duration::from_millis(200);
+const MAX_RESPONSE_LEN: usize = 8192;
+const DEFAULT_RARITY: u8 = 5;
+
+/// The service on a port, found by a probe.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Service {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extrainfo: Option<String>,
+}
+
+impl Service {
+    /// Product, version and extra info like nmap prints them, `OpenSSH 8.9p1 (protocol 2.0)`.
+    pub fn version_info(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(product) = &self.product {
+            parts.push(product.clone());
+        }
+        if let Some(version) = &self.version {
+            parts.push(version.clone());
+        }
+        if let Some(extrainfo) = &self.extrainfo {
+            parts.push(format!("({})", extrainfo));
+        }
+        parts.join(" ")
+    }
+}
+
+/// A `match` or `softmatch` line, the templates take `$1` like substitutions.
+#[derive(Debug, Clone)]
+struct ServiceMatch {
+    service: String,
+    regex: Regex,
+    soft: bool,
+    product: Option<String>,
+    version: Option<String>,
+    extrainfo: Option<String>,
+}
+
+/// A `Probe` line with its directives and matches.
+#[derive(Debug, Clone)]
+struct ServiceProbe {
+    name: String,
+    tcp: bool,
+    payload: Vec<u8>,
+    rarity: u8,
+    ports: BTreeSet<u16>,
+    total_wait: Duration,
+    fallback: Vec<String>,
+    matches: Vec<ServiceMatch>,
+}
+
+/// Probes and matches in the order of the nmap-service-probes file.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceDb {
+    probes: Vec<ServiceProbe>,
+}
+
+/// Unescape a probe string, like `GET / HTTP/1.0\r\n\r\n` or `\x16\x03`.
+fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut ret = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            ret.push(b);
+            continue;
+        }
+        let c = bytes.next()?;
+        let unescaped = match c {
+            b'0' => 0,
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0b,
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            c => c,
+        };
+        ret.push(unescaped);
+    }
+    Some(ret)
+}
+
+/// Split `<delim>text<delim>rest` into the text and the rest.
+fn delimited(s: &str) -> Option<(&str, &str)> {
+    let delim = s.chars().next()?;
+    let s = &s[delim.len_utf8()..];
+    let end = s.find(delim)?;
+    Some((&s[..end], &s[end + delim.len_utf8()..]))
+}
+
+/// Parse `Probe TCP NULL q||` into the protocol, name and payload.
+fn parse_probe(line: &str) -> Option<ServiceProbe> {
+    let mut fields = line.splitn(3, ' ');
+    let protocol = fields.next()?;
+    let name = fields.next()?;
+    let (payload, _) = delimited(fields.next()?.strip_prefix('q')?)?;
+    Some(ServiceProbe {
+        name: name.to_string(),
+        tcp: protocol == "TCP",
+        payload: unescape(payload)?,
+        rarity: DEFAULT_RARITY,
+        ports: BTreeSet::new(),
+        total_wait: DEFAULT_TOTAL_WAIT,
+        fallback: Vec::new(),
+        matches: Vec::new(),
+    })
+}
+
+/// Parse `ssh m|^SSH-([\d.]+)-OpenSSH_(\S+)|s p/OpenSSH/ v/$2/`, the part after `match`.
+/// `Ok(None)` when the pattern is fine for nmap but not for the regex crate.
+fn parse_match(line: &str, soft: bool) -> Option<Result<ServiceMatch, regex::Error>> {
+    let (service, rest) = line.split_once(' ')?;
+    let (pattern, rest) = delimited(rest.strip_prefix('m')?)?;
+    let flags_end = rest.find(' ').unwrap_or(rest.len());
+    let (flags, mut rest) = rest.split_at(flags_end);
+    let regex = RegexBuilder::new(pattern)
+        .unicode(false)
+        .octal(true)
+        .case_insensitive(flags.contains('i'))
+        .dot_matches_new_line(flags.contains('s'))
+        .build();
+    let mut service_match = ServiceMatch {
+        service: service.to_string(),
+        regex: match regex {
+            Ok(r) => r,
+            Err(e) => return Some(Err(e)),
+        },
+        soft,
+        product: None,
+        version: None,
+        extrainfo: None,
+    };
+    // version info like `p/OpenSSH/ v/$2/ i/protocol $1/ cpe:/a:openbsd:openssh:$2/`
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let key_len = if rest.starts_with("cpe:") { 4 } else { 1 };
+        // a key that is not ASCII makes the line invalid
+        let key = rest.get(..key_len)?;
+        let (value, after) = delimited(&rest[key_len..])?;
+        match key {
+            "p" => service_match.product = Some(value.to_string()),
+            "v" => service_match.version = Some(value.to_string()),
+            "i" => service_match.extrainfo = Some(value.to_string()),
+            // hostname, os, device type and cpe are not reported
+            _ => (),
+        }
+        rest = after.strip_prefix('a').unwrap_or(after);
+    }
+    Some(Ok(service_match))
+}
+
+/// Turn the bytes that are not UTF-8 into `\xHH` escapes,
+/// so a pattern like `m|^caf\xe9|` written with a raw Latin-1 byte still matches it.
+fn escape_invalid(content: &[u8]) -> String {
+    let mut ret = String::with_capacity(content.len());
+    for chunk in content.utf8_chunks() {
+        ret.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            ret.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    ret
+}
+
+impl ServiceDb {
+    /// Parse a database in nmap-service-probes format,
+    /// matches with a pattern the regex crate does not support are left out.
+    pub fn parse(content: &str) -> Result<ServiceDb> {
+        let mut db = ServiceDb::default();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || InvalidServiceProbesError::new(i + 1);
+            let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+            if directive == "Probe" {
+                db.probes.push(parse_probe(rest).ok_or_else(invalid)?);
+                continue;
+            }
+            // Exclude comes before the first probe
+            if directive == "Exclude" {
+                continue;
+            }
+            let probe = db.probes.last_mut().ok_or_else(invalid)?;
+            match directive {
+                "match" | "softmatch" => match parse_match(rest, directive == "softmatch") {
+                    Some(Ok(m)) => probe.matches.push(m),
+                    Some(Err(_)) => (),
+                    None => return Err(invalid().into()),
+                },
+                "ports" => probe.ports = ports::parse_port_spec(rest).map_err(|_| invalid())?.tcp,
+                "rarity" => probe.rarity = rest.parse().map_err(|_| invalid())?,
+                "totalwaitms" => {
+                    probe.total_wait = Duration::from_millis(rest.parse().map_err(|_| invalid())?)
+                }
+                "fallback" => probe.fallback = rest.split(',').map(|s| s.to_string()).collect(),
+                // TLS is not tunneled, so sslports are not probed differently
+                "sslports" | "tcpwrappedms" => (),
+                _ => return Err(invalid().into()),
+            }
+        }
+        Ok(db)
+    }
+
+    /// The built-in database, or the nmap-service-probes file at `path`.
+    pub fn load(path: Option<&str>) -> Result<ServiceDb> {
+        match path {
+            // patterns may hold bytes that are not UTF-8
+            Some(path) => match fs::read(path) {
+                Ok(content) => ServiceDb::parse(&escape_invalid(&content)),
+                Err(_) => Err(ReadServiceProbesError::new(path.to_string()).into()),
+            },
+            None => ServiceDb::parse(SERVICE_PROBES),
+        }
+    }
+
+    /// The TCP probes for `port` in the order they are sent: the NULL probe,
+    /// the probes registered to the port and then the rest up to the intensity.
+    fn probes_for(&self, port: u16, intensity: u8) -> Vec<&ServiceProbe> {
+        let tcp_probes = || self.probes.iter().filter(|p| p.tcp);
+        let null = tcp_probes().filter(|p| p.payload.is_empty());
+        let registered = tcp_probes().filter(|p| !p.payload.is_empty() && p.ports.contains(&port));
+        let rest = tcp_probes()
+            .filter(|p| !p.payload.is_empty() && !p.ports.contains(&port) && p.rarity <= intensity);
+        null.chain(registered).chain(rest).collect()
+    }
+
+    /// The matches for a response to `probe`, its own first and then the ones of its fallbacks.
+    fn matches_for<'a>(
+        &'a self,
+        probe: &'a ServiceProbe,
+    ) -> impl Iterator<Item = &'a ServiceMatch> {
+        let fallbacks = probe
+            .fallback
+            .iter()
+            .filter_map(|name| self.probes.iter().find(|p| &p.name == name));
+        probe
+            .matches
+            .iter()
+            .chain(fallbacks.flat_map(|p| p.matches.iter()))
+    }
+}
+
+/// Replace `$1`, `$P(1)`, `$SUBST(1,"_",".")` and `$I(1,">")` with the groups of a match.
+fn substitute(template: &str, caps: &Captures) -> String {
+    let group = |n: &str| -> Vec<u8> {
+        n.trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| caps.get(n))
+            .map(|m| m.as_bytes().to_vec())
+            .unwrap_or_default()
+    };
+    let mut ret = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        ret.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(c) = rest.chars().next().filter(|c| c.is_ascii_digit()) {
+            ret.push_str(&String::from_utf8_lossy(&group(&c.to_string())));
+            rest = &rest[1..];
+            continue;
+        }
+        let function = ["P(", "SUBST(", "I("]
+            .into_iter()
+            .find(|f| rest.starts_with(f));
+        let (Some(function), Some(end)) = (function, rest.find(')')) else {
+            ret.push('$');
+            continue;
+        };
+        let args: Vec<&str> = rest[function.len()..end].split(',').collect();
+        let bytes = group(args[0]);
+        let value = match function {
+            // printable characters only
+            "P(" => bytes
+                .iter()
+                .filter(|b| b.is_ascii_graphic() || **b == b' ')
+                .map(|&b| b as char)
+                .collect(),
+            "SUBST(" if args.len() == 3 => {
+                let unquote = |s: &str| s.trim().trim_matches('"').to_string();
+                String::from_utf8_lossy(&bytes).replace(&unquote(args[1]), &unquote(args[2]))
+            }
+            "I(" => {
+                let mut bytes = bytes;
+                if args.get(1).is_some_and(|a| a.contains('<')) {
+                    bytes.reverse();
+                }
+                bytes
+                    .iter()
+                    .fold(0u64, |n, &b| (n << 8) | b as u64)
+                    .to_string()
+            }
+            _ => String::new(),
+        };
+        ret.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
+impl ServiceMatch {
+    fn service(&self, response: &[u8]) -> Option<Service> {
+        let caps = self.regex.captures(response)?;
+        let field = |template: &Option<String>| {
+            template
+                .as_ref()
+                .map(|t| substitute(t, &caps).trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        Some(Service {
+            name: self.service.clone(),
+            product: field(&self.product),
+            version: field(&self.version),
+            extrainfo: field(&self.extrainfo),
+        })
+    }
+}
+
+/// Connect, send the payload and read the response until the server goes quiet.
+pub fn exchange(
+    addr: SocketAddr,
+    payload: &[u8],
+    connect_timeout: Duration,
+    total_wait: Duration,
+) -> Result<Vec<u8>> {
+    utils::pace();
+    let mut stream = TcpStream::connect_timeout(&addr, connect_timeout)?;
+    if !payload.is_empty() {
+        stream.write_all(payload)?;
+    }
+    let start = Instant::now();
+    let mut response = Vec::new();
+    let mut buff = [0u8; 4096];
+    while response.len() < MAX_RESPONSE_LEN {
+        let Some(remaining) = total_wait.checked_sub(start.elapsed()) else {
+            break;
+        };
+        let wait = if response.is_empty() {
+            remaining
+        } else {
+            remaining.min(READ_GAP)
+        };
+        if wait.is_zero() {
+            break;
+        }
+        stream.set_read_timeout(Some(wait))?;
+        match stream.read(&mut buff) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buff[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            // a reset after the response still leaves us the response
+            Err(_) if !response.is_empty() => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(response)
+}
+
+/// Send the probes to a port until one of the responses has a hard match,
+/// a soft match is only kept in case nothing better turns up.
+pub fn detect(
+    db: &ServiceDb,
+    addr: SocketAddr,
+    intensity: u8,
+    connect_timeout: Duration,
+) -> Option<Service> {
+    let mut soft = None;
+    for probe in db.probes_for(addr.port(), intensity) {
+        let response = match exchange(addr, &probe.payload, connect_timeout, probe.total_wait) {
+            Ok(r) if !r.is_empty() => r,
+            _ => continue,
+        };
+        for service_match in db.matches_for(probe) {
+            if let Some(service) = service_match.service(&response) {
+                if !service_match.soft {
+                    return Some(service);
+                }
+                soft.get_or_insert(service);
+                break;
+            }
+        }
+    }
+    soft
+}
+
+/// Find the services of the open TCP ports in the results, when `--service-version` is set.
+pub fn detect_services(records: &mut [Record], args: &ServiceArgs, timing: Timing) -> Result<()> {
+    if !args.service_version {
+        return Ok(());
+    }
+    let db = Arc::new(ServiceDb::load(args.version_db.as_deref())?);
+    let connect_timeout = timing.timeout.unwrap_or(timing.max_rtt_timeout);
+    let intensity = args.version_intensity;
+    let jobs: Vec<(usize, SocketAddr)> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.protocol == Protocol::Tcp && r.state == "open")
+        .filter_map(|(i, r)| r.port.map(|port| (i, SocketAddr::new(r.target, port))))
+        .collect();
+    let rets = utils::run_jobs(jobs, timing.threads, move |(i, addr)| {
+        (i, detect(&db, addr, intensity, connect_timeout))
+    });
+    for (i, service) in rets {
+        records[i].service = service;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(db: &ServiceDb, probe: &str, response: &[u8]) -> Option<Service> {
+        let probe = db.probes.iter().find(|p| p.name == probe).unwrap();
+        let mut soft = None;
+        for m in db.matches_for(probe) {
+            if let Some(s) = m.service(response) {
+                if !m.soft {
+                    return Some(s);
+                }
+                soft.get_or_insert(s);
+            }
+        }
+        soft
+    }
+
+    #[test]
+    fn test_builtin_db() {
+        let db = ServiceDb::load(None).unwrap();
+        let names: Vec<&str> = db.probes.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "NULL",
+                "HTTPHead",
+                "TLSSessionReq",
+                "SSHIdent",
+                "SMTPEhlo",
+                "GenericLines"
+            ]
+        );
+        // every built-in pattern compiles
+        let matches = SERVICE_PROBES
+            .lines()
+            .filter(|l| l.starts_with("match ") || l.starts_with("softmatch "))
+            .count();
+        let parsed: usize = db.probes.iter().map(|p| p.matches.len()).sum();
+        assert_eq!(parsed, matches);
+    }
+
+    #[test]
+    fn test_match_versions() {
+        let db = ServiceDb::load(None).unwrap();
+        let ssh = service(&db, "NULL", b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\r\n").unwrap();
+        assert_eq!(ssh.name, "ssh");
+        assert_eq!(ssh.product.as_deref(), Some("OpenSSH"));
+        assert_eq!(ssh.version.as_deref(), Some("8.9p1 Ubuntu 3ubuntu0.6"));
+        assert_eq!(ssh.extrainfo.as_deref(), Some("Ubuntu Linux; protocol 2.0"));
+
+        let http = b"HTTP/1.0 200 OK\r\nServer: nginx/1.24.0\r\nDate: x\r\n\r\n";
+        let http = service(&db, "HTTPHead", http).unwrap();
+        assert_eq!(http.name, "http");
+        assert_eq!(http.product.as_deref(), Some("nginx"));
+        assert_eq!(http.version.as_deref(), Some("1.24.0"));
+
+        // the HTTP probe falls back to the banner matches
+        let ftp = service(&db, "HTTPHead", b"220 (vsFTPd 3.0.5)\r\n").unwrap();
+        assert_eq!(ftp.name, "ftp");
+        assert_eq!(ftp.version.as_deref(), Some("3.0.5"));
+
+        let tls = service(&db, "TLSSessionReq", b"\x16\x03\x03\x00\x5a\x02\x00").unwrap();
+        assert_eq!(tls.name, "ssl");
+
+        let soft = service(&db, "NULL", b"SSH-2.0-SomethingElse\r\n").unwrap();
+        assert_eq!(soft.name, "ssh");
+        assert_eq!(soft.product, None);
+    }
+
+    #[test]
+    fn test_nmap_syntax() {
+        let db = ServiceDb::parse(
+            "Exclude T:9100-9107\n\
+             Probe TCP GetRequest q|GET / HTTP/1.0\\r\\n\\r\\n|\n\
+             rarity 1\n\
+             ports 80,8000-8010\n\
+             match x m=^v(\\d)\\.(\\d)\\0=s p/X/ v/$1.$2/ i/$SUBST(1,\"1\",\"one\")/ cpe:/a:x:x:$1/a\n\
+             match y m|(?<=lookbehind)| p/Y/\n",
+        )
+        .unwrap();
+        let probe = &db.probes[0];
+        assert_eq!(probe.payload, b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(probe.rarity, 1);
+        assert!(probe.ports.contains(&8005));
+        // the lookbehind is not supported and left out
+        assert_eq!(probe.matches.len(), 1);
+        let x = probe.matches[0].service(b"v1.2\0").unwrap();
+        assert_eq!(x.version.as_deref(), Some("1.2"));
+        assert_eq!(x.extrainfo.as_deref(), Some("one"));
+
+        assert!(ServiceDb::parse("match x m|x|\n").is_err());
+        assert!(ServiceDb::parse("Probe TCP NULL q||\nbogus 1\n").is_err());
+        // a version field that starts with a multibyte character
+        assert!(ServiceDb::parse("Probe TCP NULL q||\nmatch x m|x| \u{e9}/X/\n").is_err());
+        assert!(ServiceDb::parse("Probe TCP NULL q||\nmatch x m|x| c\u{e9}pe:/a/\n").is_err());
+    }
+
+    #[test]
+    fn test_load_latin1() {
+        let path = std::env::temp_dir().join(format!("pistolcli-probes-{}", std::process::id()));
+        fs::write(
+            &path,
+            b"Probe TCP NULL q||\nmatch x m|^caf\xe9| p/Caf\xe9/\nmatch y m|^y| p/Y/\n",
+        )
+        .unwrap();
+        let db = ServiceDb::load(path.to_str());
+        fs::remove_file(&path).unwrap();
+        let db = db.unwrap();
+        assert_eq!(db.probes[0].matches.len(), 2);
+        // the raw byte matches itself
+        let cafe = service(&db, "NULL", b"caf\xe9").unwrap();
+        assert_eq!(cafe.name, "x");
+        assert_eq!(service(&db, "NULL", b"caf\xc3\xa9"), None);
+    }
+
+    #[test]
+    fn test_escape_invalid() {
+        assert_eq!(escape_invalid(b"m|caf\xe9|"), "m|caf\\xe9|");
+        assert_eq!(escape_invalid("m|caf\u{e9}|".as_bytes()), "m|caf\u{e9}|");
+    }
+
+    #[test]
+    fn test_detect_services() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(1) {
+                let mut stream = stream.unwrap();
+                stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").unwrap();
+            }
+        });
+        let target = "127.0.0.1".parse().unwrap();
+        let mut records = vec![
+            Record::new(target, Some(port), Protocol::Tcp, "connect", "open", None),
+            Record::new(target, Some(1), Protocol::Tcp, "connect", "closed", None),
+        ];
+        let args = ServiceArgs {
+            service_version: true,
+            version_intensity: 0,
+            version_db: None,
+        };
+        detect_services(&mut records, &args, Timing::template(4)).unwrap();
+        let ssh = records[0].service.as_ref().unwrap();
+        assert_eq!(ssh.name, "ssh");
+        assert_eq!(ssh.product.as_deref(), Some("OpenSSH"));
+        assert_eq!(ssh.version.as_deref(), Some("9.6"));
+        // only the open ports are probed
+        assert_eq!(records[1].service, None);
+    }
+}
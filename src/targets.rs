@@ -0,0 +1,569 @@
+use crate::output::group_by_host;
+use crate::output::host_state;
+use crate::output::Record;
+use crate::utils;
+use crate::InvalidTargetError;
+use crate::ReadInputListError;
+use crate::ResolveHostError;
+use crate::TargetArgs;
+use crate::ValidationError;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::ToSocketAddrs;
+use std::str::FromStr;
+
+const THREADS_NUM: usize = 0; // auto detect
+/// Most addresses one target expression may have, every address is held in memory
+const MAX_ADDRESSES: usize = 1 << 24;
+/// The largest networks with at most `MAX_ADDRESSES` addresses
+const MIN_IPV4_PREFIX: u32 = 8;
+const MIN_IPV6_PREFIX: u32 = 104;
+
+fn too_many_addresses(expr: &str) -> anyhow::Error {
+    ValidationError::TooManyAddresses {
+        target: expr.to_string(),
+        max: MAX_ADDRESSES,
+    }
+    .into()
+}
+
+/// Parse one octet of an octet range, like `1`, `1-50`, `-50`, `200-`, `*` or `1,3,5-7`.
+fn parse_octet(octet: &str, expr: &str) -> Result<Vec<u8>> {
+    let mut ret = Vec::new();
+    for item in octet.split(',') {
+        let (start, end) = if item == "*" {
+            (0, u8::MAX)
+        } else if let Some((start, end)) = item.split_once('-') {
+            let start = if start.is_empty() { 0 } else { start.parse()? };
+            let end = if end.is_empty() {
+                u8::MAX
+            } else {
+                end.parse()?
+            };
+            (start, end)
+        } else {
+            let o = item.parse()?;
+            (o, o)
+        };
+        if start > end {
+            return Err(InvalidTargetError::new(expr.to_string()).into());
+        }
+        ret.extend(start..=end);
+    }
+    Ok(ret)
+}
+
+/// The values of the four octets of an octet range.
+fn parse_octets(expr: &str) -> Result<Vec<Vec<u8>>> {
+    let octets: Vec<&str> = expr.split('.').collect();
+    if octets.len() != 4 {
+        return Err(InvalidTargetError::new(expr.to_string()).into());
+    }
+    let mut ret = Vec::new();
+    for octet in octets {
+        match parse_octet(octet, expr) {
+            Ok(v) => ret.push(v),
+            Err(_) => return Err(InvalidTargetError::new(expr.to_string()).into()),
+        }
+    }
+    Ok(ret)
+}
+
+/// Octet ranges like `10.0.0.1-50` or `10.0-3.1.1`, every octet may be a range.
+fn parse_octet_range(expr: &str) -> Result<Vec<Ipv4Addr>> {
+    let octets = parse_octets(expr)?;
+    if octets.iter().map(|values| values.len()).product::<usize>() > MAX_ADDRESSES {
+        return Err(too_many_addresses(expr));
+    }
+    let mut ret = vec![Vec::new()];
+    for values in octets {
+        ret = ret
+            .iter()
+            .flat_map(|prefix| {
+                values.iter().map(move |v| {
+                    let mut p = prefix.clone();
+                    p.push(*v);
+                    p
+                })
+            })
+            .collect();
+    }
+    Ok(ret
+        .iter()
+        .map(|o| Ipv4Addr::new(o[0], o[1], o[2], o[3]))
+        .collect())
+}
+
+/// The addresses of a hostname in the wanted family, all of them or only the first one.
+fn resolve_host(host: &str, resolve_all: bool, ipv6: bool) -> Result<Vec<IpAddr>> {
+    let addrs = match (host, 0).to_socket_addrs() {
+        Ok(a) => a,
+        Err(_) => return Err(ResolveHostError::new(host.to_string()).into()),
+    };
+    let mut ret = Vec::new();
+    for addr in addrs {
+        let ip = addr.ip();
+        if ip.is_ipv6() == ipv6 && !ret.contains(&ip) {
+            ret.push(ip);
+        }
+    }
+    if ret.is_empty() {
+        return Err(ResolveHostError::new(host.to_string()).into());
+    }
+    if !resolve_all {
+        ret.truncate(1);
+    }
+    Ok(ret)
+}
+
+/// The name of an address from reverse DNS.
+fn reverse_resolve(addr: IpAddr) -> Option<String> {
+    match dns_lookup::lookup_addr(&addr) {
+        // the numeric address comes back when there is no name
+        Ok(name) if name != addr.to_string() => Some(name),
+        _ => None,
+    }
+}
+
+fn is_octet_range(expr: &str) -> bool {
+    expr.contains('.')
+        && expr
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | ',' | '*'))
+}
+
+/// One address to scan and the hostname it was given as.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub addr: IpAddr,
+    pub hostname: Option<String>,
+}
+
+/// Parse one target expression: an address, a CIDR, an octet range or a hostname.
+/// With `ipv6` hostnames resolve to IPv6 addresses and IPv4 targets are refused.
+pub fn parse_target(expr: &str, resolve_all: bool, ipv6: bool) -> Result<Vec<Target>> {
+    let (addrs, hostname) = match parse_addrs(expr, ipv6)? {
+        Some(addrs) => (addrs, None),
+        None => (
+            resolve_host(expr, resolve_all, ipv6)?,
+            Some(expr.to_string()),
+        ),
+    };
+    if ipv6 && addrs.iter().any(|a| a.is_ipv4()) {
+        return Err(ValidationError::AddressFamily {
+            addr: expr.to_string(),
+        }
+        .into());
+    }
+    Ok(addrs
+        .into_iter()
+        .map(|addr| Target {
+            addr,
+            hostname: hostname.clone(),
+        })
+        .collect())
+}
+
+fn parse_prefix(expr: &str, addr: IpAddr, prefix: &str) -> Result<u32> {
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    match prefix.parse() {
+        Ok(p) if p <= max => Ok(p),
+        _ => Err(InvalidTargetError::new(expr.to_string()).into()),
+    }
+}
+
+/// All addresses of a CIDR, from the network to the broadcast address.
+fn parse_cidr(expr: &str, addr: IpAddr, prefix: &str) -> Result<Vec<IpAddr>> {
+    let prefix = parse_prefix(expr, addr, prefix)?;
+    let min_prefix = if addr.is_ipv4() {
+        MIN_IPV4_PREFIX
+    } else {
+        MIN_IPV6_PREFIX
+    };
+    if prefix < min_prefix {
+        return Err(too_many_addresses(expr));
+    }
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            let network = u32::from(addr) & mask;
+            let broadcast = network | !mask;
+            Ok((network..=broadcast)
+                .map(|a| Ipv4Addr::from(a).into())
+                .collect())
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            let network = u128::from(addr) & mask;
+            let broadcast = network | !mask;
+            Ok((network..=broadcast)
+                .map(|a| Ipv6Addr::from(a).into())
+                .collect())
+        }
+    }
+}
+
+/// An excluded target expression, networks and octet ranges are matched instead of
+/// expanded so they can be larger than the targets.
+enum Exclusion {
+    Cidr(IpAddr, u32),
+    Octets(Vec<Vec<u8>>),
+    Addrs(Vec<IpAddr>),
+}
+
+impl Exclusion {
+    fn parse(expr: &str, ipv6: bool) -> Result<Exclusion> {
+        if let Some((addr, prefix)) = expr.split_once('/') {
+            let addr = match IpAddr::from_str(addr) {
+                Ok(a) => a,
+                Err(_) => resolve_host(addr, false, ipv6)?[0],
+            };
+            Ok(Exclusion::Cidr(addr, parse_prefix(expr, addr, prefix)?))
+        } else if IpAddr::from_str(expr).is_err() && is_octet_range(expr) {
+            Ok(Exclusion::Octets(parse_octets(expr)?))
+        } else {
+            let targets = parse_target(expr, true, ipv6)?;
+            Ok(Exclusion::Addrs(
+                targets.into_iter().map(|t| t.addr).collect(),
+            ))
+        }
+    }
+
+    fn contains(&self, addr: &IpAddr) -> bool {
+        match (self, addr) {
+            (Exclusion::Cidr(IpAddr::V4(network), prefix), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                u32::from(*network) & mask == u32::from(*addr) & mask
+            }
+            (Exclusion::Cidr(IpAddr::V6(network), prefix), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                u128::from(*network) & mask == u128::from(*addr) & mask
+            }
+            (Exclusion::Octets(octets), IpAddr::V4(addr)) => octets
+                .iter()
+                .zip(addr.octets())
+                .all(|(values, octet)| values.contains(&octet)),
+            (Exclusion::Addrs(addrs), addr) => addrs.contains(addr),
+            _ => false,
+        }
+    }
+}
+
+/// The addresses of a target expression that is not a plain hostname.
+fn parse_addrs(expr: &str, ipv6: bool) -> Result<Option<Vec<IpAddr>>> {
+    if let Some((addr, prefix)) = expr.split_once('/') {
+        let addr = match IpAddr::from_str(addr) {
+            Ok(a) => a,
+            Err(_) => resolve_host(addr, false, ipv6)?[0],
+        };
+        Ok(Some(parse_cidr(expr, addr, prefix)?))
+    } else if let Ok(addr) = IpAddr::from_str(expr) {
+        Ok(Some(vec![addr]))
+    } else if is_octet_range(expr) {
+        let addrs = parse_octet_range(expr)?;
+        Ok(Some(addrs.into_iter().map(IpAddr::V4).collect()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Split the source address by family, validation makes sure it is the family of every target.
+/// Without one the IPv4 address of the interface is looked up once here,
+/// pistol would look it up again for every probe.
+pub fn source_addrs(
+    source: Option<IpAddr>,
+    interface: Option<&str>,
+) -> (Option<Ipv4Addr>, Option<Ipv6Addr>) {
+    match source {
+        Some(IpAddr::V4(src_ipv4)) => (Some(src_ipv4), None),
+        Some(IpAddr::V6(src_ipv6)) => (None, Some(src_ipv6)),
+        None => match interface.and_then(|name| utils::interface_source(name, false)) {
+            Some(IpAddr::V4(src_ipv4)) => (Some(src_ipv4), None),
+            _ => (None, None),
+        },
+    }
+}
+
+/// Target expressions separated by whitespace or newlines, `#` starts a comment.
+fn read_exprs(reader: impl BufRead) -> io::Result<Vec<String>> {
+    let mut exprs = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default();
+        exprs.extend(line.split_whitespace().map(|expr| expr.to_string()));
+    }
+    Ok(exprs)
+}
+
+/// Read target expressions from a file or `-` for `stdin`.
+fn read_input_list(path: &str, stdin: impl BufRead) -> Result<Vec<String>> {
+    let exprs = if path == "-" {
+        read_exprs(stdin)
+    } else {
+        File::open(path).and_then(|file| read_exprs(BufReader::new(file)))
+    };
+    match exprs {
+        Ok(exprs) => Ok(exprs),
+        Err(_) => Err(ReadInputListError::new(path.to_string()).into()),
+    }
+}
+
+/// Merge all target expressions into one deduplicated and sorted target set.
+pub fn parse_targets(args: &TargetArgs) -> Result<Vec<Target>> {
+    let mut exprs: Vec<String> = args
+        .targets
+        .iter()
+        .chain(&args.host)
+        .chain(&args.subnet)
+        .cloned()
+        .collect();
+    if let Some(path) = &args.input_list {
+        exprs.extend(read_input_list(path, io::stdin().lock())?);
+    }
+    let mut targets: BTreeMap<IpAddr, Option<String>> = BTreeMap::new();
+    for expr in &exprs {
+        for target in parse_target(expr, args.resolve_all, args.ipv6)? {
+            let hostname = targets.entry(target.addr).or_default();
+            if hostname.is_none() {
+                *hostname = target.hostname;
+            }
+        }
+    }
+
+    let mut exclude_exprs = args.exclude.clone();
+    if let Some(path) = &args.excludefile {
+        exclude_exprs.extend(read_input_list(path, io::stdin().lock())?);
+    }
+    let exclusions = exclude_exprs
+        .iter()
+        .map(|expr| Exclusion::parse(expr, args.ipv6))
+        .collect::<Result<Vec<Exclusion>>>()?;
+    let before = targets.len();
+    targets.retain(|addr, _| !exclusions.iter().any(|e| e.contains(addr)));
+    let excluded_num = before - targets.len();
+    if targets.is_empty() {
+        return Err(ValidationError::NoTargets.into());
+    }
+    // stderr keeps the machine readable output clean
+    eprintln!(
+        "{} target{} ({} excluded)",
+        targets.len(),
+        if targets.len() == 1 { "" } else { "s" },
+        excluded_num
+    );
+    Ok(targets
+        .into_iter()
+        .map(|(addr, hostname)| Target { addr, hostname })
+        .collect())
+}
+
+/// Fill in hostnames from reverse DNS, for up hosts only unless `all` is set (like nmap -R).
+pub fn reverse_dns(records: &mut [Record], all: bool) {
+    let mut addrs = Vec::new();
+    for (addr, host_records) in group_by_host(records) {
+        let named = host_records.iter().any(|r| r.hostname.is_some());
+        if !named && (all || host_state(&host_records) == "up") {
+            addrs.push(addr);
+        }
+    }
+    let names: HashMap<IpAddr, String> =
+        utils::run_jobs(addrs, THREADS_NUM, |addr| (addr, reverse_resolve(addr)))
+            .into_iter()
+            .filter_map(|(addr, name)| name.map(|n| (addr, n)))
+            .collect();
+    for r in records.iter_mut() {
+        if r.hostname.is_none() {
+            r.hostname = names.get(&r.target).cloned();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(expr: &str) -> Vec<String> {
+        parse_addrs(expr, false)
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|a| a.to_string())
+            .collect()
+    }
+
+    fn target_args(targets: &[&str], exclude: &[&str], excludefile: Option<String>) -> TargetArgs {
+        TargetArgs {
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            host: Vec::new(),
+            subnet: Vec::new(),
+            input_list: None,
+            exclude: exclude.iter().map(|e| e.to_string()).collect(),
+            excludefile,
+            resolve_all: false,
+            ipv6: false,
+        }
+    }
+
+    #[test]
+    fn test_cidr() {
+        assert_eq!(
+            addrs("192.168.1.5/30"),
+            ["192.168.1.4", "192.168.1.5", "192.168.1.6", "192.168.1.7"]
+        );
+        assert_eq!(addrs("10.0.0.1/32"), ["10.0.0.1"]);
+        assert_eq!(addrs("10.0.0.0/16").len(), 65536);
+        assert_eq!(
+            parse_addrs("10.0.0.0/8", false).unwrap().unwrap().len(),
+            1 << 24
+        );
+        assert_eq!(
+            addrs("fd00::/126"),
+            ["fd00::", "fd00::1", "fd00::2", "fd00::3"]
+        );
+        for expr in ["10.0.0.0/33", "10.0.0.0/x", "fd00::/129"] {
+            let e = parse_addrs(expr, false).unwrap_err();
+            assert!(e.is::<InvalidTargetError>(), "{}", expr);
+        }
+        for expr in ["0.0.0.0/0", "10.0.0.0/7", "fd00::/103"] {
+            let e = parse_addrs(expr, false).unwrap_err();
+            assert!(
+                matches!(
+                    e.downcast_ref::<ValidationError>(),
+                    Some(ValidationError::TooManyAddresses { .. })
+                ),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn test_octet_range() {
+        assert_eq!(addrs("10.0.0.1-3"), ["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+        assert_eq!(
+            addrs("10.0-1.1.1,5"),
+            ["10.0.1.1", "10.0.1.5", "10.1.1.1", "10.1.1.5"]
+        );
+        assert_eq!(addrs("10.0.0.-2"), ["10.0.0.0", "10.0.0.1", "10.0.0.2"]);
+        assert_eq!(addrs("10.0.0.254-"), ["10.0.0.254", "10.0.0.255"]);
+        assert_eq!(addrs("10.0.*.*").len(), 65536);
+        assert_eq!(
+            parse_addrs("10.*.*.*", false).unwrap().unwrap().len(),
+            1 << 24
+        );
+        for expr in ["10.0.0.5-1", "10.0.0.256", "10.0.0"] {
+            let e = parse_addrs(expr, false).unwrap_err();
+            assert!(e.is::<InvalidTargetError>(), "{}", expr);
+        }
+        let e = parse_addrs("*.*.*.*", false).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ValidationError>(),
+            Some(ValidationError::TooManyAddresses { .. })
+        ));
+    }
+
+    #[test]
+    fn test_exclude() {
+        let args = target_args(
+            &["10.0.0.0/28", "10.0.0.100"],
+            &["10.0.0.0/30", "10.0.0.5-6", "10.0.0.100"],
+            None,
+        );
+        let targets: Vec<String> = parse_targets(&args)
+            .unwrap()
+            .iter()
+            .map(|t| t.addr.to_string())
+            .collect();
+        assert_eq!(targets.len(), 16 - 4 - 2);
+        assert!(!targets.contains(&"10.0.0.0".to_string()));
+        assert!(!targets.contains(&"10.0.0.6".to_string()));
+        assert!(targets.contains(&"10.0.0.7".to_string()));
+
+        // excluded networks may be larger than any target
+        let args = target_args(&["10.0.0.1", "192.168.0.1"], &["10.0.0.0/8"], None);
+        let targets = parse_targets(&args).unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].addr.to_string(), "192.168.0.1");
+
+        let path = std::env::temp_dir().join(format!("pistolcli-exclude-{}", std::process::id()));
+        std::fs::write(&path, "# lab hosts\n192.168.0.0/24\n10.0.0.2 10.0.0.3\n").unwrap();
+        let args = target_args(
+            &["10.0.0.1-3", "192.168.0.1"],
+            &[],
+            Some(path.to_string_lossy().into_owned()),
+        );
+        let targets = parse_targets(&args);
+        std::fs::remove_file(&path).unwrap();
+        let targets = targets.unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].addr.to_string(), "10.0.0.1");
+
+        let args = target_args(&["10.0.0.1"], &["10.0.0.0/24"], None);
+        assert!(matches!(
+            parse_targets(&args)
+                .unwrap_err()
+                .downcast_ref::<ValidationError>(),
+            Some(ValidationError::NoTargets)
+        ));
+    }
+
+    #[test]
+    fn test_resolve_hostname() {
+        let targets = parse_targets(&target_args(&["localhost", "127.0.0.1"], &[], None)).unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].addr, IpAddr::from(Ipv4Addr::LOCALHOST));
+        assert_eq!(targets[0].hostname.as_deref(), Some("localhost"));
+    }
+
+    #[test]
+    fn test_read_exprs() {
+        let list = "# lab hosts\n\n10.0.0.1 10.0.0.2\t10.0.0.3\n  \n\
+                    192.168.0.0/24 # the office\n#10.0.0.9\nhost.example";
+        assert_eq!(
+            read_exprs(list.as_bytes()).unwrap(),
+            [
+                "10.0.0.1",
+                "10.0.0.2",
+                "10.0.0.3",
+                "192.168.0.0/24",
+                "host.example"
+            ]
+        );
+        assert!(read_exprs("".as_bytes()).unwrap().is_empty());
+        assert!(read_exprs("# nothing\n\n".as_bytes()).unwrap().is_empty());
+        // Windows line endings
+        assert_eq!(
+            read_exprs("10.0.0.1\r\n10.0.0.2\r\n".as_bytes()).unwrap(),
+            ["10.0.0.1", "10.0.0.2"]
+        );
+        // not UTF-8
+        assert!(read_exprs(&b"10.0.0.1\n\xff\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_read_input_list() {
+        // `-` reads the list from stdin
+        let stdin = "10.0.0.1 # first\n10.0.0.2\n".as_bytes();
+        assert_eq!(
+            read_input_list("-", stdin).unwrap(),
+            ["10.0.0.1", "10.0.0.2"]
+        );
+
+        let path = std::env::temp_dir().join(format!("pistolcli-list-{}", std::process::id()));
+        std::fs::write(&path, "10.0.0.3\n").unwrap();
+        let exprs = read_input_list(path.to_str().unwrap(), "10.0.0.1".as_bytes());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(exprs.unwrap(), ["10.0.0.3"]);
+
+        let err = read_input_list(path.to_str().unwrap(), "".as_bytes()).unwrap_err();
+        assert!(err.downcast_ref::<ReadInputListError>().is_some());
+    }
+}
@@ -0,0 +1,495 @@
+use crate::InvalidRateError;
+use crate::InvalidTimeError;
+use crate::TimingArgs;
+use crate::ValidationError;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The nmap default, `-T3`
+pub const DEFAULT_TEMPLATE: u8 = 3;
+/// Most probes `--min-rate` runs at once, the threads of `-T5`
+const MAX_MIN_RATE_THREADS: usize = 256;
+/// Packets pistol reads while it waits for a reply, other traffic uses some of them up
+const MAX_LOOP: u32 = 64;
+/// The shortest wait of one read, a zero read timeout blocks for good
+const MIN_READ_TIMEOUT: Duration = Duration::from_millis(10);
+/// Slowest rate, one probe in about 17 minutes, slower ones do not fit a duration
+const MIN_RATE: f64 = 0.001;
+
+/// How long to wait for replies, how often to probe again and how many probes run at once.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    /// A fixed timeout for every host instead of one that follows its round trip times
+    pub timeout: Option<Duration>,
+    /// How long pistol waits for one reply before a host has a round trip time
+    pub initial_rtt_timeout: Duration,
+    /// Bounds of the timeout that follows the round trip times
+    pub min_rtt_timeout: Duration,
+    pub max_rtt_timeout: Duration,
+    /// How often a probe without a response is sent again
+    pub max_retries: usize,
+    /// Probes running at the same time, 0 is as many as cpus
+    pub threads: usize,
+    /// At most this many probes per second
+    pub max_rate: Option<f64>,
+    /// Probes the max rate lets out at once
+    pub max_burst: u32,
+    /// At least this many probes per second
+    pub min_rate: Option<f64>,
+    /// Time to wait between two probes
+    pub scan_delay: Option<Duration>,
+}
+
+impl Timing {
+    /// Timing of an nmap style template from `-T0` (paranoid) to `-T5` (insane).
+    pub fn template(level: u8) -> Timing {
+        let (initial_ms, min_ms, max_ms, max_retries, threads) = match level {
+            0 => (5000, 100, 10000, 10, 1),
+            1 => (2000, 100, 10000, 10, 1),
+            2 => (500, 50, 5000, 10, 4),
+            3 => (100, 10, 1000, 2, 0),
+            4 => (50, 10, 500, 2, 64),
+            _ => (25, 5, 250, 0, 256),
+        };
+        Timing {
+            timeout: None,
+            initial_rtt_timeout: Duration::from_millis(initial_ms),
+            min_rtt_timeout: Duration::from_millis(min_ms),
+            max_rtt_timeout: Duration::from_millis(max_ms),
+            max_retries,
+            threads,
+            max_rate: None,
+            max_burst: 1,
+            min_rate: None,
+            scan_delay: None,
+        }
+    }
+
+    /// A template with the options that were set on top of it.
+    pub fn new(args: &TimingArgs) -> Result<Timing> {
+        let template = Timing::template(args.timing.unwrap_or(DEFAULT_TEMPLATE));
+        let mut timing = Timing {
+            timeout: args.timeout,
+            initial_rtt_timeout: args
+                .initial_rtt_timeout
+                .unwrap_or(template.initial_rtt_timeout),
+            min_rtt_timeout: args.min_rtt_timeout.unwrap_or(template.min_rtt_timeout),
+            max_rtt_timeout: args.max_rtt_timeout.unwrap_or(template.max_rtt_timeout),
+            max_retries: args.max_retries.unwrap_or(template.max_retries),
+            threads: args.threads.unwrap_or(template.threads),
+            max_rate: args.max_rate,
+            max_burst: args.max_burst.unwrap_or(template.max_burst),
+            min_rate: args.min_rate,
+            scan_delay: args.scan_delay,
+        };
+        if timing.min_rtt_timeout > timing.max_rtt_timeout {
+            return Err(ValidationError::RttTimeouts {
+                min: format!("{:?}", timing.min_rtt_timeout),
+                max: format!("{:?}", timing.max_rtt_timeout),
+            }
+            .into());
+        }
+        if let (Some(min), Some(max)) = (timing.min_rate, timing.max_rate) {
+            if min > max {
+                return Err(ValidationError::Rates {
+                    min: min.to_string(),
+                    max: max.to_string(),
+                }
+                .into());
+            }
+        }
+        if let Some(min_rate) = timing.min_rate {
+            // every probe may wait for its whole timeout, enough of them
+            // have to run at once to still send min rate probes per second,
+            // the thread pools start all their threads so the rate is best effort
+            let wait = timing.timeout.unwrap_or(timing.max_rtt_timeout);
+            let needed = (min_rate * wait.as_secs_f64()).ceil() as usize;
+            let threads = match timing.threads {
+                0 => num_cpus::get(),
+                n => n,
+            };
+            timing.threads = threads.max(needed.min(MAX_MIN_RATE_THREADS));
+        }
+        Ok(timing)
+    }
+
+    /// The shortest time between two probes, `None` when they are not paced.
+    pub fn probe_interval(&self) -> Option<Duration> {
+        let rate_interval = self
+            .max_rate
+            .map(|rate| Duration::from_secs_f64(1.0 / rate));
+        let interval = match (self.scan_delay, rate_interval) {
+            (Some(delay), Some(rate)) => delay.max(rate),
+            (delay, rate) => delay.or(rate)?,
+        };
+        // the min rate wins over a longer scan delay, like nmap
+        let interval = match self.min_rate {
+            Some(rate) => interval.min(Duration::from_secs_f64(1.0 / rate)),
+            None => interval,
+        };
+        Some(interval)
+    }
+
+    /// How many probes may go out at once, the scan delay always spaces them.
+    pub fn probe_burst(&self) -> u32 {
+        match self.scan_delay {
+            Some(_) => 1,
+            None => self.max_burst,
+        }
+    }
+}
+
+/// The read timeout and the number of loops that make pistol wait about `timeout`
+/// for a reply. pistol loops up to max loop times over its `reads` sockets and every
+/// read waits up to the timeout it is given, so the timeout is spread over the reads.
+pub fn pistol_wait(timeout: Duration, reads: u32) -> (Duration, usize) {
+    let reads = reads.max(1);
+    let loops = (timeout.as_secs_f64() / (MIN_READ_TIMEOUT * reads).as_secs_f64()) as u32;
+    let loops = loops.clamp(1, MAX_LOOP);
+    let read_timeout = (timeout / (loops * reads)).max(MIN_READ_TIMEOUT);
+    (read_timeout, loops as usize)
+}
+
+/// Paces the probes of all threads to the probe interval, a token bucket that
+/// holds `burst` tokens and gets one back every interval. The bucket is kept as
+/// the time it is full again, a probe takes a token by moving that time one interval on.
+#[derive(Debug)]
+pub struct Pacer {
+    interval: Duration,
+    burst: u32,
+    full_at: Mutex<Instant>,
+}
+
+impl Pacer {
+    pub fn new(interval: Duration, burst: u32) -> Pacer {
+        Pacer {
+            interval,
+            burst: burst.max(1),
+            full_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block until the next probe may be sent.
+    pub fn wait(&self) {
+        let now = Instant::now();
+        let send_at = {
+            let mut full_at = self.full_at.lock().unwrap_or_else(|e| e.into_inner());
+            let start = (*full_at).max(now);
+            // the bucket has a token once it is less than burst intervals from full
+            let send_at = start
+                .checked_sub(self.interval * (self.burst - 1))
+                .map_or(now, |t| t.max(now));
+            *full_at = start + self.interval;
+            send_at
+        };
+        thread::sleep(send_at - now);
+    }
+}
+
+/// Smoothed round trip time and its variation, like TCP (RFC 6298) and nmap.
+#[derive(Debug, Clone, Copy)]
+struct RttEstimate {
+    srtt: Duration,
+    rttvar: Duration,
+}
+
+/// The timeout of every host, following the round trip times measured so far.
+#[derive(Debug, Clone)]
+pub struct HostTimeouts {
+    timing: Timing,
+    estimates: Arc<Mutex<HashMap<IpAddr, RttEstimate>>>,
+}
+
+impl HostTimeouts {
+    pub fn new(timing: Timing) -> HostTimeouts {
+        HostTimeouts {
+            timing,
+            estimates: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn timeout(&self, addr: IpAddr) -> Duration {
+        if let Some(timeout) = self.timing.timeout {
+            return timeout;
+        }
+        let estimates = self.estimates.lock().unwrap_or_else(|e| e.into_inner());
+        match estimates.get(&addr) {
+            Some(e) => (e.srtt + 4 * e.rttvar)
+                .clamp(self.timing.min_rtt_timeout, self.timing.max_rtt_timeout),
+            None => self.timing.initial_rtt_timeout,
+        }
+    }
+
+    /// Add the round trip time of a probe that got a response.
+    pub fn update(&self, addr: IpAddr, rtt: Duration) {
+        let mut estimates = self.estimates.lock().unwrap_or_else(|e| e.into_inner());
+        let estimate = match estimates.get(&addr) {
+            Some(e) => {
+                let delta = e.srtt.abs_diff(rtt);
+                RttEstimate {
+                    srtt: e.srtt * 7 / 8 + rtt / 8,
+                    rttvar: e.rttvar * 3 / 4 + delta / 4,
+                }
+            }
+            None => RttEstimate {
+                srtt: rtt,
+                rttvar: rtt / 2,
+            },
+        };
+        estimates.insert(addr, estimate);
+    }
+}
+
+/// Parse an nmap style time like `500ms`, `2s`, `1.5m` or `1h`, seconds without a unit.
+/// Zero is refused, a timeout of zero never waits for a reply.
+pub fn parse_time(time: &str) -> Result<Duration> {
+    let split = time
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(time.len());
+    let (value, unit) = time.split_at(split);
+    let value: f64 = match value.parse() {
+        Ok(v) => v,
+        Err(_) => return Err(InvalidTimeError::new(time.to_string()).into()),
+    };
+    let seconds = match unit {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(InvalidTimeError::new(time.to_string()).into()),
+    };
+    // too large for a duration, or zero
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(d) if !d.is_zero() => Ok(d),
+        _ => Err(InvalidTimeError::new(time.to_string()).into()),
+    }
+}
+
+/// Parse a rate in probes per second, at least one probe in 1000 seconds.
+pub fn parse_rate(rate: &str) -> Result<f64> {
+    match rate.parse::<f64>() {
+        Ok(r) if r >= MIN_RATE && r.is_finite() => Ok(r),
+        _ => Err(InvalidRateError::new(rate.to_string()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        timing: TimingArgs,
+    }
+
+    fn timing(args: &[&str]) -> Result<Timing> {
+        let cli = Cli::try_parse_from([&["pistolcli"], args].concat())?;
+        Timing::new(&cli.timing)
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_time("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_time("2").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_time("1.5m").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_time("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_time("0.25").unwrap(), Duration::from_millis(250));
+        // a zero wait would never wait for a reply
+        for time in [
+            "0", "0ms", "0.0s", "", "s", "1d", "1 s", "-1s", "1.2.3", "fast", "1e400",
+        ] {
+            assert!(parse_time(time).is_err(), "{}", time);
+        }
+    }
+
+    #[test]
+    fn test_templates() {
+        let ms = Duration::from_millis;
+        let templates = [
+            (0, ms(5000), ms(100), ms(10000), 10, 1),
+            (1, ms(2000), ms(100), ms(10000), 10, 1),
+            (2, ms(500), ms(50), ms(5000), 10, 4),
+            (3, ms(100), ms(10), ms(1000), 2, 0),
+            (4, ms(50), ms(10), ms(500), 2, 64),
+            (5, ms(25), ms(5), ms(250), 0, 256),
+        ];
+        for (level, initial, min, max, retries, threads) in templates {
+            let t = Timing::template(level);
+            assert_eq!(
+                (
+                    t.initial_rtt_timeout,
+                    t.min_rtt_timeout,
+                    t.max_rtt_timeout,
+                    t.max_retries,
+                    t.threads
+                ),
+                (initial, min, max, retries, threads),
+                "-T{}",
+                level
+            );
+            assert_eq!(t.timeout, None);
+            assert_eq!(t.probe_interval(), None);
+        }
+        // -T3 without -T and the options win over the template
+        assert_eq!(timing(&[]).unwrap().max_retries, 2);
+        let t = timing(&["-T5", "--max-retries", "3", "--timeout", "2s"]).unwrap();
+        assert_eq!((t.max_retries, t.threads), (3, 256));
+        assert_eq!(t.timeout, Some(Duration::from_secs(2)));
+        assert!(timing(&["-T6"]).is_err());
+        assert!(timing(&["--min-rtt-timeout", "2s", "--max-rtt-timeout", "1s"]).is_err());
+    }
+
+    #[test]
+    fn test_pistol_wait() {
+        let ms = Duration::from_millis;
+        // the loops and reads add up to the timeout
+        assert_eq!(pistol_wait(ms(100), 2), (ms(10), 5));
+        assert_eq!(pistol_wait(ms(100), 1), (ms(10), 10));
+        assert_eq!(pistol_wait(ms(1000), 2), (ms(10), 50));
+        // at most max loop reads of a longer wait
+        assert_eq!(
+            pistol_wait(ms(5000), 2),
+            (
+                Duration::from_micros(39_062) + Duration::from_nanos(500),
+                64
+            )
+        );
+        // a short timeout still waits one read
+        assert_eq!(pistol_wait(ms(5), 2), (ms(10), 1));
+        assert_eq!(pistol_wait(Duration::ZERO, 2), (ms(10), 1));
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("100").unwrap(), 100.0);
+        assert_eq!(parse_rate("0.5").unwrap(), 0.5);
+        assert_eq!(parse_rate("0.001").unwrap(), 0.001);
+        for rate in ["0", "0.0001", "-1", "inf", "NaN", "fast", ""] {
+            assert!(parse_rate(rate).is_err(), "{}", rate);
+        }
+    }
+
+    #[test]
+    fn test_probe_interval() {
+        assert_eq!(timing(&[]).unwrap().probe_interval(), None);
+        let max_rate = timing(&["--max-rate", "100"]).unwrap();
+        assert_eq!(max_rate.probe_interval(), Some(Duration::from_millis(10)));
+        // the longer of the two
+        let both = timing(&["--max-rate", "100", "--scan-delay", "50ms"]).unwrap();
+        assert_eq!(both.probe_interval(), Some(Duration::from_millis(50)));
+        let both = timing(&["--max-rate", "10", "--scan-delay", "50ms"]).unwrap();
+        assert_eq!(both.probe_interval(), Some(Duration::from_millis(100)));
+        // the min rate shortens a longer scan delay
+        let min_rate = timing(&["--scan-delay", "1s", "--min-rate", "4"]).unwrap();
+        assert_eq!(min_rate.probe_interval(), Some(Duration::from_millis(250)));
+        // and paces nothing on its own
+        assert_eq!(timing(&["--min-rate", "4"]).unwrap().probe_interval(), None);
+        assert!(timing(&["--min-rate", "10", "--max-rate", "5"]).is_err());
+    }
+
+    #[test]
+    fn test_min_rate_threads() {
+        // 1 s of max rtt timeout at 100 probes per second needs 100 probes at once
+        let min_rate = timing(&["-T2", "--max-rtt-timeout", "1s", "--min-rate", "100"]).unwrap();
+        assert_eq!(min_rate.threads, 100);
+        let capped = timing(&["-T2", "--min-rate", "10000"]).unwrap();
+        assert_eq!(capped.threads, MAX_MIN_RATE_THREADS);
+        // never fewer than asked for
+        let threads = timing(&["--threads", "300", "--min-rate", "1"]).unwrap();
+        assert_eq!(threads.threads, 300);
+    }
+
+    #[test]
+    fn test_probe_burst() {
+        assert_eq!(timing(&[]).unwrap().probe_burst(), 1);
+        let burst = timing(&["--max-rate", "10", "--max-burst", "5"]).unwrap();
+        assert_eq!(burst.probe_burst(), 5);
+        let delay = timing(&["--scan-delay", "1s", "--max-burst", "5"]).unwrap();
+        assert_eq!(delay.probe_burst(), 1);
+        assert!(timing(&["--max-burst", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_host_timeouts() {
+        let ms = Duration::from_millis;
+        let host: IpAddr = "192.0.2.1".parse().unwrap();
+        let timeouts = HostTimeouts::new(Timing::template(3));
+        assert_eq!(timeouts.timeout(host), ms(100));
+        // the first rtt sets srtt and rttvar to half of it
+        timeouts.update(host, ms(40));
+        assert_eq!(timeouts.timeout(host), ms(40) + 4 * ms(20));
+        // rttvar = 3/4 * 20 + 1/4 * |40 - 80|, then srtt = 7/8 * 40 + 1/8 * 80
+        timeouts.update(host, ms(80));
+        assert_eq!(timeouts.timeout(host), ms(45) + 4 * ms(25));
+        // a steady rtt brings the timeout down towards it
+        for _ in 0..100 {
+            timeouts.update(host, ms(45));
+        }
+        assert!(timeouts.timeout(host) < ms(46));
+
+        // kept between the min and max rtt timeouts
+        let fast: IpAddr = "192.0.2.2".parse().unwrap();
+        timeouts.update(fast, ms(1));
+        assert_eq!(timeouts.timeout(fast), ms(10));
+        let slow: IpAddr = "192.0.2.3".parse().unwrap();
+        timeouts.update(slow, ms(2000));
+        assert_eq!(timeouts.timeout(slow), ms(1000));
+        // every host has its own
+        assert_eq!(timeouts.timeout("192.0.2.4".parse().unwrap()), ms(100));
+
+        // a fixed timeout does not follow the rtts
+        let fixed = HostTimeouts::new(timing(&["--timeout", "3s"]).unwrap());
+        fixed.update(host, ms(1));
+        assert_eq!(fixed.timeout(host), ms(3000));
+    }
+
+    /// The time `count` waits on the pacer take.
+    fn pace(pacer: &Pacer, count: usize) -> Duration {
+        let start = Instant::now();
+        for _ in 0..count {
+            pacer.wait();
+        }
+        start.elapsed()
+    }
+
+    #[test]
+    fn test_pacer() {
+        let interval = Duration::from_millis(20);
+        // the first probe goes out at once and the others one interval apart
+        let pacer = Pacer::new(interval, 1);
+        assert!(pace(&pacer, 1) < interval);
+        // measured a little after the first probe went out
+        assert!(pace(&pacer, 4) >= interval * 3);
+
+        // a burst goes out at once, then the bucket paces again
+        let pacer = Pacer::new(interval, 3);
+        assert!(pace(&pacer, 3) < interval);
+        assert!(pace(&pacer, 2) >= interval);
+        // after a quiet moment the bucket is full again
+        thread::sleep(interval * 3);
+        assert!(pace(&pacer, 3) < interval);
+
+        // threads share the bucket
+        let pacer = Arc::new(Pacer::new(interval, 1));
+        let start = Instant::now();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pacer = pacer.clone();
+                thread::spawn(move || pacer.wait())
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(start.elapsed() >= interval * 2);
+    }
+}
@@ -0,0 +1,404 @@
+use crate::output::Protocol;
+use crate::output::Record;
+use crate::timing::Timing;
+use crate::utils;
+use crate::x509::Certificate;
+use crate::TlsArgs;
+use anyhow::Result;
+use serde::Serialize;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::time::Duration;
+use std::time::Instant;
+
+/// TLS record content type of handshake messages
+const HANDSHAKE: u8 = 22;
+
+/* TLS handshake message types */
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const CERTIFICATE: u8 = 11;
+const SERVER_HELLO_DONE: u8 = 14;
+
+/// How long the server may take for its hello and certificate, like the 5s of the nmap SSL probes
+const HANDSHAKE_WAIT: Duration = Duration::from_secs(5);
+/// Certificate chains are a few KB, anything much larger is not a TLS server
+const MAX_FLIGHT_LEN: usize = 256 * 1024;
+
+/// Cipher suites offered to the server, by preference
+const CIPHER_SUITES: &[(u16, &str)] = &[
+    (0xc02b, "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"),
+    (0xc02f, "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"),
+    (0xc02c, "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"),
+    (0xc030, "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384"),
+    (0xcca9, "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256"),
+    (0xcca8, "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256"),
+    (0xc023, "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256"),
+    (0xc027, "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256"),
+    (0xc024, "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384"),
+    (0xc028, "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384"),
+    (0xc009, "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA"),
+    (0xc013, "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA"),
+    (0xc00a, "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA"),
+    (0xc014, "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA"),
+    (0x009e, "TLS_DHE_RSA_WITH_AES_128_GCM_SHA256"),
+    (0x009f, "TLS_DHE_RSA_WITH_AES_256_GCM_SHA384"),
+    (0x0033, "TLS_DHE_RSA_WITH_AES_128_CBC_SHA"),
+    (0x0039, "TLS_DHE_RSA_WITH_AES_256_CBC_SHA"),
+    (0x009c, "TLS_RSA_WITH_AES_128_GCM_SHA256"),
+    (0x009d, "TLS_RSA_WITH_AES_256_GCM_SHA384"),
+    (0x003c, "TLS_RSA_WITH_AES_128_CBC_SHA256"),
+    (0x003d, "TLS_RSA_WITH_AES_256_CBC_SHA256"),
+    (0x002f, "TLS_RSA_WITH_AES_128_CBC_SHA"),
+    (0x0035, "TLS_RSA_WITH_AES_256_CBC_SHA"),
+    (0x000a, "TLS_RSA_WITH_3DES_EDE_CBC_SHA"),
+];
+/// TLS_EMPTY_RENEGOTIATION_INFO_SCSV
+const RENEGOTIATION_SCSV: u16 = 0x00ff;
+
+/// x25519, secp256r1, secp384r1, secp521r1 and ffdhe2048
+const SUPPORTED_GROUPS: &[u16] = &[0x001d, 0x0017, 0x0018, 0x0019, 0x0100];
+/// ECDSA, RSA-PSS and RSA PKCS#1 with SHA-256, SHA-384 and SHA-512, and the SHA-1 ones
+const SIGNATURE_ALGORITHMS: &[u16] = &[
+    0x0403, 0x0503, 0x0603, 0x0804, 0x0805, 0x0806, 0x0401, 0x0501, 0x0601, 0x0203, 0x0201,
+];
+
+/// The TLS session and certificate of a port, found by the TLS probe.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TlsInfo {
+    /// Negotiated protocol version like `TLSv1.2`
+    pub version: String,
+    /// Negotiated cipher suite like `TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256`
+    pub cipher: String,
+    #[serde(flatten)]
+    pub certificate: Certificate,
+}
+
+impl TlsInfo {
+    /// Certificate details like the nmap ssl-cert script prints them, one per line.
+    pub fn ssl_cert_output(&self) -> String {
+        let c = &self.certificate;
+        let mut lines = vec![format!("Subject: {}", c.subject)];
+        if !c.alt_names.is_empty() {
+            lines.push(format!(
+                "Subject Alternative Name: {}",
+                c.alt_names.join(", ")
+            ));
+        }
+        lines.push(format!("Issuer: {}", c.issuer));
+        lines.push(format!("Public Key type: {}", c.key_type));
+        if let Some(bits) = c.key_bits {
+            lines.push(format!("Public Key bits: {}", bits));
+        }
+        lines.push(format!(
+            "Not valid before: {}",
+            c.not_before.format("%Y-%m-%dT%H:%M:%S")
+        ));
+        lines.push(format!(
+            "Not valid after:  {}",
+            c.not_after.format("%Y-%m-%dT%H:%M:%S")
+        ));
+        lines.push(format!("Protocol: {}", self.version));
+        lines.push(format!("Cipher: {}", self.cipher));
+        lines.join("\n")
+    }
+}
+
+fn version_name(version: u16) -> String {
+    match version {
+        0x0300 => "SSLv3".to_string(),
+        0x0301 => "TLSv1.0".to_string(),
+        0x0302 => "TLSv1.1".to_string(),
+        0x0303 => "TLSv1.2".to_string(),
+        _ => format!("0x{:04x}", version),
+    }
+}
+
+fn cipher_name(cipher: u16) -> String {
+    match CIPHER_SUITES.iter().find(|(id, _)| *id == cipher) {
+        Some((_, name)) => name.to_string(),
+        None => format!("0x{:04x}", cipher),
+    }
+}
+
+fn push_u16(buff: &mut Vec<u8>, value: usize) {
+    buff.extend_from_slice(&(value as u16).to_be_bytes());
+}
+
+fn push_u24(buff: &mut Vec<u8>, value: usize) {
+    buff.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+}
+
+fn u24(bytes: &[u8]) -> usize {
+    (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize
+}
+
+fn push_extension(extensions: &mut Vec<u8>, extension_type: u16, data: &[u8]) {
+    extensions.extend_from_slice(&extension_type.to_be_bytes());
+    push_u16(extensions, data.len());
+    extensions.extend_from_slice(data);
+}
+
+/// A list of 16 bit values with its 16 bit length in front.
+fn u16_list(values: &[u16]) -> Vec<u8> {
+    let mut ret = Vec::new();
+    push_u16(&mut ret, values.len() * 2);
+    for value in values {
+        ret.extend_from_slice(&value.to_be_bytes());
+    }
+    ret
+}
+
+/// TLS 1.2 ClientHello record, TLS 1.3 is not offered as it would encrypt the certificate.
+fn client_hello(server_name: Option<&str>) -> Vec<u8> {
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&rand::random::<[u8; 32]>());
+    // no session id
+    body.push(0);
+    let mut suites: Vec<u16> = CIPHER_SUITES.iter().map(|(id, _)| *id).collect();
+    suites.push(RENEGOTIATION_SCSV);
+    body.extend_from_slice(&u16_list(&suites));
+    // only the null compression
+    body.extend_from_slice(&[1, 0]);
+
+    let mut extensions = Vec::new();
+    if let Some(name) = server_name {
+        let mut list = vec![0];
+        push_u16(&mut list, name.len());
+        list.extend_from_slice(name.as_bytes());
+        let mut data = Vec::new();
+        push_u16(&mut data, list.len());
+        data.extend_from_slice(&list);
+        push_extension(&mut extensions, 0, &data);
+    }
+    push_extension(&mut extensions, 10, &u16_list(SUPPORTED_GROUPS));
+    // uncompressed EC points
+    push_extension(&mut extensions, 11, &[1, 0]);
+    push_extension(&mut extensions, 13, &u16_list(SIGNATURE_ALGORITHMS));
+    // extended master secret and an empty renegotiation info
+    push_extension(&mut extensions, 0x0017, &[]);
+    push_extension(&mut extensions, 0xff01, &[0]);
+    push_u16(&mut body, extensions.len());
+    body.extend_from_slice(&extensions);
+
+    let mut handshake = vec![CLIENT_HELLO];
+    push_u24(&mut handshake, body.len());
+    handshake.extend_from_slice(&body);
+    // the record layer says TLS 1.0 for old servers, like the browsers do
+    let mut record = vec![HANDSHAKE, 0x03, 0x01];
+    push_u16(&mut record, handshake.len());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+/// Version and cipher suite of a ServerHello.
+fn server_hello(body: &[u8]) -> Option<(u16, u16)> {
+    let version = u16::from_be_bytes([*body.first()?, *body.get(1)?]);
+    // after the version and the random comes the session id
+    let session_id_len = *body.get(34)? as usize;
+    let cipher = body.get(35 + session_id_len..37 + session_id_len)?;
+    Some((version, u16::from_be_bytes([cipher[0], cipher[1]])))
+}
+
+/// The DER certificates of a Certificate message, the server certificate first.
+fn certificates(body: &[u8]) -> Option<Vec<&[u8]>> {
+    let len = u24(body.get(..3)?);
+    let mut list = body.get(3..3 + len)?;
+    let mut ret = Vec::new();
+    while !list.is_empty() {
+        let len = u24(list.get(..3)?);
+        ret.push(list.get(3..3 + len)?);
+        list = &list[3 + len..];
+    }
+    Some(ret)
+}
+
+/// How far the first flight of the server got.
+#[derive(Debug, PartialEq)]
+enum Flight {
+    Incomplete,
+    Done(TlsInfo),
+    /// An alert, no certificate or not TLS at all
+    Failed,
+}
+
+/// Parse what the server sent so far, up to its certificate.
+fn parse_flight(input: &[u8]) -> Flight {
+    // the handshake messages can be split over records and records over reads
+    let mut handshake = Vec::new();
+    let mut input = input;
+    while !input.is_empty() {
+        // an alert or not TLS at all
+        if input[0] != HANDSHAKE {
+            return Flight::Failed;
+        }
+        if input.len() < 5 {
+            break;
+        }
+        let len = u16::from_be_bytes([input[3], input[4]]) as usize;
+        let Some(fragment) = input.get(5..5 + len) else {
+            break;
+        };
+        handshake.extend_from_slice(fragment);
+        input = &input[5 + len..];
+    }
+    let mut hello = None;
+    let mut messages = handshake.as_slice();
+    while messages.len() >= 4 {
+        let len = u24(&messages[1..4]);
+        let Some(body) = messages.get(4..4 + len) else {
+            break;
+        };
+        match messages[0] {
+            SERVER_HELLO => match server_hello(body) {
+                Some(h) => hello = Some(h),
+                None => return Flight::Failed,
+            },
+            CERTIFICATE => {
+                let (Some((version, cipher)), Some(certificates)) = (hello, certificates(body))
+                else {
+                    return Flight::Failed;
+                };
+                let Some(certificate) = certificates.first().and_then(|c| Certificate::parse(c))
+                else {
+                    return Flight::Failed;
+                };
+                return Flight::Done(TlsInfo {
+                    version: version_name(version),
+                    cipher: cipher_name(cipher),
+                    certificate,
+                });
+            }
+            // anonymous cipher suites come without a certificate
+            SERVER_HELLO_DONE => return Flight::Failed,
+            _ => (),
+        }
+        messages = &messages[4 + len..];
+    }
+    Flight::Incomplete
+}
+
+/// Start a TLS handshake and read the server certificate, None when the port does not speak TLS 1.2 or older.
+pub fn handshake(
+    addr: SocketAddr,
+    server_name: Option<&str>,
+    connect_timeout: Duration,
+) -> Option<TlsInfo> {
+    utils::pace();
+    let mut stream = TcpStream::connect_timeout(&addr, connect_timeout).ok()?;
+    stream.write_all(&client_hello(server_name)).ok()?;
+    let start = Instant::now();
+    let mut input = Vec::new();
+    let mut buff = [0u8; 4096];
+    while input.len() < MAX_FLIGHT_LEN {
+        let remaining = HANDSHAKE_WAIT
+            .checked_sub(start.elapsed())
+            .filter(|r| !r.is_zero())?;
+        stream.set_read_timeout(Some(remaining)).ok()?;
+        match stream.read(&mut buff) {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => input.extend_from_slice(&buff[..n]),
+        }
+        match parse_flight(&input) {
+            Flight::Incomplete => (),
+            Flight::Done(info) => return Some(info),
+            Flight::Failed => return None,
+        }
+    }
+    None
+}
+
+/// Inspect the TLS certificates of the open TCP ports in the results, when `--tls` is set.
+pub fn inspect_tls(records: &mut [Record], args: &TlsArgs, timing: Timing) -> Result<()> {
+    if !args.tls {
+        return Ok(());
+    }
+    let connect_timeout = timing.timeout.unwrap_or(timing.max_rtt_timeout);
+    let jobs: Vec<(usize, SocketAddr, Option<String>)> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.protocol == Protocol::Tcp && r.state == "open")
+        .filter_map(|(i, r)| {
+            r.port
+                .map(|port| (i, SocketAddr::new(r.target, port), r.hostname.clone()))
+        })
+        .collect();
+    let rets = utils::run_jobs(jobs, timing.threads, move |(i, addr, hostname)| {
+        (i, handshake(addr, hostname.as_deref(), connect_timeout))
+    });
+    for (i, tls) in rets {
+        records[i].tls = tls;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(content_type: u8, fragment: &[u8]) -> Vec<u8> {
+        let mut ret = vec![content_type, 0x03, 0x03];
+        push_u16(&mut ret, fragment.len());
+        ret.extend_from_slice(fragment);
+        ret
+    }
+
+    fn message(message_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut ret = vec![message_type];
+        push_u24(&mut ret, body.len());
+        ret.extend_from_slice(body);
+        ret
+    }
+
+    #[test]
+    fn test_client_hello() {
+        let hello = client_hello(Some("pistol.test"));
+        assert_eq!(hello[0], HANDSHAKE);
+        assert_eq!(
+            u16::from_be_bytes([hello[3], hello[4]]) as usize,
+            hello.len() - 5
+        );
+        assert_eq!(hello[5], CLIENT_HELLO);
+        assert_eq!(u24(&hello[6..9]), hello.len() - 9);
+        assert_eq!(&hello[9..11], &[0x03, 0x03]);
+        assert!(hello
+            .windows("pistol.test".len())
+            .any(|w| w == b"pistol.test"));
+    }
+
+    #[test]
+    fn test_parse_flight() {
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        hello.push(4);
+        hello.extend_from_slice(&[1, 2, 3, 4]);
+        hello.extend_from_slice(&[0xc0, 0x2f, 0]);
+        assert_eq!(server_hello(&hello), Some((0x0303, 0xc02f)));
+        let hello = message(SERVER_HELLO, &hello);
+
+        // a certificate the DER parser refuses ends the flight
+        let mut list = Vec::new();
+        push_u24(&mut list, 2);
+        list.extend_from_slice(&[0x30, 0x00]);
+        let mut body = Vec::new();
+        push_u24(&mut body, list.len());
+        body.extend_from_slice(&list);
+        assert_eq!(certificates(&body), Some(vec![&[0x30, 0x00][..]]));
+        let certificate = message(CERTIFICATE, &body);
+
+        let input = record(HANDSHAKE, &hello);
+        assert_eq!(parse_flight(&input), Flight::Incomplete);
+        assert_eq!(parse_flight(&input[..10]), Flight::Incomplete);
+        let mut input = input;
+        input.extend_from_slice(&record(HANDSHAKE, &certificate));
+        assert_eq!(parse_flight(&input), Flight::Failed);
+        // a fatal protocol_version alert
+        assert_eq!(parse_flight(&record(21, &[2, 70])), Flight::Failed);
+        assert_eq!(
+            parse_flight(b"HTTP/1.1 400 Bad Request\r\n"),
+            Flight::Failed
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::output::no_response;
+use crate::output::Record;
+use crate::timing::HostTimeouts;
+use crate::timing::Pacer;
+use crate::timing::Timing;
+use anyhow::Result;
+use pnet_packet::ip::IpNextHeaderProtocols;
+use pnet_transport::transport_channel;
+use pnet_transport::TransportChannelType::Layer4;
+use pnet_transport::TransportProtocol::Ipv4;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::UdpSocket;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
+use threadpool::ThreadPool;
+
+/// When `threads_num` is 0, use as many threads as cpus.
+pub fn get_threads_pool(threads_num: usize) -> ThreadPool {
+    if threads_num > 0 {
+        ThreadPool::new(threads_num)
+    } else {
+        ThreadPool::new(num_cpus::get())
+    }
+}
+
+/// Run `func` for every job on a threads pool, the results come back in no particular order.
+pub fn run_jobs<J, R, F>(jobs: Vec<J>, threads_num: usize, func: F) -> Vec<R>
+where
+    J: Send + 'static,
+    R: Send + 'static,
+    F: Fn(J) -> R + Send + Sync + 'static,
+{
+    let pool = get_threads_pool(threads_num);
+    let func = Arc::new(func);
+    let (tx, rx) = channel();
+    let recv_size = jobs.len();
+    for job in jobs {
+        let tx = tx.clone();
+        let func = func.clone();
+        pool.execute(move || {
+            let _ = tx.send(func(job));
+        });
+    }
+    // if a job panics we get fewer results instead of waiting forever
+    drop(tx);
+    rx.into_iter().take(recv_size).collect()
+}
+
+/// Run `probe` and run it again while `no_response` says it got no response,
+/// at most `max_retries` more times.
+pub fn retry<R, N, P>(max_retries: usize, no_response: N, probe: P) -> Result<R>
+where
+    N: Fn(&R) -> bool,
+    P: Fn() -> Result<R>,
+{
+    let mut ret = probe()?;
+    for _ in 0..max_retries {
+        if !no_response(&ret) {
+            break;
+        }
+        ret = probe()?;
+    }
+    Ok(ret)
+}
+
+/// Send a probe until it gets a response or runs out of retries,
+/// the round trip times of the responses adjust the timeout of the host.
+/// The time is taken around the whole send, less the time it takes to open the sockets.
+pub fn probe<R, S, F>(
+    addr: IpAddr,
+    timing: Timing,
+    timeouts: &HostTimeouts,
+    send: S,
+    to_records: F,
+) -> Result<Vec<Record>>
+where
+    S: Fn(Duration) -> Result<R>,
+    F: Fn(&R, Duration) -> Vec<Record>,
+{
+    retry(
+        timing.max_retries,
+        |r: &Vec<Record>| no_response(r),
+        || {
+            pace();
+            let start = Instant::now();
+            let ret = send(timeouts.timeout(addr))?;
+            let rtt = start.elapsed().saturating_sub(setup_time());
+            let records = to_records(&ret, rtt);
+            if !no_response(&records) {
+                timeouts.update(addr, rtt);
+            }
+            Ok(records)
+        },
+    )
+}
+
+/// How long a probe takes to open and close its raw sockets, measured once
+static SETUP_TIME: OnceLock<Duration> = OnceLock::new();
+
+/// Every probe opens its own raw sockets before it sends and closes them after the reply,
+/// time the TCP and ICMP sockets pistol opens and take the fastest of a few tries.
+fn setup_time() -> Duration {
+    *SETUP_TIME.get_or_init(|| {
+        (0..3)
+            .filter_map(|_| {
+                let start = Instant::now();
+                let tcp = transport_channel(4096, Layer4(Ipv4(IpNextHeaderProtocols::Tcp))).ok()?;
+                let icmp =
+                    transport_channel(4096, Layer4(Ipv4(IpNextHeaderProtocols::Icmp))).ok()?;
+                drop((tcp, icmp));
+                Some(start.elapsed())
+            })
+            .min()
+            // without raw sockets nothing is sent either
+            .unwrap_or_default()
+    })
+}
+
+/// Paces the probes of the whole run, not set when they are not paced
+static PACER: OnceLock<Pacer> = OnceLock::new();
+
+pub fn init_pacer(timing: &Timing) {
+    if let Some(interval) = timing.probe_interval() {
+        let _ = PACER.set(Pacer::new(interval, timing.probe_burst()));
+    }
+}
+
+/// Wait until the next probe may be sent, `--max-rate` and `--scan-delay` pace all threads.
+pub fn pace() {
+    if let Some(pacer) = PACER.get() {
+        pacer.wait();
+    }
+}
+
+/// Jobs that failed without failing the whole run
+static FAILED_JOBS: AtomicUsize = AtomicUsize::new(0);
+
+/// The results of the jobs that succeeded, the failed ones are reported on stderr.
+/// Only when every job failed the run fails, with the first error.
+pub fn collect_jobs<R>(rets: Vec<Result<R>>) -> Result<Vec<R>> {
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    for ret in rets {
+        match ret {
+            Ok(r) => results.push(r),
+            Err(e) => errors.push(e),
+        }
+    }
+    if results.is_empty() && !errors.is_empty() {
+        return Err(errors.remove(0));
+    }
+    for e in &errors {
+        eprintln!("{}", e);
+    }
+    FAILED_JOBS.fetch_add(errors.len(), Ordering::Relaxed);
+    Ok(results)
+}
+
+pub fn failed_jobs() -> usize {
+    FAILED_JOBS.load(Ordering::Relaxed)
+}
+
+/// The source address of the IPv4 packets we build ourselves: the source host,
+/// the address of the interface or the one the system routes to `dst` with.
+pub fn source4(src: Option<Ipv4Addr>, dst: Ipv4Addr, interface: Option<&str>) -> Option<Ipv4Addr> {
+    let interface_source4 = || match interface.and_then(|name| interface_source(name, false)) {
+        Some(IpAddr::V4(src)) => Some(src),
+        _ => None,
+    };
+    src.or_else(interface_source4)
+        .or_else(|| route_source4(dst))
+}
+
+/// The source address the system routes to `dst` with, for the packets we build ourselves.
+pub fn route_source4(dst: Ipv4Addr) -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((dst, 9)).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(src) => Some(src),
+        IpAddr::V6(_) => None,
+    }
+}
+
+/// The source address the system routes to `dst` with,
+/// pistol can not find the IPv6 address of an interface by itself.
+pub fn route_source6(dst: Ipv6Addr) -> Option<Ipv6Addr> {
+    let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).ok()?;
+    // connecting a UDP socket sends nothing, it only picks the route
+    socket.connect((dst, 9)).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V6(src) => Some(src),
+        IpAddr::V4(_) => None,
+    }
+}
+
+/// The address of this family on the interface, for the sockets of the system,
+/// a link-local IPv6 address would need the scope of the interface too.
+pub fn interface_source(name: &str, ipv6: bool) -> Option<IpAddr> {
+    let interface = pnet_datalink::interfaces()
+        .into_iter()
+        .find(|i| i.name == name)?;
+    interface
+        .ips
+        .iter()
+        .map(|network| network.ip())
+        .find(|ip| match ip {
+            IpAddr::V4(_) => !ipv6,
+            IpAddr::V6(ipv6_addr) => ipv6 && ipv6_addr.segments()[0] & 0xffc0 != 0xfe80,
+        })
+}
@@ -0,0 +1,429 @@
+use crate::output::Protocol;
+use crate::targets::Target;
+use crate::GetTargetPortFailed;
+use crate::IdleScanValueError;
+use crate::ValidationError;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+
+/// Everything a technique is started with, as given on the command line.
+#[derive(Debug, Default)]
+pub struct Inputs<'a> {
+    pub targets: &'a [Target],
+    pub ports: Option<&'a BTreeSet<u16>>,
+    pub interface: Option<&'a str>,
+    pub source_host: Option<IpAddr>,
+    pub zombie_host: Option<Ipv4Addr>,
+    /// `-O` after the scan, it fingerprints IPv4 hosts only
+    pub os_detection: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Technique {
+    TcpScan,
+    UdpScan,
+    IdleScan,
+    IpScan,
+    Ping,
+    /// The ICMP timestamp and address mask pings
+    IcmpQuery,
+    ArpPing,
+    Flood,
+}
+
+/// What a technique needs besides its targets.
+#[derive(Debug, Clone, Copy, Default)]
+struct Requirements {
+    /// Ports of this protocol have to be set
+    ports: Option<Protocol>,
+    /// pistol sends IPv4 packets from the address of the interface or the source host
+    interface: bool,
+    /// pistol has no IPv6 version of the technique
+    ipv4_only: bool,
+    /// The zombie of an idle scan must not be a target
+    zombie: bool,
+}
+
+impl Technique {
+    fn name(self) -> &'static str {
+        match self {
+            Technique::TcpScan => "tcp scan",
+            Technique::UdpScan => "udp scan",
+            Technique::IdleScan => "idle scan",
+            Technique::IpScan => "ip protocol scan",
+            Technique::Ping => "ping",
+            Technique::IcmpQuery => "icmp timestamp and address mask ping",
+            Technique::ArpPing => "arp ping",
+            Technique::Flood => "flood",
+        }
+    }
+
+    fn requirements(self) -> Requirements {
+        match self {
+            Technique::TcpScan => Requirements {
+                ports: Some(Protocol::Tcp),
+                interface: true,
+                ..Default::default()
+            },
+            Technique::UdpScan => Requirements {
+                ports: Some(Protocol::Udp),
+                interface: true,
+                ..Default::default()
+            },
+            Technique::IdleScan => Requirements {
+                ports: Some(Protocol::Tcp),
+                interface: true,
+                ipv4_only: true,
+                zombie: true,
+            },
+            Technique::IpScan => Requirements {
+                interface: true,
+                ipv4_only: true,
+                ..Default::default()
+            },
+            // ARP finds the interface of the target by itself
+            Technique::ArpPing => Requirements {
+                ipv4_only: true,
+                ..Default::default()
+            },
+            // they send their own IPv4 packets from any source address
+            Technique::IcmpQuery => Requirements {
+                ipv4_only: true,
+                ..Default::default()
+            },
+            Technique::Ping | Technique::Flood => Requirements {
+                interface: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Check the inputs of a technique before anything is sent.
+pub fn validate(technique: Technique, inputs: &Inputs) -> Result<(), ValidationError> {
+    let requirements = technique.requirements();
+    if requirements.ipv4_only && inputs.targets.iter().any(|t| t.addr.is_ipv6()) {
+        return Err(ValidationError::Ipv6NotSupported {
+            method: technique.name().to_string(),
+        });
+    }
+    if inputs.os_detection && inputs.targets.iter().any(|t| t.addr.is_ipv6()) {
+        return Err(ValidationError::Ipv6NotSupported {
+            method: "OS detection".to_string(),
+        });
+    }
+    if let Some(source_host) = inputs.source_host {
+        if let Some(t) = inputs
+            .targets
+            .iter()
+            .find(|t| t.addr.is_ipv6() != source_host.is_ipv6())
+        {
+            return Err(ValidationError::AddressFamily {
+                addr: t.addr.to_string(),
+            });
+        }
+    }
+    if let Some(protocol) = requirements.ports {
+        match inputs.ports {
+            None => return Err(GetTargetPortFailed::NoPort.into()),
+            Some(ports) if ports.is_empty() => {
+                let protocol = match protocol {
+                    Protocol::Udp => "udp",
+                    _ => "tcp",
+                };
+                return Err(GetTargetPortFailed::NoProtocolPort {
+                    protocol: protocol.to_string(),
+                }
+                .into());
+            }
+            Some(_) => (),
+        }
+    }
+    // IPv6 sources are looked up from the routing table instead
+    if requirements.interface
+        && inputs.interface.is_none()
+        && inputs.source_host.is_none()
+        && inputs.targets.iter().any(|t| t.addr.is_ipv4())
+    {
+        return Err(ValidationError::MissingInterface {
+            method: technique.name().to_string(),
+        });
+    }
+    if requirements.zombie {
+        let Some(zombie_host) = inputs.zombie_host else {
+            return Err(IdleScanValueError::NoZombie.into());
+        };
+        if inputs
+            .targets
+            .iter()
+            .any(|t| t.addr == IpAddr::V4(zombie_host))
+        {
+            return Err(IdleScanValueError::ZombieIsTarget {
+                zombie: zombie_host.to_string(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+
+    fn target(addr: &str) -> Target {
+        Target {
+            addr: addr.parse().unwrap(),
+            hostname: None,
+        }
+    }
+
+    fn ports(ports: &[u16]) -> BTreeSet<u16> {
+        ports.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_valid_inputs() {
+        let targets = [target("192.168.1.1"), target("192.168.1.2")];
+        let ports = ports(&[22, 80]);
+        let inputs = Inputs {
+            targets: &targets,
+            ports: Some(&ports),
+            interface: Some("eth0"),
+            zombie_host: Some("192.168.1.3".parse().unwrap()),
+            ..Default::default()
+        };
+        for technique in [
+            Technique::TcpScan,
+            Technique::UdpScan,
+            Technique::IdleScan,
+            Technique::IpScan,
+            Technique::Ping,
+            Technique::IcmpQuery,
+            Technique::ArpPing,
+            Technique::Flood,
+        ] {
+            assert_eq!(validate(technique, &inputs), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_missing_port() {
+        let targets = [target("192.168.1.1")];
+        let empty = ports(&[]);
+        for (technique, protocol) in [
+            (Technique::TcpScan, "tcp"),
+            (Technique::UdpScan, "udp"),
+            (Technique::IdleScan, "tcp"),
+        ] {
+            let no_protocol_port = GetTargetPortFailed::NoProtocolPort {
+                protocol: protocol.to_string(),
+            };
+            for (ports, error) in [
+                (None, GetTargetPortFailed::NoPort),
+                (Some(&empty), no_protocol_port),
+            ] {
+                let inputs = Inputs {
+                    targets: &targets,
+                    ports,
+                    interface: Some("eth0"),
+                    zombie_host: Some("192.168.1.3".parse().unwrap()),
+                    ..Default::default()
+                };
+                assert_eq!(validate(technique, &inputs), Err(error.into()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_ports_not_needed() {
+        let targets = [target("192.168.1.1")];
+        let inputs = Inputs {
+            targets: &targets,
+            interface: Some("eth0"),
+            ..Default::default()
+        };
+        for technique in [Technique::IpScan, Technique::Ping, Technique::Flood] {
+            assert_eq!(validate(technique, &inputs), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_missing_interface() {
+        let targets = [target("192.168.1.1")];
+        let ports = ports(&[80]);
+        for technique in [
+            Technique::TcpScan,
+            Technique::UdpScan,
+            Technique::IdleScan,
+            Technique::IpScan,
+            Technique::Ping,
+            Technique::Flood,
+        ] {
+            let inputs = Inputs {
+                targets: &targets,
+                ports: Some(&ports),
+                zombie_host: Some("192.168.1.3".parse().unwrap()),
+                ..Default::default()
+            };
+            assert_eq!(
+                validate(technique, &inputs),
+                Err(ValidationError::MissingInterface {
+                    method: technique.name().to_string()
+                })
+            );
+            // a source host works as well
+            let inputs = Inputs {
+                source_host: Some("192.168.1.100".parse().unwrap()),
+                ..inputs
+            };
+            assert_eq!(validate(technique, &inputs), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_interface_not_needed() {
+        let targets = [target("192.168.1.1")];
+        for technique in [Technique::ArpPing, Technique::IcmpQuery] {
+            let inputs = Inputs {
+                targets: &targets,
+                ..Default::default()
+            };
+            assert_eq!(validate(technique, &inputs), Ok(()));
+        }
+        // the IPv6 source address comes from the routing table
+        let targets = [target("fd00::1")];
+        let ports = ports(&[80]);
+        let inputs = Inputs {
+            targets: &targets,
+            ports: Some(&ports),
+            ..Default::default()
+        };
+        assert_eq!(validate(Technique::TcpScan, &inputs), Ok(()));
+    }
+
+    #[test]
+    fn test_ipv6_not_supported() {
+        let targets = [target("192.168.1.1"), target("fd00::1")];
+        let ports = ports(&[80]);
+        for technique in [
+            Technique::IdleScan,
+            Technique::IpScan,
+            Technique::ArpPing,
+            Technique::IcmpQuery,
+        ] {
+            let inputs = Inputs {
+                targets: &targets,
+                ports: Some(&ports),
+                interface: Some("eth0"),
+                ..Default::default()
+            };
+            assert_eq!(
+                validate(technique, &inputs),
+                Err(ValidationError::Ipv6NotSupported {
+                    method: technique.name().to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_os_detection_ipv6() {
+        let targets = [target("192.168.1.1"), target("fd00::1")];
+        let ports = ports(&[80]);
+        let inputs = Inputs {
+            targets: &targets,
+            ports: Some(&ports),
+            interface: Some("eth0"),
+            os_detection: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            validate(Technique::TcpScan, &inputs),
+            Err(ValidationError::Ipv6NotSupported {
+                method: "OS detection".to_string()
+            })
+        );
+        let inputs = Inputs {
+            targets: &targets[..1],
+            ..inputs
+        };
+        assert_eq!(validate(Technique::TcpScan, &inputs), Ok(()));
+    }
+
+    #[test]
+    fn test_source_host_family() {
+        let targets = [target("192.168.1.1"), target("fd00::1")];
+        let ports = ports(&[80]);
+        for (source_host, addr) in [("192.168.1.100", "fd00::1"), ("fd00::100", "192.168.1.1")] {
+            let inputs = Inputs {
+                targets: &targets,
+                ports: Some(&ports),
+                source_host: Some(source_host.parse().unwrap()),
+                ..Default::default()
+            };
+            assert_eq!(
+                validate(Technique::TcpScan, &inputs),
+                Err(ValidationError::AddressFamily {
+                    addr: addr.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_zombie_is_target() {
+        let targets = [target("192.168.1.1"), target("192.168.1.2")];
+        let ports = ports(&[80]);
+        let inputs = Inputs {
+            targets: &targets,
+            ports: Some(&ports),
+            interface: Some("eth0"),
+            zombie_host: Some("192.168.1.2".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate(Technique::IdleScan, &inputs),
+            Err(IdleScanValueError::ZombieIsTarget {
+                zombie: "192.168.1.2".to_string()
+            }
+            .into())
+        );
+        let inputs = Inputs {
+            zombie_host: None,
+            ..inputs
+        };
+        assert_eq!(
+            validate(Technique::IdleScan, &inputs),
+            Err(IdleScanValueError::NoZombie.into())
+        );
+    }
+
+    #[test]
+    fn test_missing_arguments() {
+        // missing arguments are refused by clap before validation
+        for args in [
+            "pistolcli scan syn -p 80 -i eth0",
+            "pistolcli scan syn 192.168.1.1 -i eth0",
+            "pistolcli scan syn 192.168.1.1 -p 80 --top-ports 10",
+            "pistolcli scan idle 192.168.1.1 -p 80 --zombie-port 80",
+            "pistolcli scan idle 192.168.1.1 -p 80 --zombie-host 192.168.1.2",
+            "pistolcli scan ip -i eth0",
+            "pistolcli scan arp --subnet 192.168.1.0/24",
+            "pistolcli ping icmp -i eth0",
+            "pistolcli ping arp",
+            "pistolcli flood syn -p 80",
+            "pistolcli scan",
+            "pistolcli",
+        ] {
+            assert!(
+                Args::try_parse_from(args.split_whitespace()).is_err(),
+                "{}",
+                args
+            );
+        }
+    }
+}
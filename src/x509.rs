@@ -0,0 +1,386 @@
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+/* DER tags */
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const BMP_STRING: u8 = 0x1e;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+/// `[0]` version of the TBS certificate
+const VERSION: u8 = 0xa0;
+/// `[3]` extensions of the TBS certificate
+const EXTENSIONS: u8 = 0xa3;
+
+/* General names of the subject alternative name */
+const RFC822_NAME: u8 = 0x81;
+const DNS_NAME: u8 = 0x82;
+const URI: u8 = 0x86;
+const IP_ADDRESS: u8 = 0x87;
+
+const SUBJECT_ALT_NAME: &str = "2.5.29.17";
+
+/// The attribute names of distinguished names, like nmap prints them
+const ATTRIBUTES: &[(&str, &str)] = &[
+    ("2.5.4.3", "commonName"),
+    ("2.5.4.5", "serialNumber"),
+    ("2.5.4.6", "countryName"),
+    ("2.5.4.7", "localityName"),
+    ("2.5.4.8", "stateOrProvinceName"),
+    ("2.5.4.10", "organizationName"),
+    ("2.5.4.11", "organizationalUnitName"),
+    ("1.2.840.113549.1.9.1", "emailAddress"),
+    ("0.9.2342.19200300.100.1.25", "domainComponent"),
+];
+
+/* Public key algorithms */
+const RSA: &str = "1.2.840.113549.1.1.1";
+const DSA: &str = "1.2.840.10040.4.1";
+const EC: &str = "1.2.840.10045.2.1";
+const ED25519: &str = "1.3.101.112";
+const ED448: &str = "1.3.101.113";
+
+/// The named curves of EC keys and their sizes
+const CURVES: &[(&str, u32)] = &[
+    ("1.2.840.10045.3.1.7", 256),
+    ("1.3.132.0.34", 384),
+    ("1.3.132.0.35", 521),
+    ("1.3.132.0.10", 256),
+];
+
+/// What the TLS probe reports of a certificate.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alt_names: Vec<String>,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    /// `rsa`, `ec`, `dsa`, `ed25519`, `ed448` or the algorithm OID
+    pub key_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_bits: Option<u32>,
+    /// The subject is the issuer
+    pub self_signed: bool,
+}
+
+/// A DER element.
+struct Der<'a> {
+    tag: u8,
+    contents: &'a [u8],
+}
+
+/// Reads the elements of DER contents one after the other.
+struct Reader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(input: &'a [u8]) -> Reader<'a> {
+        Reader { input }
+    }
+    fn next(&mut self) -> Option<Der<'a>> {
+        let (&tag, rest) = self.input.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        // short form below 0x80, otherwise the number of length bytes
+        let (len, rest) = if first < 0x80 {
+            (first as usize, rest)
+        } else {
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 || rest.len() < n {
+                return None;
+            }
+            let len = rest[..n]
+                .iter()
+                .fold(0usize, |len, &b| len << 8 | b as usize);
+            (len, &rest[n..])
+        };
+        if rest.len() < len {
+            return None;
+        }
+        self.input = &rest[len..];
+        Some(Der {
+            tag,
+            contents: &rest[..len],
+        })
+    }
+    /// The contents of the next element, when it has this tag.
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        self.next().filter(|d| d.tag == tag).map(|d| d.contents)
+    }
+    fn peek_tag(&self) -> Option<u8> {
+        self.input.first().copied()
+    }
+}
+
+/// Dotted OID like `2.5.4.3`.
+fn oid(contents: &[u8]) -> String {
+    let mut arcs: Vec<u64> = Vec::new();
+    let mut value = 0u64;
+    for &b in contents {
+        value = value << 7 | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                // the first two arcs share the first value
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    let arcs: Vec<String> = arcs.iter().map(|a| a.to_string()).collect();
+    arcs.join(".")
+}
+
+fn string(der: &Der) -> String {
+    match der.tag {
+        BMP_STRING => {
+            let units: Vec<u16> = der
+                .contents
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(der.contents).into_owned(),
+    }
+}
+
+/// Distinguished name like nmap prints it, `commonName=example.com/organizationName=Example`.
+fn name(contents: &[u8]) -> String {
+    let mut parts = Vec::new();
+    let mut rdns = Reader::new(contents);
+    while let Some(rdn) = rdns.next() {
+        let mut attributes = Reader::new(rdn.contents);
+        while let Some(attribute) = attributes.next() {
+            let mut r = Reader::new(attribute.contents);
+            let (Some(id), Some(value)) = (r.expect(OID), r.next()) else {
+                continue;
+            };
+            let id = oid(id);
+            let key = match ATTRIBUTES.iter().find(|(o, _)| *o == id) {
+                Some((_, name)) => name.to_string(),
+                None => id,
+            };
+            parts.push(format!("{}={}", key, string(&value)));
+        }
+    }
+    parts.join("/")
+}
+
+fn time(der: Der) -> Option<DateTime<Utc>> {
+    let s = std::str::from_utf8(der.contents).ok()?.strip_suffix('Z')?;
+    let s = match der.tag {
+        // two digit years from 1950 to 2049
+        UTC_TIME => {
+            let year: u32 = s.get(..2)?.parse().ok()?;
+            format!("{}{}", if year < 50 { "20" } else { "19" }, s)
+        }
+        GENERALIZED_TIME => s.to_string(),
+        _ => return None,
+    };
+    let time = NaiveDateTime::parse_from_str(&s, "%Y%m%d%H%M%S").ok()?;
+    Some(time.and_utc())
+}
+
+/// Bits of a big endian unsigned integer.
+fn bits(integer: &[u8]) -> u32 {
+    let Some(start) = integer.iter().position(|&b| b != 0) else {
+        return 0;
+    };
+    let integer = &integer[start..];
+    (integer.len() as u32 - 1) * 8 + (8 - integer[0].leading_zeros())
+}
+
+/// Type and size of the subject public key info.
+fn public_key(contents: &[u8]) -> Option<(String, Option<u32>)> {
+    let mut r = Reader::new(contents);
+    let mut algorithm = Reader::new(r.expect(SEQUENCE)?);
+    let id = oid(algorithm.expect(OID)?);
+    let params = algorithm.next();
+    // the first byte of a bit string counts the unused bits
+    let key = r.expect(BIT_STRING)?.get(1..)?;
+    let ret = match id.as_str() {
+        RSA => {
+            let mut k = Reader::new(Reader::new(key).expect(SEQUENCE)?);
+            ("rsa".to_string(), Some(bits(k.expect(INTEGER)?)))
+        }
+        DSA => {
+            let p = params
+                .filter(|p| p.tag == SEQUENCE)
+                .and_then(|p| Reader::new(p.contents).expect(INTEGER));
+            ("dsa".to_string(), p.map(bits))
+        }
+        EC => {
+            let curve = params.filter(|p| p.tag == OID).map(|p| oid(p.contents));
+            let size = CURVES
+                .iter()
+                .find(|(c, _)| Some(*c) == curve.as_deref())
+                .map(|(_, size)| *size);
+            ("ec".to_string(), size)
+        }
+        ED25519 => ("ed25519".to_string(), Some(256)),
+        ED448 => ("ed448".to_string(), Some(456)),
+        _ => (id, None),
+    };
+    Some(ret)
+}
+
+/// The names of the subject alternative name extension, like `DNS:example.com`.
+fn alt_names(contents: &[u8]) -> Vec<String> {
+    let mut ret = Vec::new();
+    let Some(extensions) = Reader::new(contents).expect(SEQUENCE) else {
+        return ret;
+    };
+    let mut extensions = Reader::new(extensions);
+    while let Some(extension) = extensions.next() {
+        let mut r = Reader::new(extension.contents);
+        if r.expect(OID).map(oid).as_deref() != Some(SUBJECT_ALT_NAME) {
+            continue;
+        }
+        if r.peek_tag() == Some(BOOLEAN) {
+            r.next();
+        }
+        let Some(names) = r
+            .expect(OCTET_STRING)
+            .and_then(|value| Reader::new(value).expect(SEQUENCE))
+        else {
+            continue;
+        };
+        let mut names = Reader::new(names);
+        while let Some(name) = names.next() {
+            let value = String::from_utf8_lossy(name.contents);
+            match name.tag {
+                DNS_NAME => ret.push(format!("DNS:{}", value)),
+                RFC822_NAME => ret.push(format!("email:{}", value)),
+                URI => ret.push(format!("URI:{}", value)),
+                IP_ADDRESS => {
+                    if let Ok(octets) = <[u8; 4]>::try_from(name.contents) {
+                        ret.push(format!("IP Address:{}", Ipv4Addr::from(octets)));
+                    } else if let Ok(octets) = <[u8; 16]>::try_from(name.contents) {
+                        ret.push(format!("IP Address:{}", Ipv6Addr::from(octets)));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    ret
+}
+
+impl Certificate {
+    /// Parse a DER encoded X.509 certificate.
+    pub fn parse(der: &[u8]) -> Option<Certificate> {
+        let mut certificate = Reader::new(Reader::new(der).expect(SEQUENCE)?);
+        let mut tbs = Reader::new(certificate.expect(SEQUENCE)?);
+        if tbs.peek_tag() == Some(VERSION) {
+            tbs.next();
+        }
+        // serial number and signature algorithm
+        tbs.expect(INTEGER)?;
+        tbs.expect(SEQUENCE)?;
+        let issuer = tbs.expect(SEQUENCE)?;
+        let mut validity = Reader::new(tbs.expect(SEQUENCE)?);
+        let not_before = time(validity.next()?)?;
+        let not_after = time(validity.next()?)?;
+        let subject = tbs.expect(SEQUENCE)?;
+        let (key_type, key_bits) = public_key(tbs.expect(SEQUENCE)?)?;
+        let mut names = Vec::new();
+        while let Some(der) = tbs.next() {
+            if der.tag == EXTENSIONS {
+                names = alt_names(der.contents);
+            }
+        }
+        Some(Certificate {
+            subject: name(subject),
+            issuer: name(issuer),
+            alt_names: names,
+            not_before,
+            not_after,
+            key_type,
+            key_bits,
+            self_signed: subject == issuer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed P-256 certificate of `pistol.test` with DNS and IP alternative names
+    const EC_CERTIFICATE: &str = concat!(
+        "308201c030820167a003020102020101300a06082a8648ce3d04030230273114301206035504030c",
+        "0b706973746f6c2e74657374310f300d060355040a0c06506973746f6c301e170d32363031303130",
+        "30303030305a170d3237303130313030303030305a30273114301206035504030c0b706973746f6c",
+        "2e74657374310f300d060355040a0c06506973746f6c3059301306072a8648ce3d020106082a8648",
+        "ce3d03010703420004b912a640590fa21cfc18b8543b23d941985815f3204ebfc5ca12a29e946cd2",
+        "a2d61f86545bd800add965078eaf9c1339d0e0b6364e9103d94c22d4ab4c0ce6ffa3818330818030",
+        "1d0603551d0e0416041433a9bd92d1b2870e606d5e8958ba6e5291777df6301f0603551d23041830",
+        "16801433a9bd92d1b2870e606d5e8958ba6e5291777df6300f0603551d130101ff040530030101ff",
+        "302d0603551d1104263024820b706973746f6c2e74657374820f7777772e706973746f6c2e746573",
+        "7487047f000001300a06082a8648ce3d0403020347003044022075624eceba3a878b7f57c16959f3",
+        "2b2b6c10aecd61185c4d39452edb91153d66022042edfeddc065729aa80b535931b96c7e9cd01e4f",
+        "8ff536120e2d08ce0a1731ca",
+    );
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_oid() {
+        assert_eq!(oid(&[0x55, 0x04, 0x03]), "2.5.4.3");
+        assert_eq!(
+            oid(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01]),
+            RSA
+        );
+        assert_eq!(bits(&[0x00, 0xc0, 0x00]), 16);
+        assert_eq!(bits(&[0x01]), 1);
+    }
+
+    #[test]
+    fn test_parse_certificate() {
+        let certificate = Certificate::parse(&from_hex(EC_CERTIFICATE)).unwrap();
+        assert_eq!(
+            certificate.subject,
+            "commonName=pistol.test/organizationName=Pistol"
+        );
+        assert_eq!(certificate.issuer, certificate.subject);
+        assert!(certificate.self_signed);
+        assert_eq!(
+            certificate.alt_names,
+            [
+                "DNS:pistol.test",
+                "DNS:www.pistol.test",
+                "IP Address:127.0.0.1"
+            ]
+        );
+        assert_eq!(
+            certificate.not_before.to_rfc3339(),
+            "2026-01-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            certificate.not_after.to_rfc3339(),
+            "2027-01-01T00:00:00+00:00"
+        );
+        assert_eq!(certificate.key_type, "ec");
+        assert_eq!(certificate.key_bits, Some(256));
+        assert!(Certificate::parse(&from_hex(EC_CERTIFICATE)[..100]).is_none());
+    }
+}
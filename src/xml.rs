@@ -0,0 +1,359 @@
+use crate::output::group_by_host;
+use crate::output::host_state;
+use crate::output::protocol_str;
+use crate::output::service_name;
+use crate::output::summary;
+use crate::output::time_str;
+use crate::output::Protocol;
+use crate::output::Record;
+use crate::ports;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::net::IpAddr;
+
+const XML_OUTPUT_VERSION: &str = "1.05";
+/// pistol does not tell us why a port or host is in a state
+const REASON: &str = "unknown";
+
+fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            // attributes keep their line breaks, like the OS fingerprint
+            '\n' => ret.push_str("&#xa;"),
+            // the other controls are not allowed in XML even as a reference,
+            // certificates and banners come from the remote
+            '\t' | '\r' => ret.push(c),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => ret.push(char::REPLACEMENT_CHARACTER),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+fn addr_type(addr: &IpAddr) -> &'static str {
+    match addr {
+        IpAddr::V4(_) => "ipv4",
+        IpAddr::V6(_) => "ipv6",
+    }
+}
+
+/// Ports in nmap's `services` notation, like `22,80-85,443`.
+fn services(ports: &BTreeSet<u16>) -> String {
+    ports::to_ranges(ports)
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                format!("{}", start)
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Render the results as an nmap `-oX` compatible document.
+pub fn to_xml(records: &[Record], start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    let args: Vec<String> = std::env::args().collect();
+    let hosts = group_by_host(records);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<!DOCTYPE nmaprun>\n");
+    let _ = writeln!(
+        xml,
+        "<nmaprun scanner=\"pistolcli\" args=\"{}\" start=\"{}\" startstr=\"{}\" version=\"{}\" xmloutputversion=\"{}\">",
+        escape(&args.join(" ")),
+        start.timestamp(),
+        time_str(&start),
+        env!("CARGO_PKG_VERSION"),
+        XML_OUTPUT_VERSION,
+    );
+    // only port scans have scaninfo elements, one per protocol like nmap
+    let mut protocols = Vec::new();
+    for r in records.iter().filter(|r| r.port.is_some()) {
+        if !protocols.iter().any(|(p, _)| *p == r.protocol) {
+            protocols.push((r.protocol, r.technique));
+        }
+    }
+    for (protocol, technique) in protocols {
+        let ports: BTreeSet<u16> = records
+            .iter()
+            .filter(|r| r.protocol == protocol)
+            .filter_map(|r| r.port)
+            .collect();
+        let scan_type = match protocol {
+            Protocol::Ip => "ipproto",
+            _ => technique,
+        };
+        let _ = writeln!(
+            xml,
+            "<scaninfo type=\"{}\" protocol=\"{}\" numservices=\"{}\" services=\"{}\"/>",
+            scan_type,
+            protocol_str(protocol),
+            ports.len(),
+            services(&ports),
+        );
+    }
+
+    let mut up = 0;
+    let mut down = 0;
+    for (addr, host_records) in &hosts {
+        let state = host_state(host_records);
+        match state {
+            "up" => up += 1,
+            "down" => down += 1,
+            _ => (),
+        }
+        xml.push_str("<host>\n");
+        let _ = writeln!(
+            xml,
+            "<status state=\"{}\" reason=\"{}\" reason_ttl=\"0\"/>",
+            state, REASON,
+        );
+        let _ = writeln!(
+            xml,
+            "<address addr=\"{}\" addrtype=\"{}\"/>",
+            addr,
+            addr_type(addr)
+        );
+        if let Some(r) = host_records.iter().find(|r| r.mac.is_some()) {
+            let _ = write!(
+                xml,
+                "<address addr=\"{}\" addrtype=\"mac\"",
+                r.mac.as_deref().unwrap_or_default()
+            );
+            if let Some(vendor) = r.vendor {
+                let _ = write!(xml, " vendor=\"{}\"", escape(vendor));
+            }
+            xml.push_str("/>\n");
+        }
+        xml.push_str("<hostnames>\n");
+        if let Some(hostname) = host_records.iter().find_map(|r| r.hostname.as_deref()) {
+            let _ = writeln!(xml, "<hostname name=\"{}\"/>", escape(hostname));
+        }
+        xml.push_str("</hostnames>\n");
+        let ports: Vec<&&Record> = host_records.iter().filter(|r| r.port.is_some()).collect();
+        if !ports.is_empty() {
+            xml.push_str("<ports>");
+            for r in ports {
+                let port = r.port.unwrap_or_default();
+                let _ = write!(
+                    xml,
+                    "<port protocol=\"{}\" portid=\"{}\"><state state=\"{}\" reason=\"{}\" reason_ttl=\"0\"/>",
+                    protocol_str(r.protocol),
+                    port,
+                    r.state,
+                    REASON,
+                );
+                if let Some(service) = &r.service {
+                    let _ = write!(xml, "<service name=\"{}\"", escape(&service.name));
+                    let fields = [
+                        ("product", &service.product),
+                        ("version", &service.version),
+                        ("extrainfo", &service.extrainfo),
+                    ];
+                    for (attr, value) in fields {
+                        if let Some(value) = value {
+                            let _ = write!(xml, " {}=\"{}\"", attr, escape(value));
+                        }
+                    }
+                    xml.push_str(" method=\"probed\" conf=\"10\"/>");
+                } else if let Some(name) = service_name(r) {
+                    let _ = write!(
+                        xml,
+                        "<service name=\"{}\" method=\"table\" conf=\"3\"/>",
+                        name
+                    );
+                }
+                // nmap reports banners as the output of its banner script
+                if let Some(banner) = &r.banner {
+                    let _ = write!(xml, "<script id=\"banner\" output=\"{}\"/>", escape(banner));
+                }
+                if let Some(tls) = &r.tls {
+                    let certificate = &tls.certificate;
+                    let _ = write!(
+                        xml,
+                        "<script id=\"ssl-cert\" output=\"{}\"><table key=\"pubkey\"><elem key=\"type\">{}</elem>",
+                        escape(&tls.ssl_cert_output()),
+                        escape(&certificate.key_type)
+                    );
+                    if let Some(bits) = certificate.key_bits {
+                        let _ = write!(xml, "<elem key=\"bits\">{}</elem>", bits);
+                    }
+                    let _ = write!(
+                        xml,
+                        "</table><table key=\"validity\"><elem key=\"notBefore\">{}</elem><elem key=\"notAfter\">{}</elem></table></script>",
+                        certificate.not_before.format("%Y-%m-%dT%H:%M:%S"),
+                        certificate.not_after.format("%Y-%m-%dT%H:%M:%S")
+                    );
+                }
+                xml.push_str("</port>\n");
+            }
+            xml.push_str("</ports>\n");
+        }
+        if let Some(os) = host_records.iter().find_map(|r| r.os.as_ref()) {
+            xml.push_str("<os>\n");
+            for m in &os.matches {
+                let _ = writeln!(
+                    xml,
+                    "<osmatch name=\"{}\" accuracy=\"{}\" line=\"0\">",
+                    escape(&m.name),
+                    m.accuracy
+                );
+                for class in &m.classes {
+                    let _ = write!(
+                        xml,
+                        "<osclass type=\"{}\" vendor=\"{}\" osfamily=\"{}\"",
+                        escape(&class.device_type),
+                        escape(&class.vendor),
+                        escape(&class.family),
+                    );
+                    if let Some(generation) = &class.generation {
+                        let _ = write!(xml, " osgen=\"{}\"", escape(generation));
+                    }
+                    let _ = write!(xml, " accuracy=\"{}\">", m.accuracy);
+                    for cpe in &class.cpe {
+                        let _ = write!(xml, "<cpe>{}</cpe>", escape(cpe));
+                    }
+                    xml.push_str("</osclass>\n");
+                }
+                xml.push_str("</osmatch>\n");
+            }
+            let _ = writeln!(
+                xml,
+                "<osfingerprint fingerprint=\"{}\"/>",
+                escape(&os.fingerprint)
+            );
+            xml.push_str("</os>\n");
+        }
+        xml.push_str("</host>\n");
+    }
+
+    let elapsed = (end - start).num_milliseconds() as f64 / 1000.0;
+    xml.push_str("<runstats>");
+    let _ = write!(
+        xml,
+        "<finished time=\"{}\" timestr=\"{}\" elapsed=\"{:.2}\" summary=\"pistolcli done; {}\" exit=\"success\"/>",
+        end.timestamp(),
+        time_str(&end),
+        elapsed,
+        summary(hosts.len(), up, elapsed),
+    );
+    let _ = writeln!(
+        xml,
+        "<hosts up=\"{}\" down=\"{}\" total=\"{}\"/>",
+        up,
+        down,
+        hosts.len()
+    );
+    xml.push_str("</runstats>\n");
+    xml.push_str("</nmaprun>\n");
+    xml
+}
+
+/// Write the XML report to `path`, `-` means stdout.
+pub fn write_xml(
+    records: &[Record],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    path: &str,
+) -> Result<()> {
+    let xml = to_xml(records, start, end);
+    if path == "-" {
+        print!("{}", xml);
+    } else {
+        fs::write(path, xml)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a<b>&\"'"), "a&lt;b&gt;&amp;&quot;&apos;");
+        assert_eq!(escape("a\nb\tc\r"), "a&#xa;b\tc\r");
+        assert_eq!(escape("CN=a\u{0}b\u{1b}[0m"), "CN=a\u{fffd}b\u{fffd}[0m");
+        assert_eq!(escape("\u{ffff}é"), "\u{fffd}é");
+    }
+
+    #[test]
+    fn test_to_xml() {
+        let record = |addr: &str, port, protocol, technique, state| {
+            Record::new(
+                addr.parse().unwrap(),
+                port,
+                protocol,
+                technique,
+                state,
+                None,
+            )
+        };
+        let records = [
+            record("192.0.2.1", None, Protocol::Icmp, "icmp", "up"),
+            record("192.0.2.1", Some(22), Protocol::Tcp, "syn", "open"),
+            record("192.0.2.1", Some(25), Protocol::Tcp, "syn", "filtered"),
+            record("192.0.2.1", Some(53), Protocol::Udp, "udp", "open|filtered"),
+            record("192.0.2.2", None, Protocol::Icmp, "icmp", "down"),
+        ];
+        let start = "2026-01-02T03:04:05Z".parse().unwrap();
+        let end = "2026-01-02T03:04:07Z".parse().unwrap();
+        let xml = to_xml(&records, start, end);
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE nmaprun>\n"));
+        assert!(xml.contains("<nmaprun scanner=\"pistolcli\""));
+        assert!(xml.ends_with("</runstats>\n</nmaprun>\n"));
+        assert!(xml.contains(
+            "<scaninfo type=\"syn\" protocol=\"tcp\" numservices=\"2\" services=\"22,25\"/>\n\
+             <scaninfo type=\"udp\" protocol=\"udp\" numservices=\"1\" services=\"53\"/>\n<host>"
+        ));
+
+        // a host element per address, in address order
+        let hosts: Vec<&str> = xml
+            .split("<host>\n")
+            .skip(1)
+            .map(|h| h.split("</host>\n").next().unwrap())
+            .collect();
+        assert_eq!(hosts.len(), 2);
+        let up = hosts[0];
+        assert!(up.starts_with(
+            "<status state=\"up\" reason=\"unknown\" reason_ttl=\"0\"/>\n\
+             <address addr=\"192.0.2.1\" addrtype=\"ipv4\"/>\n<hostnames>\n</hostnames>\n<ports>"
+        ));
+        let ports: Vec<&str> = up
+            .split("<port ")
+            .skip(1)
+            .map(|p| p.split("</port>").next().unwrap())
+            .collect();
+        assert_eq!(
+            ports,
+            [
+                "protocol=\"tcp\" portid=\"22\"><state state=\"open\" reason=\"unknown\" reason_ttl=\"0\"/><service name=\"ssh\" method=\"table\" conf=\"3\"/>",
+                "protocol=\"tcp\" portid=\"25\"><state state=\"filtered\" reason=\"unknown\" reason_ttl=\"0\"/><service name=\"smtp\" method=\"table\" conf=\"3\"/>",
+                "protocol=\"udp\" portid=\"53\"><state state=\"open|filtered\" reason=\"unknown\" reason_ttl=\"0\"/><service name=\"domain\" method=\"table\" conf=\"3\"/>",
+            ]
+        );
+        assert!(up.ends_with("</ports>\n"));
+        // the down host has no ports
+        assert_eq!(
+            hosts[1],
+            "<status state=\"down\" reason=\"unknown\" reason_ttl=\"0\"/>\n\
+             <address addr=\"192.0.2.2\" addrtype=\"ipv4\"/>\n<hostnames>\n</hostnames>\n"
+        );
+        assert!(xml.contains("elapsed=\"2.00\""));
+        assert!(xml.contains("<hosts up=\"1\" down=\"1\" total=\"2\"/>"));
+    }
+}